@@ -0,0 +1,322 @@
+//! Sunrise wake-up alarm
+//!
+//! Starting `duration_minutes` before the alarm time of the current weekday, the stripe ramps
+//! from off along a color temperature curve (deep red/ orange to white) up to the configured
+//! brightness. The brightness follows the perceived lightness, so the ramp looks even instead
+//! of jumping up in the first minutes. Any manual color change or turning the stripe off cancels
+//! a running alarm.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, LocalTime};
+use crate::power::Power;
+use crate::rgb_led::{kelvin_to_rgb, lightness_to_luminance, RGBA8};
+use crate::storage::Storage;
+use crate::transition::{self, Transition};
+
+const STORAGE_KEY: &str = "alarm";
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_CURVE_POINTS: usize = 8;
+const MINUTES_PER_DAY: i32 = 24 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmConfig {
+    /// alarm time as minute of the day per weekday (index 0 is Sunday), None disables the day
+    pub times: [Option<u16>; 7],
+    pub duration_minutes: u16,
+    /// color temperatures in Kelvin, evenly spread over the ramp
+    pub curve: Vec<u16>,
+    pub max_brightness: u8,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        return AlarmConfig {
+            times: [None; 7],
+            duration_minutes: 30,
+            curve: vec![1000, 1800, 2700, 4000],
+            max_brightness: 255,
+        };
+    }
+}
+
+impl AlarmConfig {
+    pub fn set_curve(&mut self, curve: Vec<u16>) -> Result<(), &'static str> {
+        if curve.is_empty() || curve.len() > MAX_CURVE_POINTS {
+            return Err("invalid number of curve points");
+        }
+        if curve.iter().any(|kelvin| !(1000..=40000).contains(kelvin)) {
+            return Err("color temperature out of range");
+        }
+        self.curve = curve;
+        return Ok(());
+    }
+
+    /// color of the ramp at the given progress (0.0 - 1.0)
+    fn color_at(&self, progress: f32) -> RGBA8 {
+        let progress = progress.clamp(0.0, 1.0);
+        let segments = self.curve.len().saturating_sub(1);
+        let kelvin = if segments == 0 {
+            self.curve.first().copied().unwrap_or(2700) as f32
+        } else {
+            let position = progress * segments as f32;
+            let idx = (position.floor() as usize).min(segments - 1);
+            let fraction = position - idx as f32;
+            let (from, to) = (self.curve[idx] as f32, self.curve[idx + 1] as f32);
+            from + (to - from) * fraction
+        };
+        let rgb = kelvin_to_rgb(kelvin.round() as u16);
+        let brightness = lightness_to_luminance(progress as f64) * self.max_brightness as f64;
+        return RGBA8::new(rgb.r, rgb.g, rgb.b, brightness.round() as u8);
+    }
+
+    /// returns true, if the ramp of an alarm starts at the given minute
+    fn ramp_starts_at(&self, now: &LocalTime) -> bool {
+        let minute = now.minute_of_day() as i32;
+        let duration = self.duration_minutes as i32;
+        let today = now.weekday as usize;
+        let tomorrow = (today + 1) % 7;
+
+        if let Some(alarm) = self.times[today] {
+            if alarm as i32 - duration == minute {
+                return true;
+            }
+        }
+        // ramps of early alarms start on the previous day
+        if let Some(alarm) = self.times[tomorrow] {
+            if alarm as i32 - duration + MINUTES_PER_DAY == minute {
+                return true;
+            }
+        }
+        return false;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmState {
+    Idle,
+    Ramping {
+        start: Instant,
+        // the color written last, a different set color means a manual change
+        last_written: Option<RGBA8>,
+    },
+    Snoozed {
+        until: Instant,
+        progress: f32,
+        last_written: RGBA8,
+    },
+}
+
+pub struct Alarm {
+    config: AlarmConfig,
+    state: AlarmState,
+    storage: Arc<Storage>,
+}
+
+impl Alarm {
+    /// loads the previously saved alarm config from the storage
+    pub fn load(storage: Arc<Storage>) -> Alarm {
+        let config = storage.load(STORAGE_KEY).unwrap_or_default();
+        return Alarm {
+            config,
+            state: AlarmState::Idle,
+            storage,
+        };
+    }
+
+    pub fn config(&self) -> &AlarmConfig {
+        return &self.config;
+    }
+
+    pub fn state(&self) -> AlarmState {
+        return self.state;
+    }
+
+    pub fn set_config(&mut self, config: AlarmConfig) -> Result<(), &'static str> {
+        self.storage.store(STORAGE_KEY, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+
+    /// starts the ramp immediately, e.g. to preview the alarm
+    pub fn start(&mut self) {
+        self.state = AlarmState::Ramping {
+            start: Instant::now(),
+            last_written: None,
+        };
+    }
+
+    /// turns the stripe off for the given time, afterwards the ramp continues
+    pub fn snooze(&mut self, duration: Duration) -> Result<(), &'static str> {
+        let (progress, last_written) = match self.state {
+            AlarmState::Ramping {
+                start,
+                last_written,
+            } => (
+                self.progress(start),
+                last_written.unwrap_or(RGBA8::new(0, 0, 0, 0)),
+            ),
+            AlarmState::Snoozed {
+                progress,
+                last_written,
+                ..
+            } => (progress, last_written),
+            AlarmState::Idle => return Err("no active alarm"),
+        };
+        self.state = AlarmState::Snoozed {
+            until: Instant::now() + duration,
+            progress,
+            last_written,
+        };
+        return Ok(());
+    }
+
+    pub fn cancel(&mut self) -> Result<(), &'static str> {
+        if self.state == AlarmState::Idle {
+            return Err("no active alarm");
+        }
+        self.state = AlarmState::Idle;
+        return Ok(());
+    }
+
+    fn progress(&self, start: Instant) -> f32 {
+        let duration = Duration::from_secs(self.config.duration_minutes as u64 * 60);
+        if duration.is_zero() {
+            return 1.0;
+        }
+        return (start.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0);
+    }
+
+    /// advances the alarm, returns the color to write if the stripe has to be updated.
+    /// `current` is the currently set color and `powered` the power state, both used to detect
+    /// manual changes.
+    fn tick(&mut self, now: Option<LocalTime>, current: RGBA8, powered: bool) -> Option<RGBA8> {
+        if let Some(now) = now {
+            if self.state == AlarmState::Idle && self.config.ramp_starts_at(&now) {
+                info!("Starting sunrise alarm");
+                self.start();
+            }
+        }
+
+        let has_written = match self.state {
+            AlarmState::Idle => false,
+            AlarmState::Ramping { last_written, .. } => last_written.is_some(),
+            AlarmState::Snoozed { .. } => true,
+        };
+        if has_written && !powered {
+            info!("Sunrise alarm cancelled by turning the stripe off");
+            self.state = AlarmState::Idle;
+            return None;
+        }
+
+        match self.state {
+            AlarmState::Idle => return None,
+            AlarmState::Ramping {
+                start,
+                last_written,
+            } => {
+                if last_written.map_or(false, |color| color != current) {
+                    info!("Sunrise alarm cancelled by manual color change");
+                    self.state = AlarmState::Idle;
+                    return None;
+                }
+                let progress = self.progress(start);
+                let color = self.config.color_at(progress);
+                self.state = if progress >= 1.0 {
+                    AlarmState::Idle
+                } else {
+                    AlarmState::Ramping {
+                        start,
+                        last_written: Some(color),
+                    }
+                };
+                return Some(color);
+            }
+            AlarmState::Snoozed {
+                until,
+                progress,
+                last_written,
+            } => {
+                let off = RGBA8 {
+                    a: 0,
+                    ..last_written
+                };
+                if current != last_written && current != off {
+                    info!("Sunrise alarm cancelled by manual color change");
+                    self.state = AlarmState::Idle;
+                    return None;
+                }
+                if Instant::now() < until {
+                    self.state = AlarmState::Snoozed {
+                        until,
+                        progress,
+                        last_written: off,
+                    };
+                    return Some(off);
+                }
+                // continue the ramp where it was snoozed
+                let ramp = Duration::from_secs(self.config.duration_minutes as u64 * 60);
+                let start = Instant::now()
+                    .checked_sub(ramp.mul_f32(progress))
+                    .unwrap_or_else(Instant::now);
+                self.state = AlarmState::Ramping {
+                    start,
+                    last_written: Some(off),
+                };
+                return None;
+            }
+        }
+    }
+}
+
+/// Spawns the alarm thread, which starts alarms and drives the sunrise ramp
+pub fn spawn(
+    alarm: Arc<Mutex<Alarm>>,
+    clock: Arc<Clock>,
+    rgba: Arc<RwLock<RGBA8>>,
+    transition: Arc<Mutex<Option<Transition>>>,
+    power: Arc<Mutex<Power>>,
+) {
+    thread::spawn(move || {
+        let mut last_checked_minute = None;
+        loop {
+            thread::sleep(TICK_INTERVAL);
+
+            // only check for new alarms once per minute
+            let now = clock.now().filter(|now| {
+                let minute = Some((now.day, now.minute_of_day()));
+                let is_new_minute = last_checked_minute != minute;
+                last_checked_minute = minute;
+                is_new_minute
+            });
+            let current = match rgba.read() {
+                Ok(val) => *val,
+                Err(_) => continue,
+            };
+            let powered = match power.lock() {
+                Ok(power) => power.is_on(),
+                Err(_) => continue,
+            };
+            let color = match alarm.lock() {
+                Ok(mut alarm) => alarm.tick(now, current, powered),
+                Err(_) => continue,
+            };
+            if let Some(color) = color {
+                if color != current {
+                    if let Err(e) = transition::start(&rgba, &transition, color, Duration::ZERO) {
+                        warn!("Could not set alarm color: {}", e);
+                    }
+                }
+                // the ramp starts at zero brightness, so the stripe can be turned on immediately
+                if let Ok(mut power) = power.lock() {
+                    power.set(true, Some(Duration::ZERO));
+                }
+            }
+        }
+    });
+}
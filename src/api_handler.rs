@@ -1,12 +1,44 @@
 use std::borrow::Borrow;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use embedded_svc::http::server::{Handler, HandlerError, Request};
-use embedded_svc::io::Write;
+use embedded_svc::http::{Headers, Method};
+use embedded_svc::io::{Read, Write};
 use esp_idf_svc::http::server::EspHttpConnection;
+use log::{warn, Level};
+use serde::Deserialize;
+use std::str::FromStr;
 use url::Url;
 
-use crate::rgb_led::RGBA8;
+use crate::alarm::{Alarm, AlarmState};
+use crate::button::{Button, ButtonAction};
+use crate::calibration::{Calibration, MAX_KELVIN, MIN_KELVIN};
+use crate::clock::{Clock, TimeConfig};
+use crate::encoder::{Encoder, EncoderMode, PushAction};
+use crate::group_sync::{GroupSync, SyncRole};
+use crate::hue::{Hue, PairRequest};
+use crate::identify::{self, Identify};
+use crate::ir_decoder::IrCode;
+use crate::ir_remote::{self, IrRemote, RemoteAction};
+use crate::logger;
+use crate::metrics::Metrics;
+use crate::pins::{ChannelOrder, Pins};
+use crate::power::Power;
+use crate::power_budget::PowerBudget;
+use crate::provisioning::{self, WifiCredentials};
+use crate::realtime::Realtime;
+use crate::rgb_led::{
+    apply_color_params, apply_white_params, is_color_param, is_white_param, Adjustment,
+    ColorFormat, WhiteChannels, WhiteStrategy, RGB8, RGBA8,
+};
+use crate::scenes::Scenes;
+use crate::scheduler::{self, Scheduler};
+use crate::storage::Storage;
+use crate::thermal::Thermal;
+use crate::transition::{self, Transition};
+use crate::wled::{self, StateUpdate, Wled};
+use crate::zones::{self, Zone};
 
 pub struct GetRGBAHandler {
     pub rgba: Arc<RwLock<RGBA8>>,
@@ -21,13 +53,25 @@ impl GetRGBAHandler {
 impl Handler<EspHttpConnection<'_>> for GetRGBAHandler {
     fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
         let req = Request::wrap(c);
+
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+        let format = match parse_color_format(&url) {
+            Ok(val) => val,
+            Err(e) => return Err(send_error_response(req, e)),
+        };
+
         let rgba = self.rgba.read();
 
         match rgba {
             Ok(val) => {
                 let mut response = req.into_ok_response().unwrap();
                 response
-                    .write_fmt(format_args!("{},{},{},{}", val.r, val.g, val.b, val.a))
+                    .write_fmt(format_args!("{}", format.format(&val)))
                     .unwrap();
                 response.flush().unwrap();
                 return Ok(());
@@ -41,11 +85,17 @@ impl Handler<EspHttpConnection<'_>> for GetRGBAHandler {
 
 pub struct SetRGBAHandler {
     rgba: Arc<RwLock<RGBA8>>,
+    white: Arc<RwLock<WhiteChannels>>,
+    power: Arc<Mutex<Power>>,
 }
 
 impl SetRGBAHandler {
-    pub fn new(rgba: Arc<RwLock<RGBA8>>) -> SetRGBAHandler {
-        return SetRGBAHandler { rgba };
+    pub fn new(
+        rgba: Arc<RwLock<RGBA8>>,
+        white: Arc<RwLock<WhiteChannels>>,
+        power: Arc<Mutex<Power>>,
+    ) -> SetRGBAHandler {
+        return SetRGBAHandler { rgba, white, power };
     }
 }
 
@@ -53,13 +103,11 @@ impl Handler<EspHttpConnection<'_>> for SetRGBAHandler {
     fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
         let req = Request::wrap(c);
 
-        // create a dummy base url
-        let base_url = Url::parse("http://localhost").unwrap();
-        let url = match base_url.join(req.uri()) {
-            Err(_) => {
+        let url = match parse_request_url(req.uri()) {
+            None => {
                 return Err(send_error_response(req, "parse URL from request"));
             }
-            Ok(val) => val,
+            Some(val) => val,
         };
 
         let mut new_rgba = match self.rgba.write() {
@@ -69,25 +117,47 @@ impl Handler<EspHttpConnection<'_>> for SetRGBAHandler {
             }
         };
 
-        for pair in url.query_pairs() {
-            let color = pair.0;
-            let value = pair.1;
-
-            match color.borrow() {
-                "r" => {
-                    new_rgba.r = value.to_string().parse::<u8>().unwrap_or(0);
-                }
-                "g" => {
-                    new_rgba.g = value.to_string().parse::<u8>().unwrap_or(0);
-                }
-                "b" => {
-                    new_rgba.b = value.to_string().parse::<u8>().unwrap_or(0);
+        let params: Vec<_> = url.query_pairs().collect();
+        let mut toggle = false;
+        for (key, value) in &params {
+            match key.borrow() {
+                "toggle" => toggle = true,
+                key if is_color_param(key) || is_white_param(key) => {}
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value)
                 }
-                "a" => {
-                    new_rgba.a = value.to_string().parse::<u8>().unwrap_or(0);
+            }
+        }
+
+        let mut white = match self.white.write() {
+            Ok(val) => val,
+            Err(_) => {
+                drop(new_rgba);
+                return Err(send_error_response(req, "could not get write lock"));
+            }
+        };
+
+        // only apply the new color, if all values are valid
+        let mut color = *new_rgba;
+        let mut new_white = *white;
+        let result = apply_color_params(&mut color, &params)
+            .and_then(|_| apply_white_params(&mut new_white, &params));
+        if let Err(e) = result {
+            drop(new_rgba);
+            drop(white);
+            return Err(send_error_response(req, e));
+        }
+        *new_rgba = color;
+        *white = new_white;
+        drop(new_rgba);
+        drop(white);
+        if toggle {
+            match self.power.lock() {
+                Ok(mut power) => {
+                    power.toggle(None);
                 }
-                _ => {
-                    println!("Unknown query parameter! key:{} value:{}!", color, value)
+                Err(_) => {
+                    return Err(send_error_response(req, "could not get power lock"));
                 }
             }
         }
@@ -95,14 +165,42 @@ impl Handler<EspHttpConnection<'_>> for SetRGBAHandler {
         let mut response = req.into_ok_response().unwrap();
         response.write_fmt(format_args!(
             "{},{},{},{}",
-            new_rgba.r, new_rgba.g, new_rgba.b, new_rgba.a
+            color.r, color.g, color.b, color.a
         ))?;
         response.flush().unwrap();
         Ok(())
     }
 }
 
+fn parse_request_url(uri: &str) -> Option<Url> {
+    // create a dummy base url
+    let base_url = Url::parse("http://localhost").unwrap();
+    return base_url.join(uri).ok();
+}
+
+/// maximum accepted size of a request body
+const MAX_BODY_SIZE: usize = 1024;
+
+fn read_body(req: &mut Request<&mut EspHttpConnection>) -> Result<Vec<u8>, &'static str> {
+    let len = req.content_len().unwrap_or(0) as usize;
+    if len > MAX_BODY_SIZE {
+        return Err("request body too large");
+    }
+    let mut body = vec![0u8; len];
+    let mut read = 0;
+    while read < len {
+        match req.read(&mut body[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(_) => return Err("could not read request body"),
+        }
+    }
+    body.truncate(read);
+    return Ok(body);
+}
+
 fn send_error_response(req: Request<&mut EspHttpConnection>, msg: &str) -> HandlerError {
+    warn!("Request to {} failed: {}", req.uri(), msg);
     let mut response = req.into_status_response(400).unwrap();
     response.flush().unwrap();
     return msg.into();
@@ -121,7 +219,56 @@ impl Handler<EspHttpConnection<'_>> for HelpHandler {
         let help_text = "<h1>Help - Supported functions</h1>
             <b>/help</b> - shows this help page</br>
             <b>/setRGBA?r=VALUE&g=VALUE&b=VALUE&a=VALUE</b> - sets the r,g,b and brightness/ alpha values</br>
-            <b>/getRGBA</b> - gets the r,g,b and brightness/alpha values (in this order) as CSV without a CSV header</br>";
+            <b>/setRGBA?h=0-360&s=0-100&v=0-100</b> - sets the color as hsv (use l instead of v for hsl), missing hue/ saturation values are kept</br>
+            <b>/setRGBA?hex=RRGGBB[AA]</b> - sets the color (and brightness/ alpha) as hex value</br>
+            <b>/setRGBA?a=+20&r=-10&h=+30&s=-10</b> - all values except hex can be relative steps, channels and percent values are clamped, the hue wraps around</br>
+            <b>/setRGBA?w=VALUE&ww=VALUE&cw=VALUE</b> - sets the white channels of RGBW/ RGBWW stripes (w drives all white channels, ww/ cw only warm/ cold white)</br>
+            <b>/white?w=VALUE&ww=VALUE&cw=VALUE</b> - gets and optionally sets the white channels as CSV (w,ww,cw)</br>
+            <b>/setRGBA?toggle</b> - toggles the power state, same as /toggle</br>
+            <b>/on?t=MS</b>, <b>/off?t=MS</b>, <b>/toggle?t=MS</b> - switches the power state without changing the color, the fade time t is optional</br>
+            <b>/power?fade_on=MS&fade_off=MS</b> - shows the power state and sets the default fade times</br>
+            <b>/getState?format=rgba|hsv|hsl|hex</b> - gets the power state, the set color and the estimated power draw in watts as CSV (on|off,COLOR,WATTS), format is optional</br>
+            <b>/zones</b> - lists all zones as CSV (id,name,on|off,r,g,b,a,watts) without a CSV header</br>
+            <b>/zones/ID/state?power=on|off|toggle&t=MS&format=rgba|hsv|hsl|hex</b> - gets and optionally sets the state of a zone (id or name, all addresses every zone), accepts the same color and white parameters as /setRGBA</br>
+            <b>/zones/ID/budget?supply=WATTS&length=METERS&wpm=R,G,B,W,CW</b> - shows and sets the power budget of a zone (supply watts, 0 disables it, stripe length and watts per meter per channel) and the estimated draw</br>
+            <b>/getRGBA</b> - gets the r,g,b and brightness/alpha values (in this order) as CSV without a CSV header</br>
+            <b>/getRGBA?format=rgba|hsv|hsl|hex</b> - gets the color in the given format (h,s,v,a / h,s,l,a / #RRGGBBAA)</br>
+            <b>/pins?pins=1,2,3,4,5&order=rgb&invert=0,0,0,0,0&freq=HZ&status=8&zone2=6,7,10&button=GPIO|none&ir=GPIO|none&encoder=A,B|none&encoder_switch=GPIO|none&restart=1</b> - shows and sets the pin mapping, channel order, inverted outputs (common anode), pwm frequency, button, ir receiver and rotary encoder pins, all parameters are optional, changes take effect after a restart</br>
+            <b>/button?click=ACTION&double=ACTION&long=ACTION&verylong=ACTION&zone=ID&step=VALUE&t=MS</b> - shows and sets the actions of the push button gestures (none, toggle, scene, dim, provision, restart, link), all parameters are optional</br>
+            <b>/encoder?mode=MODE&click=ACTION&double=ACTION&long=ACTION&zone=ID&brightness=STEP&hue=STEP&saturation=STEP&detent=1|2|4&accel=0|1&reverse=0|1</b> - shows and sets the rotary encoder mode (brightness, hue, saturation), the push switch actions (none, toggle, mode) and the steps per detent, all parameters are optional</br>
+            <b>/ir?zone=ID</b> - shows the last received ir code and the learn mode and sets the zones addressed by the remote</br>
+            <b>/ir/codes</b> - lists the learned and built-in ir mappings as CSV (code,action,learned|builtin) without a CSV header</br>
+            <b>/ir/learn?action=ACTION&timeout=SECONDS</b> - maps the next received ir code to the action (on, off, toggle, none, scene:NAME, ct:KELVIN or color parameters like a=+16)</br>
+            <b>/ir/map?code=PROTOCOL:ADDRESS:COMMAND&action=ACTION</b> - maps an ir code (e.g. nec:00:1a) to the action</br>
+            <b>/ir/delete?code=PROTOCOL:ADDRESS:COMMAND</b> - deletes a learned mapping, the built-in mapping applies again</br>
+            <b>/identify?duration=SECONDS&stop=1</b> - blinks all zones and the status LED to locate the controller (default 10 s, at most 300 s), stop=1 ends it early</br>
+            <b>/sync?group=NAME&role=auto|leader|follower&priority=VALUE</b> - shows the sync status and sets the sync group of the main zone (empty group disables it), the role and the leader priority, all parameters are optional</br>
+            <b>/json</b>, <b>/json/state</b>, <b>/json/info</b>, <b>/json/eff</b>, <b>/json/pal</b> - WLED compatible JSON API, POST to /json or /json/state sets on, bri, transition and the segments (one per zone)</br>
+            <b>/realtime?timeout=MS&stop=1</b> - shows the running realtime stream (wled, tpm2, ddp or none) and sets the timeout of TPM2.net and DDP streams, stop=1 ends a running stream</br>
+            <b>/hue?link=1&clear=1</b> - shows the Hue bridge id, the remaining pairing time and the paired clients, link=1 presses the link button for 30 s, clear=1 unpairs all clients</br>
+            <b>/api</b>, <b>/api/USER/lights</b>, <b>/api/USER/lights/ID/state</b>, <b>/description.xml</b> - Hue bridge (API v1) emulation, every zone is a color light</br>
+            <b>/wifi?ssid=SSID&passphrase=PASSPHRASE&provision=1&restart=1</b> - stores the wifi credentials used after a restart, provision=1 restarts into the setup access point</br>
+            <b>/metrics</b> - exposes runtime metrics in the Prometheus text format</br>
+            <b>/logs?level=LEVEL</b> - shows the latest log lines, optionally only up to the given level</br>
+            <b>/logs/config?filter=FILTER&syslog=HOST:PORT</b> - sets the log filter (e.g. info,api_handler=debug) and the remote syslog server (off disables it)</br>
+            <b>/scenes</b> - lists all saved scenes as CSV (name,r,g,b,a) without a CSV header</br>
+            <b>/scenes/save?name=NAME</b> - saves the current r,g,b and brightness/alpha values as scene</br>
+            <b>/scenes/recall?name=NAME&t=MILLISECONDS</b> - recalls a scene, optionally with a transition of the given duration</br>
+            <b>/scenes/rename?name=NAME&to=NEW_NAME</b> - renames a scene</br>
+            <b>/scenes/delete?name=NAME</b> - deletes a scene</br>
+            <b>/time?ntp=SERVER&tz=TIMEZONE&lat=LATITUDE&lon=LONGITUDE</b> - shows the local time and time config, all parameters are optional and update the config</br>
+            <b>/schedules</b> - lists all schedules as JSON</br>
+            <b>/schedules/add?when=WHEN&action=ACTION&t=MILLISECONDS</b> - adds a schedule, WHEN is a cron expression or sunrise/sunset with offset, ACTION is color:R,G,B,A, scene:NAME or off</br>
+            <b>/schedules/enable?id=ID&enabled=0|1</b> - enables or disables a schedule</br>
+            <b>/schedules/delete?id=ID</b> - deletes a schedule</br>
+            <b>/setCT?k=KELVIN&a=VALUE</b> - sets a calibrated color temperature (1000 - 40000 K) and optionally the brightness/ alpha value, POST accepts {\"k\":KELVIN,\"a\":VALUE} as JSON body, a can be relative (\"+20\")</br>
+            <b>/thermal?start=C&critical=C&min=LEVEL&hysteresis=C</b> - shows the chip/ NTC temperature and the derating status and sets the derating thresholds, all parameters are optional</br>
+            <b>/calibration?rmax=VALUE&gmax=VALUE&bmax=VALUE&point=KELVIN:R,G,B&clear=1</b> - shows and sets the white balance and the measured color temperature table, all parameters are optional</br>
+            <b>/calibration?wpoint=R,G,B&wstrategy=off|max|accurate</b> - sets the measured color of the white LEDs and how white is extracted from r,g,b for RGBW/ RGBWW stripes</br>
+            <b>/alarm?days=DAYS&time=HH:MM&duration=MINUTES&curve=KELVIN,KELVIN&brightness=VALUE</b> - shows and configures the sunrise alarm, all parameters are optional, time=off disables the days</br>
+            <b>/alarm/start</b> - starts the sunrise ramp immediately</br>
+            <b>/alarm/snooze?min=MINUTES</b> - turns the stripe off and continues the sunrise ramp afterwards</br>
+            <b>/alarm/cancel</b> - stops a running sunrise alarm</br>";
 
         let req = Request::wrap(c);
         let mut response = req.into_ok_response()?;
@@ -130,3 +277,2337 @@ impl Handler<EspHttpConnection<'_>> for HelpHandler {
         Ok(())
     }
 }
+
+pub struct HealthHandler {}
+
+impl HealthHandler {
+    pub fn new() -> HealthHandler {
+        return HealthHandler {};
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for HealthHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let mut response = req.into_ok_response()?;
+        response.write_all(b"I am alive")?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct MetricsHandler {
+    rgba: Arc<RwLock<RGBA8>>,
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsHandler {
+    pub fn new(rgba: Arc<RwLock<RGBA8>>, metrics: Arc<Metrics>) -> MetricsHandler {
+        return MetricsHandler { rgba, metrics };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for MetricsHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let rgba = match self.rgba.read() {
+            Ok(val) => *val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get read lock"));
+            }
+        };
+
+        let body = self.metrics.render(&rgba);
+        let mut response =
+            req.into_response(200, None, &[("Content-Type", "text/plain; version=0.0.4")])?;
+        response.write_all(body.as_bytes())?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+/// Wraps another handler and counts its requests by route and status code.
+/// Handlers report failures by returning an error after sending a 400 response.
+pub struct MeteredHandler<H> {
+    route: &'static str,
+    handler: H,
+    metrics: Arc<Metrics>,
+}
+
+impl<H> MeteredHandler<H> {
+    pub fn new(route: &'static str, handler: H, metrics: Arc<Metrics>) -> MeteredHandler<H> {
+        return MeteredHandler {
+            route,
+            handler,
+            metrics,
+        };
+    }
+}
+
+impl<'a, H> Handler<EspHttpConnection<'a>> for MeteredHandler<H>
+where
+    H: Handler<EspHttpConnection<'a>>,
+{
+    fn handle(&self, c: &mut EspHttpConnection<'a>) -> embedded_svc::http::server::HandlerResult {
+        let result = self.handler.handle(c);
+        let status = if result.is_ok() { 200 } else { 400 };
+        self.metrics.record_http_request(self.route, status);
+        return result;
+    }
+}
+
+pub struct LogsHandler {}
+
+impl LogsHandler {
+    pub fn new() -> LogsHandler {
+        return LogsHandler {};
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for LogsHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut max_level = Level::Trace;
+        for (key, value) in url.query_pairs() {
+            if key == "level" {
+                max_level = match Level::from_str(&value) {
+                    Ok(val) => val,
+                    Err(_) => {
+                        return Err(send_error_response(req, "unknown log level"));
+                    }
+                };
+            }
+        }
+
+        let mut body = String::new();
+        logger::logger().for_each_entry(|entry| {
+            if entry.level <= max_level {
+                body.push_str(&entry.line);
+                body.push('\n');
+            }
+        });
+
+        let mut response = req.into_ok_response()?;
+        response.write_all(body.as_bytes())?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct LogConfigHandler {}
+
+impl LogConfigHandler {
+    pub fn new() -> LogConfigHandler {
+        return LogConfigHandler {};
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for LogConfigHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "filter" => logger::logger().set_filter(&value),
+                "syslog" => match value.borrow() {
+                    "" | "off" => logger::logger().set_syslog(None),
+                    host => match host.rsplit_once(':') {
+                        Some((host, port)) => match port.parse::<u16>() {
+                            Ok(port) => logger::logger().set_syslog(Some((host, port))),
+                            Err(_) => Err(format!("invalid syslog port: {}", port)),
+                        },
+                        // default syslog port
+                        None => logger::logger().set_syslog(Some((host, 514))),
+                    },
+                },
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                warn!("Could not update log config: {}", e);
+                return Err(send_error_response(req, "could not update log config"));
+            }
+        }
+
+        let syslog = match logger::logger().syslog() {
+            Some(addr) => addr.to_string(),
+            None => "off".to_string(),
+        };
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "filter={}\nsyslog={}\n",
+            logger::logger().filter(),
+            syslog
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SceneAction {
+    List,
+    Save,
+    Recall,
+    Rename,
+    Delete,
+}
+
+pub struct SceneHandler {
+    action: SceneAction,
+    rgba: Arc<RwLock<RGBA8>>,
+    transition: Arc<Mutex<Option<Transition>>>,
+    scenes: Arc<Mutex<Scenes>>,
+}
+
+impl SceneHandler {
+    pub fn new(
+        action: SceneAction,
+        rgba: Arc<RwLock<RGBA8>>,
+        transition: Arc<Mutex<Option<Transition>>>,
+        scenes: Arc<Mutex<Scenes>>,
+    ) -> SceneHandler {
+        return SceneHandler {
+            action,
+            rgba,
+            transition,
+            scenes,
+        };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for SceneHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut name = None;
+        let mut new_name = None;
+        let mut duration = Duration::ZERO;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "name" => name = Some(value.to_string()),
+                "to" => new_name = Some(value.to_string()),
+                "t" => duration = Duration::from_millis(value.parse::<u64>().unwrap_or(0)),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value)
+                }
+            }
+        }
+
+        let mut scenes = match self.scenes.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get scenes lock"));
+            }
+        };
+
+        let result = match (self.action, name.as_deref()) {
+            (SceneAction::List, _) => Ok(()),
+            (_, None) => Err("missing scene name"),
+            (SceneAction::Save, Some(name)) => match self.rgba.read() {
+                Ok(rgba) => scenes.save(name, *rgba),
+                Err(_) => Err("could not get read lock"),
+            },
+            (SceneAction::Recall, Some(name)) => match scenes.get(name) {
+                Some(scene) => {
+                    transition::start(&self.rgba, &self.transition, scene.color, duration)
+                }
+                None => Err("unknown scene"),
+            },
+            (SceneAction::Rename, Some(name)) => match new_name.as_deref() {
+                Some(new_name) => scenes.rename(name, new_name),
+                None => Err("missing new scene name"),
+            },
+            (SceneAction::Delete, Some(name)) => scenes.delete(name),
+        };
+        if let Err(e) = result {
+            return Err(send_error_response(req, e));
+        }
+
+        let mut response = req.into_ok_response()?;
+        for scene in scenes.list() {
+            response.write_fmt(format_args!(
+                "{},{},{},{},{}\n",
+                scene.name, scene.color.r, scene.color.g, scene.color.b, scene.color.a
+            ))?;
+        }
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct TimeHandler {
+    clock: Arc<Clock>,
+}
+
+impl TimeHandler {
+    pub fn new(clock: Arc<Clock>) -> TimeHandler {
+        return TimeHandler { clock };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for TimeHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut config: TimeConfig = match self.clock.config() {
+            Some(val) => val,
+            None => {
+                return Err(send_error_response(req, "could not get time config"));
+            }
+        };
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "ntp" => config.ntp_server = value.to_string(),
+                "tz" => config.timezone = value.to_string(),
+                "lat" => match value.parse::<f32>() {
+                    Ok(val) if (-90.0..=90.0).contains(&val) => config.latitude = val,
+                    _ => return Err(send_error_response(req, "invalid latitude")),
+                },
+                "lon" => match value.parse::<f32>() {
+                    Ok(val) if (-180.0..=180.0).contains(&val) => config.longitude = val,
+                    _ => return Err(send_error_response(req, "invalid longitude")),
+                },
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = self.clock.set_config(config.clone()) {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let mut response = req.into_ok_response()?;
+        match self.clock.now() {
+            Some(now) => response.write_fmt(format_args!(
+                "time={:04}-{:02}-{:02}T{:02}:{:02}:{:02}\n",
+                now.year, now.month, now.day, now.hour, now.minute, now.second
+            ))?,
+            None => response.write_all(b"time=unsynchronized\n")?,
+        }
+        response.write_fmt(format_args!(
+            "ntp={}\ntz={}\nlat={}\nlon={}\n",
+            config.ntp_server, config.timezone, config.latitude, config.longitude
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ScheduleAction {
+    List,
+    Add,
+    Enable,
+    Delete,
+}
+
+pub struct ScheduleHandler {
+    action: ScheduleAction,
+    scheduler: Arc<Mutex<Scheduler>>,
+}
+
+impl ScheduleHandler {
+    pub fn new(action: ScheduleAction, scheduler: Arc<Mutex<Scheduler>>) -> ScheduleHandler {
+        return ScheduleHandler { action, scheduler };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for ScheduleHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut id = None;
+        let mut when = None;
+        let mut action = None;
+        let mut t = 0;
+        let mut enabled = true;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "id" => id = value.parse::<u8>().ok(),
+                "when" => when = Some(value.to_string()),
+                "action" => action = Some(value.to_string()),
+                "t" => t = value.parse::<u32>().unwrap_or(0),
+                "enabled" => enabled = value != "0",
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value)
+                }
+            }
+        }
+
+        let mut scheduler = match self.scheduler.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get scheduler lock"));
+            }
+        };
+
+        let result = match self.action {
+            ScheduleAction::List => Ok(()),
+            ScheduleAction::Add => match (when.as_deref(), action.as_deref()) {
+                (Some(when), Some(action)) => scheduler.add(when, action, t).map(|_| ()),
+                _ => Err("missing when or action"),
+            },
+            ScheduleAction::Enable => match id {
+                Some(id) => scheduler.set_enabled(id, enabled),
+                None => Err("missing or invalid schedule id"),
+            },
+            ScheduleAction::Delete => match id {
+                Some(id) => scheduler.delete(id),
+                None => Err("missing or invalid schedule id"),
+            },
+        };
+        if let Err(e) = result {
+            return Err(send_error_response(req, e));
+        }
+
+        let body = match serde_json::to_string(scheduler.list()) {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not serialize schedules"));
+            }
+        };
+        let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+        response.write_all(body.as_bytes())?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AlarmAction {
+    Config,
+    Start,
+    Snooze,
+    Cancel,
+}
+
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const DEFAULT_SNOOZE_MINUTES: u64 = 9;
+
+pub struct AlarmHandler {
+    action: AlarmAction,
+    alarm: Arc<Mutex<Alarm>>,
+}
+
+impl AlarmHandler {
+    pub fn new(action: AlarmAction, alarm: Arc<Mutex<Alarm>>) -> AlarmHandler {
+        return AlarmHandler { action, alarm };
+    }
+}
+
+/// parses a time of day given as HH:MM into the minute of the day
+fn parse_time_of_day(value: &str) -> Option<u16> {
+    let (hour, minute) = value.split_once(':')?;
+    let hour = hour.parse::<u16>().ok().filter(|hour| *hour < 24)?;
+    let minute = minute.parse::<u16>().ok().filter(|minute| *minute < 60)?;
+    return Some(hour * 60 + minute);
+}
+
+impl Handler<EspHttpConnection<'_>> for AlarmHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut alarm = match self.alarm.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get alarm lock"));
+            }
+        };
+
+        let result = match self.action {
+            AlarmAction::Config => {
+                let mut config = alarm.config().clone();
+                let mut days = None;
+                let mut time = None;
+                let mut result = Ok(());
+                for (key, value) in url.query_pairs() {
+                    result = match key.borrow() {
+                        "days" => scheduler::parse_weekdays(&value).map(|val| days = Some(val)),
+                        "time" => match value.borrow() {
+                            "off" => {
+                                time = Some(None);
+                                Ok(())
+                            }
+                            _ => parse_time_of_day(&value)
+                                .map(|val| time = Some(Some(val)))
+                                .ok_or("invalid time, expected HH:MM"),
+                        },
+                        "duration" => match value.parse::<u16>() {
+                            Ok(val) if val <= 180 => {
+                                config.duration_minutes = val;
+                                Ok(())
+                            }
+                            _ => Err("invalid duration"),
+                        },
+                        "curve" => value
+                            .split(',')
+                            .map(|kelvin| kelvin.trim().parse::<u16>())
+                            .collect::<Result<Vec<u16>, _>>()
+                            .map_err(|_| "invalid curve")
+                            .and_then(|curve| config.set_curve(curve)),
+                        "brightness" => value
+                            .parse::<u8>()
+                            .map(|val| config.max_brightness = val)
+                            .map_err(|_| "invalid brightness"),
+                        _ => {
+                            warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                            Ok(())
+                        }
+                    };
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                match (result, days, time) {
+                    (Err(e), _, _) => Err(e),
+                    (Ok(_), Some(_), None) | (Ok(_), None, Some(_)) => {
+                        Err("days and time have to be set together")
+                    }
+                    (Ok(_), days, time) => {
+                        if let (Some(days), Some(time)) = (days, time) {
+                            for (weekday, alarm_time) in config.times.iter_mut().enumerate() {
+                                if days & (1u64 << weekday) != 0 {
+                                    *alarm_time = time;
+                                }
+                            }
+                        }
+                        alarm.set_config(config)
+                    }
+                }
+            }
+            AlarmAction::Start => {
+                alarm.start();
+                Ok(())
+            }
+            AlarmAction::Snooze => {
+                let mut minutes = DEFAULT_SNOOZE_MINUTES;
+                for (key, value) in url.query_pairs() {
+                    if key == "min" {
+                        minutes = value.parse::<u64>().unwrap_or(DEFAULT_SNOOZE_MINUTES);
+                    }
+                }
+                alarm.snooze(Duration::from_secs(minutes * 60))
+            }
+            AlarmAction::Cancel => alarm.cancel(),
+        };
+        if let Err(e) = result {
+            return Err(send_error_response(req, e));
+        }
+
+        let state = match alarm.state() {
+            AlarmState::Idle => "idle",
+            AlarmState::Ramping { .. } => "ramping",
+            AlarmState::Snoozed { .. } => "snoozed",
+        };
+        let config = alarm.config();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!("state={}\n", state))?;
+        for (name, time) in WEEKDAY_NAMES.iter().zip(config.times.iter()) {
+            match time {
+                Some(time) => response.write_fmt(format_args!(
+                    "{}={:02}:{:02}\n",
+                    name,
+                    time / 60,
+                    time % 60
+                ))?,
+                None => response.write_fmt(format_args!("{}=off\n", name))?,
+            }
+        }
+        let curve: Vec<String> = config
+            .curve
+            .iter()
+            .map(|kelvin| kelvin.to_string())
+            .collect();
+        response.write_fmt(format_args!(
+            "duration={}\ncurve={}\nbrightness={}\n",
+            config.duration_minutes,
+            curve.join(","),
+            config.max_brightness
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct SetCTRequest {
+    k: u16,
+    a: Option<Adjustment>,
+}
+
+pub struct SetCTHandler {
+    rgba: Arc<RwLock<RGBA8>>,
+    calibration: Arc<Mutex<Calibration>>,
+}
+
+impl SetCTHandler {
+    pub fn new(rgba: Arc<RwLock<RGBA8>>, calibration: Arc<Mutex<Calibration>>) -> SetCTHandler {
+        return SetCTHandler { rgba, calibration };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for SetCTHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let mut req = Request::wrap(c);
+
+        // GET passes the values as query parameters, POST as JSON body
+        let ct_request = match req.method() {
+            Method::Post => {
+                let body = match read_body(&mut req) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        return Err(send_error_response(req, e));
+                    }
+                };
+                match serde_json::from_slice::<SetCTRequest>(&body) {
+                    Ok(val) => val,
+                    Err(_) => {
+                        return Err(send_error_response(req, "invalid JSON body"));
+                    }
+                }
+            }
+            _ => {
+                let url = match parse_request_url(req.uri()) {
+                    None => {
+                        return Err(send_error_response(req, "parse URL from request"));
+                    }
+                    Some(val) => val,
+                };
+                let mut k = None;
+                let mut a = None;
+                for (key, value) in url.query_pairs() {
+                    match key.borrow() {
+                        "k" => k = value.parse::<u16>().ok(),
+                        "a" => a = Adjustment::from_str(&value).ok(),
+                        _ => {
+                            warn!("Unknown query parameter! key:{} value:{}!", key, value)
+                        }
+                    }
+                }
+                match k {
+                    Some(k) => SetCTRequest { k, a },
+                    None => {
+                        return Err(send_error_response(req, "missing or invalid k"));
+                    }
+                }
+            }
+        };
+        if !(MIN_KELVIN..=MAX_KELVIN).contains(&ct_request.k) {
+            return Err(send_error_response(req, "color temperature out of range"));
+        }
+
+        let rgb = match self.calibration.lock() {
+            Ok(calibration) => calibration.ct_to_rgb(ct_request.k),
+            Err(_) => {
+                return Err(send_error_response(req, "could not get calibration lock"));
+            }
+        };
+        let mut new_rgba = match self.rgba.write() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get write lock"));
+            }
+        };
+        let brightness = match ct_request.a {
+            Some(adjustment) => match adjustment.apply_channel(new_rgba.a) {
+                Ok(val) => val,
+                Err(e) => {
+                    drop(new_rgba);
+                    return Err(send_error_response(req, e));
+                }
+            },
+            None => new_rgba.a,
+        };
+        *new_rgba = rgb.alpha(brightness);
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "{},{},{},{}",
+            new_rgba.r, new_rgba.g, new_rgba.b, new_rgba.a
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct CalibrationHandler {
+    calibration: Arc<Mutex<Calibration>>,
+}
+
+impl CalibrationHandler {
+    pub fn new(calibration: Arc<Mutex<Calibration>>) -> CalibrationHandler {
+        return CalibrationHandler { calibration };
+    }
+}
+
+/// parses a color given as R,G,B
+fn parse_rgb(value: &str) -> Option<RGB8> {
+    let channels = value
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()?;
+    return match channels[..] {
+        [r, g, b] => Some(RGB8::new(r, g, b)),
+        _ => None,
+    };
+}
+
+/// parses a calibration point given as KELVIN:R,G,B
+fn parse_calibration_point(value: &str) -> Option<(u16, RGB8)> {
+    let (kelvin, color) = value.split_once(':')?;
+    return Some((kelvin.parse::<u16>().ok()?, parse_rgb(color)?));
+}
+
+impl Handler<EspHttpConnection<'_>> for CalibrationHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut calibration = match self.calibration.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get calibration lock"));
+            }
+        };
+
+        let mut channel_max = calibration.data().channel_max;
+        let mut channel_max_changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "rmax" | "gmax" | "bmax" => match value.parse::<u8>() {
+                    Ok(val) => {
+                        let idx = match key.borrow() {
+                            "rmax" => 0,
+                            "gmax" => 1,
+                            _ => 2,
+                        };
+                        channel_max[idx] = val;
+                        channel_max_changed = true;
+                        Ok(())
+                    }
+                    Err(_) => Err("invalid channel maximum"),
+                },
+                "point" => match parse_calibration_point(&value) {
+                    Some((kelvin, color)) => calibration.set_point(kelvin, color),
+                    None => Err("invalid calibration point, expected KELVIN:R,G,B"),
+                },
+                "clear" => calibration.clear_table(),
+                "wpoint" => match parse_rgb(&value) {
+                    Some(color) => calibration.set_white_point(color),
+                    None => Err("invalid white point, expected R,G,B"),
+                },
+                "wstrategy" => WhiteStrategy::from_str(&value)
+                    .and_then(|strategy| calibration.set_white_strategy(strategy)),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+        }
+        if channel_max_changed {
+            if let Err(e) = calibration.set_channel_max(channel_max) {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let data = calibration.data();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "rmax={}\ngmax={}\nbmax={}\n",
+            data.channel_max[0], data.channel_max[1], data.channel_max[2]
+        ))?;
+        response.write_fmt(format_args!(
+            "wpoint={},{},{}\nwstrategy={}\n",
+            data.white_point.r,
+            data.white_point.g,
+            data.white_point.b,
+            data.white_strategy.name()
+        ))?;
+        for (kelvin, color) in data.table.iter() {
+            response.write_fmt(format_args!(
+                "point={}:{},{},{}\n",
+                kelvin, color.r, color.g, color.b
+            ))?;
+        }
+        response.flush()?;
+        Ok(())
+    }
+}
+
+/// formats the power state and the set color as `on|off,COLOR` with the color in the given format
+fn format_state(on: bool, rgba: &RGBA8, format: ColorFormat) -> String {
+    let power = if on { "on" } else { "off" };
+    return format!("{},{}", power, format.format(rgba));
+}
+
+/// parses the optional `format` parameter, defaults to r,g,b,a
+fn parse_color_format(url: &Url) -> Result<ColorFormat, &'static str> {
+    return match url.query_pairs().find(|(key, _)| key == "format") {
+        Some((_, value)) => ColorFormat::from_str(&value),
+        None => Ok(ColorFormat::Rgba),
+    };
+}
+
+pub struct GetStateHandler {
+    rgba: Arc<RwLock<RGBA8>>,
+    power: Arc<Mutex<Power>>,
+    budget: Arc<Mutex<PowerBudget>>,
+}
+
+impl GetStateHandler {
+    pub fn new(
+        rgba: Arc<RwLock<RGBA8>>,
+        power: Arc<Mutex<Power>>,
+        budget: Arc<Mutex<PowerBudget>>,
+    ) -> GetStateHandler {
+        return GetStateHandler {
+            rgba,
+            power,
+            budget,
+        };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for GetStateHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+        let format = match parse_color_format(&url) {
+            Ok(val) => val,
+            Err(e) => return Err(send_error_response(req, e)),
+        };
+
+        let on = match self.power.lock() {
+            Ok(power) => power.is_on(),
+            Err(_) => {
+                return Err(send_error_response(req, "could not get power lock"));
+            }
+        };
+        let rgba = match self.rgba.read() {
+            Ok(val) => *val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get read lock"));
+            }
+        };
+
+        let estimate = match self.budget.lock() {
+            Ok(budget) => budget.estimate_w(),
+            Err(_) => {
+                return Err(send_error_response(req, "could not get budget lock"));
+            }
+        };
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "{},{:.1}",
+            format_state(on, &rgba, format),
+            estimate
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PowerAction {
+    On,
+    Off,
+    Toggle,
+    Config,
+}
+
+pub struct PowerHandler {
+    action: PowerAction,
+    rgba: Arc<RwLock<RGBA8>>,
+    power: Arc<Mutex<Power>>,
+}
+
+impl PowerHandler {
+    pub fn new(
+        action: PowerAction,
+        rgba: Arc<RwLock<RGBA8>>,
+        power: Arc<Mutex<Power>>,
+    ) -> PowerHandler {
+        return PowerHandler {
+            action,
+            rgba,
+            power,
+        };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for PowerHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut power = match self.power.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get power lock"));
+            }
+        };
+
+        if let PowerAction::Config = self.action {
+            let mut config = power.config().clone();
+            for (key, value) in url.query_pairs() {
+                let field = match key.borrow() {
+                    "fade_on" => &mut config.fade_on_ms,
+                    "fade_off" => &mut config.fade_off_ms,
+                    _ => {
+                        warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                        continue;
+                    }
+                };
+                match value.parse::<u32>() {
+                    Ok(val) => *field = val,
+                    Err(_) => {
+                        drop(power);
+                        return Err(send_error_response(req, "invalid fade duration"));
+                    }
+                }
+            }
+            if let Err(e) = power.set_config(config) {
+                drop(power);
+                return Err(send_error_response(req, e));
+            }
+
+            let mut response = req.into_ok_response()?;
+            response.write_fmt(format_args!(
+                "power={}\nfade_on={}\nfade_off={}\n",
+                if power.is_on() { "on" } else { "off" },
+                power.config().fade_on_ms,
+                power.config().fade_off_ms
+            ))?;
+            response.flush()?;
+            return Ok(());
+        }
+
+        let mut duration = None;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "t" => match value.parse::<u64>() {
+                    Ok(ms) => duration = Some(Duration::from_millis(ms)),
+                    Err(_) => {
+                        drop(power);
+                        return Err(send_error_response(req, "invalid transition time"));
+                    }
+                },
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value)
+                }
+            }
+        }
+        match self.action {
+            PowerAction::On => power.set(true, duration),
+            PowerAction::Off => power.set(false, duration),
+            _ => {
+                power.toggle(duration);
+            }
+        }
+        let on = power.is_on();
+        drop(power);
+
+        let rgba = match self.rgba.read() {
+            Ok(val) => *val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get read lock"));
+            }
+        };
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "{}",
+            format_state(on, &rgba, ColorFormat::Rgba)
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct WhiteHandler {
+    white: Arc<RwLock<WhiteChannels>>,
+}
+
+impl WhiteHandler {
+    pub fn new(white: Arc<RwLock<WhiteChannels>>) -> WhiteHandler {
+        return WhiteHandler { white };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for WhiteHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let params: Vec<_> = url.query_pairs().collect();
+        for (key, value) in &params {
+            if !is_white_param(key) {
+                warn!("Unknown query parameter! key:{} value:{}!", key, value);
+            }
+        }
+
+        let mut white = match self.white.write() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get write lock"));
+            }
+        };
+        let mut new_white = *white;
+        if let Err(e) = apply_white_params(&mut new_white, &params) {
+            drop(white);
+            return Err(send_error_response(req, e));
+        }
+        *white = new_white;
+        drop(white);
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "{},{},{}",
+            new_white.w, new_white.ww, new_white.cw
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ZoneAction {
+    List,
+    State,
+}
+
+pub struct ZoneHandler {
+    action: ZoneAction,
+    zones: Arc<Vec<Arc<Zone>>>,
+}
+
+impl ZoneHandler {
+    pub fn new(action: ZoneAction, zones: Arc<Vec<Arc<Zone>>>) -> ZoneHandler {
+        return ZoneHandler { action, zones };
+    }
+}
+
+/// new color and white values of a zone, applied after all zones were validated
+struct ZoneUpdate {
+    color: RGBA8,
+    white: WhiteChannels,
+}
+
+/// parses the zone target and resource of `/zones/{id|name|all}/{state|budget}`
+fn parse_zone_target(path: &str) -> Option<(&str, &str)> {
+    let (target, resource) = path.strip_prefix("/zones/")?.split_once('/')?;
+    if target.is_empty() || !matches!(resource, "state" | "budget") {
+        return None;
+    }
+    return Some((target, resource));
+}
+
+/// shows and sets the power budget of the addressed zones
+fn handle_zone_budget(
+    req: Request<&mut EspHttpConnection>,
+    url: &Url,
+    zones: &[&Arc<Zone>],
+) -> embedded_svc::http::server::HandlerResult {
+    let mut budgets = Vec::with_capacity(zones.len());
+    for zone in zones {
+        match zone.budget.lock() {
+            Ok(budget) => budgets.push(budget),
+            Err(_) => return Err(send_error_response(req, "could not get budget lock")),
+        }
+    }
+
+    if let Some(budget) = budgets.first() {
+        let mut config = budget.config().clone();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "supply" => value
+                    .parse::<f32>()
+                    .map(|val| config.supply_watts = val)
+                    .map_err(|_| "invalid supply watts"),
+                "length" => value
+                    .parse::<f32>()
+                    .map(|val| config.length_m = val)
+                    .map_err(|_| "invalid stripe length"),
+                "wpm" => parse_list(&value, &mut config.watts_per_meter)
+                    .ok_or("invalid watts per meter, expected R,G,B[,W[,CW]]"),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+            changed = true;
+        }
+        if changed {
+            for budget in budgets.iter_mut() {
+                if let Err(e) = budget.set_config(config.clone()) {
+                    return Err(send_error_response(req, e));
+                }
+            }
+        }
+    }
+
+    let mut response = req.into_ok_response()?;
+    for (zone, budget) in zones.iter().zip(budgets) {
+        let config = budget.config();
+        response.write_fmt(format_args!(
+            "zone={}\nsupply={}\nlength={}\nwpm={}\nestimate={:.1}\nlimited={}\n\n",
+            zone.id,
+            config.supply_watts,
+            config.length_m,
+            format_list(&config.watts_per_meter),
+            budget.estimate_w(),
+            budget.is_limited() as u8
+        ))?;
+    }
+    response.flush()?;
+    Ok(())
+}
+
+impl Handler<EspHttpConnection<'_>> for ZoneHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+        let format = match parse_color_format(&url) {
+            Ok(val) => val,
+            Err(e) => return Err(send_error_response(req, e)),
+        };
+
+        let zones = match self.action {
+            ZoneAction::List => self.zones.iter().collect(),
+            ZoneAction::State => match parse_zone_target(url.path()) {
+                Some((target, resource)) => match zones::resolve(&self.zones, target) {
+                    Some(zones) if resource == "budget" => {
+                        return handle_zone_budget(req, &url, &zones);
+                    }
+                    Some(zones) => zones,
+                    None => return Err(send_error_response(req, "unknown zone")),
+                },
+                None => return Err(send_error_response(req, "invalid zone path")),
+            },
+        };
+
+        if let ZoneAction::State = self.action {
+            let params: Vec<_> = url.query_pairs().collect();
+            let mut power_command = None;
+            let mut duration = None;
+            for (key, value) in &params {
+                match key.borrow() {
+                    "power" if matches!(value.as_ref(), "on" | "off" | "toggle") => {
+                        power_command = Some(value.to_string())
+                    }
+                    "power" => return Err(send_error_response(req, "invalid power command")),
+                    "t" => match value.parse::<u64>() {
+                        Ok(ms) => duration = Some(Duration::from_millis(ms)),
+                        Err(_) => {
+                            return Err(send_error_response(req, "invalid transition time"));
+                        }
+                    },
+                    "format" => {}
+                    key if is_color_param(key) || is_white_param(key) => {}
+                    _ => {
+                        warn!("Unknown query parameter! key:{} value:{}!", key, value)
+                    }
+                }
+            }
+
+            // only update the zones, if the values are valid for every addressed zone
+            let mut updates = Vec::with_capacity(zones.len());
+            for zone in &zones {
+                let (mut color, mut white) = match (zone.rgba.read(), zone.white.read()) {
+                    (Ok(rgba), Ok(white)) => (*rgba, *white),
+                    _ => return Err(send_error_response(req, "could not get read lock")),
+                };
+                let result = apply_color_params(&mut color, &params)
+                    .and_then(|_| apply_white_params(&mut white, &params));
+                if let Err(e) = result {
+                    return Err(send_error_response(req, e));
+                }
+                updates.push(ZoneUpdate { color, white });
+            }
+
+            // keep running transitions, if the color is not changed
+            let has_color = params.iter().any(|(key, _)| is_color_param(key));
+            for (zone, update) in zones.iter().zip(updates) {
+                if has_color {
+                    let result = transition::start(
+                        &zone.rgba,
+                        &zone.transition,
+                        update.color,
+                        duration.unwrap_or(Duration::ZERO),
+                    );
+                    if let Err(e) = result {
+                        return Err(send_error_response(req, e));
+                    }
+                }
+                match zone.white.write() {
+                    Ok(mut white) => *white = update.white,
+                    Err(_) => return Err(send_error_response(req, "could not get write lock")),
+                }
+                if let Some(command) = power_command.as_deref() {
+                    match zone.power.lock() {
+                        Ok(mut power) => match command {
+                            "on" => power.set(true, duration),
+                            "off" => power.set(false, duration),
+                            _ => {
+                                power.toggle(duration);
+                            }
+                        },
+                        Err(_) => {
+                            return Err(send_error_response(req, "could not get power lock"));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut states = Vec::with_capacity(zones.len());
+        for zone in &zones {
+            let on = match zone.power.lock() {
+                Ok(power) => power.is_on(),
+                Err(_) => {
+                    return Err(send_error_response(req, "could not get power lock"));
+                }
+            };
+            let rgba = match zone.rgba.read() {
+                Ok(val) => *val,
+                Err(_) => {
+                    return Err(send_error_response(req, "could not get read lock"));
+                }
+            };
+            let estimate = match zone.budget.lock() {
+                Ok(budget) => budget.estimate_w(),
+                Err(_) => {
+                    return Err(send_error_response(req, "could not get budget lock"));
+                }
+            };
+            states.push(format!(
+                "{},{},{},{:.1}\n",
+                zone.id,
+                zone.name,
+                format_state(on, &rgba, format),
+                estimate
+            ));
+        }
+
+        let mut response = req.into_ok_response()?;
+        for state in states {
+            response.write_all(state.as_bytes())?;
+        }
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct PinsHandler {
+    pins: Arc<Pins>,
+}
+
+impl PinsHandler {
+    pub fn new(pins: Arc<Pins>) -> PinsHandler {
+        return PinsHandler { pins };
+    }
+}
+
+/// parses a comma separated list of values into the start of `target`
+fn parse_list<T: FromStr>(value: &str, target: &mut [T]) -> Option<()> {
+    let values = value
+        .split(',')
+        .map(|val| val.trim().parse::<T>())
+        .collect::<Result<Vec<T>, _>>()
+        .ok()?;
+    if values.is_empty() || values.len() > target.len() {
+        return None;
+    }
+    for (target, value) in target.iter_mut().zip(values) {
+        *target = value;
+    }
+    return Some(());
+}
+
+/// formats values as comma separated list
+fn format_list<T: std::fmt::Display>(values: &[T]) -> String {
+    return values
+        .iter()
+        .map(|val| val.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+}
+
+/// parses a GPIO number or `none`
+fn parse_optional_pin(value: &str) -> Option<Option<u8>> {
+    if value == "none" {
+        return Some(None);
+    }
+    return value.parse::<u8>().ok().map(Some);
+}
+
+fn format_optional_pin(pin: Option<u8>) -> String {
+    return match pin {
+        Some(pin) => pin.to_string(),
+        None => "none".to_string(),
+    };
+}
+
+impl Handler<EspHttpConnection<'_>> for PinsHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut config = self.pins.pending();
+        let mut changed = false;
+        let mut restart = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "pins" => parse_list(&value, &mut config.pins).ok_or("invalid pins"),
+                "invert" => {
+                    let mut inverted = config.inverted.map(u8::from);
+                    match parse_list(&value, &mut inverted) {
+                        Some(_) if inverted.iter().all(|val| *val <= 1) => {
+                            config.inverted = inverted.map(|val| val == 1);
+                            Ok(())
+                        }
+                        _ => Err("invalid inversion, expected 0 or 1 per pin"),
+                    }
+                }
+                "order" => ChannelOrder::from_str(&value).map(|order| config.order = order),
+                "freq" => value
+                    .parse::<u32>()
+                    .map(|val| config.frequency_hz = val)
+                    .map_err(|_| "invalid pwm frequency"),
+                "status" => value
+                    .parse::<u8>()
+                    .map(|val| config.status_pin = val)
+                    .map_err(|_| "invalid status pin"),
+                "zone2" => parse_list(&value, &mut config.zone2_pins).ok_or("invalid zone2 pins"),
+                "button" => parse_optional_pin(&value)
+                    .map(|val| config.button_pin = val)
+                    .ok_or("invalid button pin"),
+                "ir" => parse_optional_pin(&value)
+                    .map(|val| config.ir_pin = val)
+                    .ok_or("invalid ir pin"),
+                "encoder" if value == "none" => {
+                    config.encoder_pins = None;
+                    Ok(())
+                }
+                "encoder" => {
+                    let mut pins = [0; 2];
+                    match parse_list(&value, &mut pins) {
+                        Some(_) if !value.contains(',') => Err("encoder needs the A and B pin"),
+                        Some(_) => {
+                            config.encoder_pins = Some(pins);
+                            Ok(())
+                        }
+                        None => Err("invalid encoder pins"),
+                    }
+                }
+                "encoder_switch" => parse_optional_pin(&value)
+                    .map(|val| config.encoder_switch_pin = val)
+                    .ok_or("invalid encoder switch pin"),
+                "restart" => {
+                    restart = value == "1";
+                    continue;
+                }
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = self.pins.store(&config) {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "pins={}\norder={}\ninvert={}\nfreq={}\nstatus={}\nzone2={}\n",
+            format_list(&config.pins),
+            config.order.name(),
+            format_list(&config.inverted.map(u8::from)),
+            config.frequency_hz,
+            config.status_pin,
+            format_list(&config.zone2_pins)
+        ))?;
+        response.write_fmt(format_args!(
+            "button={}\nir={}\nencoder={}\nencoder_switch={}\n",
+            format_optional_pin(config.button_pin),
+            format_optional_pin(config.ir_pin),
+            config
+                .encoder_pins
+                .map_or("none".to_string(), |pins| format_list(&pins)),
+            format_optional_pin(config.encoder_switch_pin)
+        ))?;
+        response.write_fmt(format_args!(
+            "restart_required={}\n",
+            config != *self.pins.active()
+        ))?;
+        response.flush()?;
+
+        if restart {
+            warn!("Restarting to apply the pin config");
+            // give the response some time to be sent
+            std::thread::sleep(Duration::from_millis(100));
+            unsafe { esp_idf_sys::esp_restart() };
+        }
+        Ok(())
+    }
+}
+
+pub struct ThermalHandler {
+    thermal: Arc<Mutex<Thermal>>,
+}
+
+impl ThermalHandler {
+    pub fn new(thermal: Arc<Mutex<Thermal>>) -> ThermalHandler {
+        return ThermalHandler { thermal };
+    }
+}
+
+/// formats an optional temperature, `unavailable` without reading
+fn format_temperature(celsius: Option<f32>) -> String {
+    return match celsius {
+        Some(val) => format!("{:.1}", val),
+        None => "unavailable".to_string(),
+    };
+}
+
+impl Handler<EspHttpConnection<'_>> for ThermalHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut thermal = match self.thermal.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get thermal lock"));
+            }
+        };
+
+        let mut config = thermal.config().clone();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let field = match key.borrow() {
+                "start" => &mut config.start_c,
+                "critical" => &mut config.critical_c,
+                "min" => &mut config.min_level,
+                "hysteresis" => &mut config.hysteresis_c,
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            match value.parse::<f32>() {
+                Ok(val) => *field = val,
+                Err(_) => {
+                    drop(thermal);
+                    return Err(send_error_response(req, "invalid derating value"));
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = thermal.set_config(config) {
+                drop(thermal);
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let config = thermal.config();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "chip={}\nntc={}\nstatus={}\nlevel={:.2}\n",
+            format_temperature(thermal.chip_c()),
+            format_temperature(thermal.ntc_c()),
+            thermal.status().name(),
+            thermal.level()
+        ))?;
+        response.write_fmt(format_args!(
+            "start={}\ncritical={}\nmin={}\nhysteresis={}\n",
+            config.start_c, config.critical_c, config.min_level, config.hysteresis_c
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct ButtonHandler {
+    button: Arc<Mutex<Button>>,
+}
+
+impl ButtonHandler {
+    pub fn new(button: Arc<Mutex<Button>>) -> ButtonHandler {
+        return ButtonHandler { button };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for ButtonHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut button = match self.button.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get button lock"));
+            }
+        };
+
+        let mut config = button.config().clone();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "click" => ButtonAction::from_str(&value).map(|val| config.click = val),
+                "double" => ButtonAction::from_str(&value).map(|val| config.double_click = val),
+                "long" => ButtonAction::from_str(&value).map(|val| config.long_press = val),
+                "verylong" => {
+                    ButtonAction::from_str(&value).map(|val| config.very_long_press = val)
+                }
+                "zone" => {
+                    config.zone = value.to_string();
+                    Ok(())
+                }
+                "step" => value
+                    .parse::<u8>()
+                    .map(|val| config.dim_step = val)
+                    .map_err(|_| "invalid dim step"),
+                "t" => value
+                    .parse::<u32>()
+                    .map(|val| config.transition_ms = val)
+                    .map_err(|_| "invalid transition time"),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                drop(button);
+                return Err(send_error_response(req, e));
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = button.set_config(config) {
+                drop(button);
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let config = button.config();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "click={}\ndouble={}\nlong={}\nverylong={}\nzone={}\nstep={}\nt={}\n",
+            config.click.name(),
+            config.double_click.name(),
+            config.long_press.name(),
+            config.very_long_press.name(),
+            config.zone,
+            config.dim_step,
+            config.transition_ms
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct EncoderHandler {
+    encoder: Arc<Mutex<Encoder>>,
+}
+
+impl EncoderHandler {
+    pub fn new(encoder: Arc<Mutex<Encoder>>) -> EncoderHandler {
+        return EncoderHandler { encoder };
+    }
+}
+
+/// parses `0` or `1`
+fn parse_flag(value: &str) -> Option<bool> {
+    return match value {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    };
+}
+
+impl Handler<EspHttpConnection<'_>> for EncoderHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut encoder = match self.encoder.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get encoder lock"));
+            }
+        };
+
+        let mut config = encoder.config().clone();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "mode" => {
+                    match EncoderMode::from_str(&value) {
+                        Ok(mode) => encoder.set_mode(mode),
+                        Err(e) => {
+                            drop(encoder);
+                            return Err(send_error_response(req, e));
+                        }
+                    }
+                    continue;
+                }
+                "click" => PushAction::from_str(&value).map(|val| config.click = val),
+                "double" => PushAction::from_str(&value).map(|val| config.double_click = val),
+                "long" => PushAction::from_str(&value).map(|val| config.long_press = val),
+                "zone" => {
+                    config.zone = value.to_string();
+                    Ok(())
+                }
+                "brightness" => value
+                    .parse::<u8>()
+                    .map(|val| config.brightness_step = val)
+                    .map_err(|_| "invalid brightness step"),
+                "hue" => value
+                    .parse::<u16>()
+                    .map(|val| config.hue_step = val)
+                    .map_err(|_| "invalid hue step"),
+                "saturation" => value
+                    .parse::<u8>()
+                    .map(|val| config.saturation_step = val)
+                    .map_err(|_| "invalid saturation step"),
+                "detent" => value
+                    .parse::<u8>()
+                    .map(|val| config.steps_per_detent = val)
+                    .map_err(|_| "invalid steps per detent"),
+                "accel" => parse_flag(&value)
+                    .map(|val| config.acceleration = val)
+                    .ok_or("invalid acceleration, expected 0 or 1"),
+                "reverse" => parse_flag(&value)
+                    .map(|val| config.reverse = val)
+                    .ok_or("invalid reverse, expected 0 or 1"),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                drop(encoder);
+                return Err(send_error_response(req, e));
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = encoder.set_config(config) {
+                drop(encoder);
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let config = encoder.config();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "mode={}\nclick={}\ndouble={}\nlong={}\nzone={}\n",
+            encoder.mode().name(),
+            config.click.name(),
+            config.double_click.name(),
+            config.long_press.name(),
+            config.zone
+        ))?;
+        response.write_fmt(format_args!(
+            "brightness={}\nhue={}\nsaturation={}\ndetent={}\naccel={}\nreverse={}\n",
+            config.brightness_step,
+            config.hue_step,
+            config.saturation_step,
+            config.steps_per_detent,
+            u8::from(config.acceleration),
+            u8::from(config.reverse)
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct IdentifyHandler {
+    identify: Arc<Identify>,
+}
+
+impl IdentifyHandler {
+    pub fn new(identify: Arc<Identify>) -> IdentifyHandler {
+        return IdentifyHandler { identify };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for IdentifyHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut duration = identify::DEFAULT_DURATION;
+        let mut stop = false;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "duration" => match value.parse::<u64>() {
+                    Ok(val) => duration = Duration::from_secs(val),
+                    Err(_) => {
+                        return Err(send_error_response(req, "invalid identify duration"));
+                    }
+                },
+                "stop" => stop = value == "1",
+                _ => warn!("Unknown query parameter! key:{} value:{}!", key, value),
+            }
+        }
+        let result = if stop {
+            self.identify.stop()
+        } else {
+            self.identify.start(duration)
+        };
+        if let Err(e) = result {
+            return Err(send_error_response(req, e));
+        }
+
+        let remaining = self.identify.remaining();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "identifying={}\nremaining={}\n",
+            remaining.is_some(),
+            remaining.map_or(0, |val| val.as_secs())
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct SyncHandler {
+    sync: Arc<Mutex<GroupSync>>,
+}
+
+impl SyncHandler {
+    pub fn new(sync: Arc<Mutex<GroupSync>>) -> SyncHandler {
+        return SyncHandler { sync };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for SyncHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut sync = match self.sync.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get sync lock"));
+            }
+        };
+
+        let mut config = sync.config().clone();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "group" => {
+                    config.group = value.to_string();
+                    Ok(())
+                }
+                "role" => SyncRole::from_str(&value).map(|val| config.role = val),
+                "priority" => value
+                    .parse::<u8>()
+                    .map(|val| config.priority = val)
+                    .map_err(|_| "invalid sync priority"),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                drop(sync);
+                return Err(send_error_response(req, e));
+            }
+            changed = true;
+        }
+        if changed {
+            if let Err(e) = sync.set_config(config) {
+                drop(sync);
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let config = sync.config();
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "group={}\nrole={}\npriority={}\nnode={}\n",
+            config.group,
+            config.role.name(),
+            config.priority,
+            sync.node()
+        ))?;
+        response.write_fmt(format_args!(
+            "leader={}\npeers={}\nclock={}\nversion={}\n",
+            sync.leader().unwrap_or("none"),
+            sync.peers().join(","),
+            sync.clock_ms(),
+            sync.version()
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct WifiHandler {
+    storage: Arc<Storage>,
+    // ssid from the cfg.toml, used without stored credentials
+    default_ssid: &'static str,
+    // restarts after storing new credentials, the access point is useless once they are set
+    restart_on_store: bool,
+}
+
+impl WifiHandler {
+    pub fn new(
+        storage: Arc<Storage>,
+        default_ssid: &'static str,
+        restart_on_store: bool,
+    ) -> WifiHandler {
+        return WifiHandler {
+            storage,
+            default_ssid,
+            restart_on_store,
+        };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for WifiHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut ssid = None;
+        let mut passphrase = String::new();
+        let mut provision = false;
+        let mut restart = false;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "ssid" => ssid = Some(value.to_string()),
+                "passphrase" => passphrase = value.to_string(),
+                "provision" => provision = value == "1",
+                "restart" => restart = value == "1",
+                _ => warn!("Unknown query parameter! key:{}!", key),
+            }
+        }
+        if let Some(ssid) = ssid {
+            let credentials = WifiCredentials { ssid, passphrase };
+            if let Err(e) = provisioning::store_credentials(&self.storage, &credentials) {
+                return Err(send_error_response(req, e));
+            }
+            restart |= self.restart_on_store;
+        }
+        if provision {
+            if let Err(e) = provisioning::request(&self.storage) {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        // the passphrase is never sent back
+        let (ssid, stored) = match provisioning::load_credentials(&self.storage) {
+            Some(credentials) => (credentials.ssid, true),
+            None => (self.default_ssid.to_string(), false),
+        };
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!("ssid={}\nstored={}\n", ssid, stored))?;
+        response.flush()?;
+
+        if restart || provision {
+            warn!("Restarting to apply the wifi config");
+            // give the response some time to be sent
+            std::thread::sleep(Duration::from_millis(100));
+            unsafe { esp_idf_sys::esp_restart() };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IrAction {
+    Status,
+    Codes,
+    Learn,
+    Map,
+    Delete,
+}
+
+pub struct IrHandler {
+    action: IrAction,
+    remote: Arc<Mutex<IrRemote>>,
+}
+
+impl IrHandler {
+    pub fn new(action: IrAction, remote: Arc<Mutex<IrRemote>>) -> IrHandler {
+        return IrHandler { action, remote };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for IrHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut code = None;
+        let mut action = None;
+        let mut zone = None;
+        let mut timeout = ir_remote::DEFAULT_LEARN_TIMEOUT;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "code" => IrCode::from_str(&value).map(|val| code = Some(val)),
+                "action" => RemoteAction::from_str(&value).map(|val| action = Some(val)),
+                "zone" => {
+                    zone = Some(value.to_string());
+                    Ok(())
+                }
+                "timeout" => value
+                    .parse::<u64>()
+                    .map(|val| timeout = Duration::from_secs(val))
+                    .map_err(|_| "invalid timeout"),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let mut remote = match self.remote.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                return Err(send_error_response(req, "could not get ir lock"));
+            }
+        };
+
+        let result = match (self.action, code, action) {
+            (IrAction::Status, _, _) => match zone {
+                Some(zone) => remote.set_zone(&zone),
+                None => Ok(()),
+            },
+            (IrAction::Codes, _, _) => Ok(()),
+            (IrAction::Learn, _, Some(action)) => {
+                remote.learn(action, timeout);
+                Ok(())
+            }
+            (IrAction::Map, Some(code), Some(action)) => remote.map(code, &action),
+            (IrAction::Delete, Some(code), _) => remote.delete(code),
+            (IrAction::Learn | IrAction::Map, _, None) => Err("missing action"),
+            (IrAction::Map | IrAction::Delete, None, _) => Err("missing ir code"),
+        };
+        if let Err(e) = result {
+            drop(remote);
+            return Err(send_error_response(req, e));
+        }
+
+        let mut response = req.into_ok_response()?;
+        match self.action {
+            IrAction::Codes | IrAction::Map | IrAction::Delete => {
+                for (code, action, learned) in remote.mappings() {
+                    let source = if learned { "learned" } else { "builtin" };
+                    response.write_fmt(format_args!("{},{},{}\n", code, action, source))?;
+                }
+            }
+            IrAction::Status | IrAction::Learn => {
+                let last = match remote.last_code() {
+                    Some(code) => code.to_string(),
+                    None => "none".to_string(),
+                };
+                let learning = match remote.learning() {
+                    Some(action) => action.to_string(),
+                    None => "off".to_string(),
+                };
+                response.write_fmt(format_args!(
+                    "zone={}\nlast={}\nlearning={}\n",
+                    remote.zone(),
+                    last,
+                    learning
+                ))?;
+            }
+        }
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum WledAction {
+    All,
+    State,
+    Info,
+    Effects,
+    Palettes,
+}
+
+pub struct WledHandler {
+    action: WledAction,
+    wled: Arc<Wled>,
+}
+
+impl WledHandler {
+    pub fn new(action: WledAction, wled: Arc<Wled>) -> WledHandler {
+        return WledHandler { action, wled };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for WledHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let mut req = Request::wrap(c);
+
+        // WLED clients POST state changes to /json or /json/state
+        let mut verbose = true;
+        if let Method::Post = req.method() {
+            let body = match read_body(&mut req) {
+                Ok(val) => val,
+                Err(e) => {
+                    return Err(send_error_response(req, e));
+                }
+            };
+            let update = match serde_json::from_slice::<StateUpdate>(&body) {
+                Ok(val) => val,
+                Err(_) => {
+                    return Err(send_error_response(req, "invalid JSON body"));
+                }
+            };
+            if let Err(e) = self.wled.update(&update) {
+                return Err(send_error_response(req, e));
+            }
+            verbose = update.v;
+        }
+
+        let body = match self.action {
+            _ if !verbose => Ok("{\"success\":true}".to_string()),
+            WledAction::All => self.wled.state().and_then(|state| {
+                serde_json::to_string(&wled::Everything {
+                    state,
+                    info: self.wled.info(),
+                    effects: wled::EFFECTS,
+                    palettes: wled::PALETTES,
+                })
+                .map_err(|_| "could not serialize WLED state")
+            }),
+            WledAction::State => self.wled.state().and_then(|state| {
+                serde_json::to_string(&state).map_err(|_| "could not serialize WLED state")
+            }),
+            WledAction::Info => serde_json::to_string(&self.wled.info())
+                .map_err(|_| "could not serialize WLED info"),
+            WledAction::Effects => serde_json::to_string(&wled::EFFECTS)
+                .map_err(|_| "could not serialize WLED effects"),
+            WledAction::Palettes => serde_json::to_string(&wled::PALETTES)
+                .map_err(|_| "could not serialize WLED palettes"),
+        };
+        let body = match body {
+            Ok(val) => val,
+            Err(e) => {
+                return Err(send_error_response(req, e));
+            }
+        };
+        let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+        response.write_all(body.as_bytes())?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct HueHandler {
+    hue: Arc<Hue>,
+}
+
+impl HueHandler {
+    pub fn new(hue: Arc<Hue>) -> HueHandler {
+        return HueHandler { hue };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for HueHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "link" if value == "1" => self.hue.press_link(),
+                "clear" if value == "1" => self.hue.clear_users(),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "bridgeid={}\nlink={}\nusers={}\n",
+            self.hue.bridge_id(),
+            self.hue.link_remaining().map_or(0, |val| val.as_secs()),
+            self.hue.user_count()
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HueApiAction {
+    Description,
+    Pair,
+    Resource,
+}
+
+pub struct HueApiHandler {
+    action: HueApiAction,
+    hue: Arc<Hue>,
+}
+
+impl HueApiHandler {
+    pub fn new(action: HueApiAction, hue: Arc<Hue>) -> HueApiHandler {
+        return HueApiHandler { action, hue };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for HueApiHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let mut req = Request::wrap(c);
+
+        let result = match self.action {
+            HueApiAction::Description => {
+                // the description points the client to the address it used
+                let host = req.header("Host").unwrap_or("").to_string();
+                let body = self.hue.description(&host);
+                let mut response = req.into_response(200, None, &[("Content-Type", "text/xml")])?;
+                response.write_all(body.as_bytes())?;
+                response.flush()?;
+                return Ok(());
+            }
+            HueApiAction::Pair => {
+                let body = match read_body(&mut req) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        return Err(send_error_response(req, e));
+                    }
+                };
+                match serde_json::from_slice::<PairRequest>(&body) {
+                    Ok(request) => self.hue.pair(&request),
+                    Err(_) => {
+                        return Err(send_error_response(req, "invalid JSON body"));
+                    }
+                }
+            }
+            HueApiAction::Resource => {
+                let uri = req.uri().to_string();
+                let path = uri.split('?').next().unwrap_or("");
+                let mut segments = path
+                    .trim_start_matches("/api/")
+                    .split('/')
+                    .filter(|val| !val.is_empty());
+                let user = segments.next().unwrap_or("");
+                let path: Vec<&str> = segments.collect();
+                match req.method() {
+                    Method::Put => match read_body(&mut req) {
+                        Ok(body) => self.hue.put(user, &path, &body),
+                        Err(e) => Err(e),
+                    },
+                    _ => self.hue.get(user, &path),
+                }
+            }
+        };
+        let body = match result {
+            Ok(val) => val.to_string(),
+            Err(e) => {
+                return Err(send_error_response(req, e));
+            }
+        };
+        let mut response = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+        response.write_all(body.as_bytes())?;
+        response.flush()?;
+        Ok(())
+    }
+}
+
+pub struct RealtimeHandler {
+    realtime: Arc<Realtime>,
+}
+
+impl RealtimeHandler {
+    pub fn new(realtime: Arc<Realtime>) -> RealtimeHandler {
+        return RealtimeHandler { realtime };
+    }
+}
+
+impl Handler<EspHttpConnection<'_>> for RealtimeHandler {
+    fn handle(&self, c: &mut EspHttpConnection<'_>) -> embedded_svc::http::server::HandlerResult {
+        let req = Request::wrap(c);
+        let url = match parse_request_url(req.uri()) {
+            None => {
+                return Err(send_error_response(req, "parse URL from request"));
+            }
+            Some(val) => val,
+        };
+
+        let mut config = self.realtime.config();
+        let mut changed = false;
+        for (key, value) in url.query_pairs() {
+            let result = match key.borrow() {
+                "timeout" => value
+                    .parse::<u32>()
+                    .map(|val| {
+                        config.timeout_ms = val;
+                        changed = true;
+                    })
+                    .map_err(|_| "invalid realtime timeout"),
+                "stop" if value == "1" => self.realtime.stop(),
+                _ => {
+                    warn!("Unknown query parameter! key:{} value:{}!", key, value);
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                return Err(send_error_response(req, e));
+            }
+        }
+        if changed {
+            if let Err(e) = self.realtime.set_config(config) {
+                return Err(send_error_response(req, e));
+            }
+        }
+
+        let mut response = req.into_ok_response()?;
+        response.write_fmt(format_args!(
+            "source={}\ntimeout={}\n",
+            self.realtime.source().unwrap_or("none"),
+            self.realtime.config().timeout_ms
+        ))?;
+        response.flush()?;
+        Ok(())
+    }
+}
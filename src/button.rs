@@ -0,0 +1,434 @@
+//! Local control with a push button
+//!
+//! The button is polled every `POLL_INTERVAL` and debounced, the gesture detector turns the
+//! debounced state into clicks, double clicks and long presses. Every gesture is mapped to an
+//! action, which changes the addressed zones the same way as the HTTP API does:
+//! - `toggle`: switches the power state
+//! - `scene`: recalls the next saved scene
+//! - `dim`: dims up or down while the button is held, the direction changes with every press
+//! - `provision`: restarts into the wifi provisioning access point
+//! - `restart`: restarts the controller
+//! - `link`: presses the link button of the Hue bridge emulation, so Hue clients can pair
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::gpio::{PinDriver, Pull};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::hue::Hue;
+use crate::pins;
+use crate::provisioning;
+use crate::rgb_led::RGBA8;
+use crate::scenes::Scenes;
+use crate::storage::Storage;
+use crate::transition;
+use crate::zones::{self, Zone, GROUP_TARGET};
+
+const STORAGE_KEY: &str = "button";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// time the raw state has to be stable to be accepted
+const DEBOUNCE: Duration = Duration::from_millis(30);
+/// maximum time between the release of the first and the press of the second click
+const DOUBLE_CLICK_WINDOW: Duration = Duration::from_millis(350);
+const LONG_PRESS: Duration = Duration::from_millis(600);
+/// interval of the dim steps while the button is held
+const HOLD_INTERVAL: Duration = Duration::from_millis(100);
+const VERY_LONG_PRESS: Duration = Duration::from_secs(8);
+/// dimming down stops at this brightness, so the button never turns the stripe off
+const MIN_DIM_LEVEL: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Click,
+    DoubleClick,
+    LongPress,
+    /// repeated every `HOLD_INTERVAL` after a long press until the button is released
+    Hold,
+    /// release after a long or very long press
+    LongRelease,
+    VeryLongPress,
+}
+
+/// Debounces the raw button state and detects gestures, has no hardware dependencies
+pub struct GestureDetector {
+    raw: bool,
+    raw_since: Instant,
+    // debounced state
+    pressed: bool,
+    pressed_since: Instant,
+    last_hold: Instant,
+    long: bool,
+    very_long: bool,
+    // release of a first click, waiting for a second one
+    pending_click: Option<Instant>,
+}
+
+impl GestureDetector {
+    pub fn new(now: Instant) -> GestureDetector {
+        return GestureDetector {
+            raw: false,
+            raw_since: now,
+            pressed: false,
+            pressed_since: now,
+            last_hold: now,
+            long: false,
+            very_long: false,
+            pending_click: None,
+        };
+    }
+
+    /// feeds the raw button state, returns the detected gesture
+    pub fn update(&mut self, raw_pressed: bool, now: Instant) -> Option<Gesture> {
+        if raw_pressed != self.raw {
+            self.raw = raw_pressed;
+            self.raw_since = now;
+        }
+        if self.raw != self.pressed && now.saturating_duration_since(self.raw_since) >= DEBOUNCE {
+            self.pressed = self.raw;
+            if self.pressed {
+                self.pressed_since = now;
+                self.long = false;
+                self.very_long = false;
+                return None;
+            }
+            return self.release(now);
+        }
+        if self.pressed {
+            return self.held(now);
+        }
+        match self.pending_click {
+            Some(released) if now.saturating_duration_since(released) >= DOUBLE_CLICK_WINDOW => {
+                self.pending_click = None;
+                return Some(Gesture::Click);
+            }
+            _ => return None,
+        }
+    }
+
+    fn release(&mut self, now: Instant) -> Option<Gesture> {
+        if self.long {
+            return Some(Gesture::LongRelease);
+        }
+        if self.pending_click.take().is_some() {
+            return Some(Gesture::DoubleClick);
+        }
+        self.pending_click = Some(now);
+        return None;
+    }
+
+    fn held(&mut self, now: Instant) -> Option<Gesture> {
+        let held = now.saturating_duration_since(self.pressed_since);
+        if self.very_long {
+            return None;
+        }
+        if held >= VERY_LONG_PRESS {
+            self.very_long = true;
+            return Some(Gesture::VeryLongPress);
+        }
+        if !self.long && held >= LONG_PRESS {
+            self.long = true;
+            self.last_hold = now;
+            // a long second press is no double click
+            self.pending_click = None;
+            return Some(Gesture::LongPress);
+        }
+        if self.long && now.saturating_duration_since(self.last_hold) >= HOLD_INTERVAL {
+            self.last_hold = now;
+            return Some(Gesture::Hold);
+        }
+        return None;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ButtonAction {
+    None,
+    Toggle,
+    Scene,
+    Dim,
+    Provision,
+    Restart,
+    /// presses the link button of the Hue bridge emulation
+    Link,
+}
+
+impl ButtonAction {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ButtonAction::None => "none",
+            ButtonAction::Toggle => "toggle",
+            ButtonAction::Scene => "scene",
+            ButtonAction::Dim => "dim",
+            ButtonAction::Provision => "provision",
+            ButtonAction::Restart => "restart",
+            ButtonAction::Link => "link",
+        };
+    }
+}
+
+impl FromStr for ButtonAction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "none" => Ok(ButtonAction::None),
+            "toggle" => Ok(ButtonAction::Toggle),
+            "scene" => Ok(ButtonAction::Scene),
+            "dim" => Ok(ButtonAction::Dim),
+            "provision" => Ok(ButtonAction::Provision),
+            "restart" => Ok(ButtonAction::Restart),
+            "link" => Ok(ButtonAction::Link),
+            _ => Err("unknown button action"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ButtonConfig {
+    pub click: ButtonAction,
+    pub double_click: ButtonAction,
+    pub long_press: ButtonAction,
+    pub very_long_press: ButtonAction,
+    /// addressed zones (zone id, zone name or `all`)
+    pub zone: String,
+    /// brightness change per dim step
+    pub dim_step: u8,
+    /// transition time of scene recalls in milliseconds
+    pub transition_ms: u32,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        return ButtonConfig {
+            click: ButtonAction::Toggle,
+            double_click: ButtonAction::Scene,
+            long_press: ButtonAction::Dim,
+            very_long_press: ButtonAction::Provision,
+            zone: GROUP_TARGET.to_string(),
+            dim_step: 5,
+            transition_ms: 500,
+        };
+    }
+}
+
+impl ButtonConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        // dimming needs the hold steps, which only follow the long press
+        if [self.click, self.double_click, self.very_long_press].contains(&ButtonAction::Dim) {
+            return Err("dim is only available for the long press");
+        }
+        if self.dim_step == 0 {
+            return Err("dim step must not be 0");
+        }
+        return Ok(());
+    }
+}
+
+pub struct Button {
+    config: ButtonConfig,
+    storage: Arc<Storage>,
+}
+
+impl Button {
+    /// loads the previously saved action mapping from the storage
+    pub fn load(storage: Arc<Storage>) -> Button {
+        let config: ButtonConfig = storage.load(STORAGE_KEY).unwrap_or_default();
+        let config = match config.validate() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!(
+                    "Stored button config is invalid, using defaults! Error: {}",
+                    e
+                );
+                ButtonConfig::default()
+            }
+        };
+        return Button { config, storage };
+    }
+
+    pub fn config(&self) -> &ButtonConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: ButtonConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.storage.store(STORAGE_KEY, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+}
+
+/// executes the actions of the detected gestures on the addressed zones
+struct Dispatcher {
+    zones: Arc<Vec<Arc<Zone>>>,
+    scenes: Arc<Mutex<Scenes>>,
+    storage: Arc<Storage>,
+    hue: Arc<Hue>,
+    next_scene: usize,
+    dim_up: bool,
+}
+
+impl Dispatcher {
+    fn handle(&mut self, gesture: Gesture, config: &ButtonConfig) -> Result<(), &'static str> {
+        let all_zones = self.zones.clone();
+        let zones = zones::resolve(&all_zones, &config.zone).ok_or("unknown zone")?;
+        let action = match gesture {
+            Gesture::Click => config.click,
+            Gesture::DoubleClick => config.double_click,
+            Gesture::LongPress => config.long_press,
+            Gesture::VeryLongPress => config.very_long_press,
+            Gesture::Hold if config.long_press == ButtonAction::Dim => {
+                return dim(&zones, self.dim_up, config.dim_step);
+            }
+            Gesture::LongRelease if config.long_press == ButtonAction::Dim => {
+                self.dim_up = !self.dim_up;
+                return Ok(());
+            }
+            Gesture::Hold | Gesture::LongRelease => return Ok(()),
+        };
+        info!("Button {:?}: {}", gesture, action.name());
+        return match action {
+            ButtonAction::None => Ok(()),
+            ButtonAction::Toggle => zones::toggle_power(&zones),
+            ButtonAction::Scene => self.recall_next_scene(&zones, config.transition_ms),
+            ButtonAction::Dim => self.start_dimming(&zones),
+            ButtonAction::Provision => {
+                provisioning::request(&self.storage)?;
+                restart();
+                Ok(())
+            }
+            ButtonAction::Restart => {
+                restart();
+                Ok(())
+            }
+            ButtonAction::Link => self.hue.press_link(),
+        };
+    }
+
+    fn recall_next_scene(
+        &mut self,
+        zones: &[&Arc<Zone>],
+        transition_ms: u32,
+    ) -> Result<(), &'static str> {
+        let scenes = self
+            .scenes
+            .lock()
+            .map_err(|_| "could not get scenes lock")?;
+        let list = scenes.list();
+        if list.is_empty() {
+            return Err("no scenes saved");
+        }
+        let scene = &list[self.next_scene % list.len()];
+        self.next_scene = (self.next_scene + 1) % list.len();
+        info!("Recalling scene {:?}", scene.name);
+        for zone in zones {
+            let duration = Duration::from_millis(transition_ms.into());
+            transition::start(&zone.rgba, &zone.transition, scene.color, duration)?;
+            zones::set_power(zone, true)?;
+        }
+        return Ok(());
+    }
+
+    /// turns off zones on and picks the dim direction, if the brightness is at a limit
+    fn start_dimming(&mut self, zones: &[&Arc<Zone>]) -> Result<(), &'static str> {
+        if !zones::any_on(zones)? {
+            for zone in zones {
+                zones::set_power(zone, true)?;
+            }
+            self.dim_up = true;
+            return Ok(());
+        }
+        if let Some(zone) = zones.first() {
+            let brightness = zone.rgba.read().map_err(|_| "could not get read lock")?.a;
+            if brightness == u8::MAX {
+                self.dim_up = false;
+            } else if brightness <= MIN_DIM_LEVEL {
+                self.dim_up = true;
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn dim(zones: &[&Arc<Zone>], up: bool, step: u8) -> Result<(), &'static str> {
+    for zone in zones {
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let brightness = if up {
+            color.a.saturating_add(step)
+        } else {
+            color.a.saturating_sub(step).max(MIN_DIM_LEVEL)
+        };
+        if brightness != color.a {
+            let color = RGBA8 {
+                a: brightness,
+                ..color
+            };
+            transition::start(&zone.rgba, &zone.transition, color, Duration::ZERO)?;
+        }
+    }
+    return Ok(());
+}
+
+fn restart() {
+    warn!("Restarting by button");
+    unsafe { esp_idf_sys::esp_restart() };
+}
+
+/// Polls the button on `gpio` and executes the mapped actions in a background thread
+pub fn spawn(
+    gpio: u8,
+    button: Arc<Mutex<Button>>,
+    zones: Arc<Vec<Arc<Zone>>>,
+    scenes: Arc<Mutex<Scenes>>,
+    storage: Arc<Storage>,
+    hue: Arc<Hue>,
+) {
+    let mut input = match PinDriver::input(pins::input_pin(gpio)) {
+        Ok(val) => val,
+        Err(e) => {
+            error!(
+                "Could not set up the button on gpio {}! Error: {:?}",
+                gpio, e
+            );
+            return;
+        }
+    };
+    if let Err(e) = input.set_pull(Pull::Up) {
+        error!("Could not enable the button pull-up! Error: {:?}", e);
+        return;
+    }
+    info!("Listening to the button on gpio {}", gpio);
+
+    let mut dispatcher = Dispatcher {
+        zones,
+        scenes,
+        storage,
+        hue,
+        next_scene: 0,
+        dim_up: true,
+    };
+    thread::spawn(move || {
+        let mut detector = GestureDetector::new(Instant::now());
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            // the button pulls the pin to ground
+            let gesture = match detector.update(input.is_low(), Instant::now()) {
+                Some(val) => val,
+                None => continue,
+            };
+            let config = match button.lock() {
+                Ok(button) => button.config().clone(),
+                Err(_) => continue,
+            };
+            if let Err(e) = dispatcher.handle(gesture, &config) {
+                warn!("Could not handle button {:?}: {}", gesture, e);
+            }
+        }
+    });
+}
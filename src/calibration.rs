@@ -0,0 +1,152 @@
+//! White point calibration for color temperature control
+//!
+//! LEDs from different sources have different color points, so the same r,g,b values do not
+//! look the same on every stripe. The calibration consists of
+//! - the measured maximum duty per channel, at which the stripe shows a neutral white
+//!   (white balance), applied to the black body approximation
+//! - an optional table of measured r,g,b values per color temperature, which replaces the
+//!   white balanced black body approximation when set
+//! - the measured color of the white LEDs and the strategy to derive the white channels of
+//!   RGBW/ RGBWW stripes from r,g,b
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rgb_led::{
+    kelvin_to_rgb, to_channels, ChannelLayout, WhiteChannels, WhiteStrategy, RGB8,
+};
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "calibration";
+pub const MAX_TABLE_POINTS: usize = 16;
+pub const MIN_KELVIN: u16 = 1000;
+pub const MAX_KELVIN: u16 = 40000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationData {
+    /// r, g, b maximum duty for a neutral white
+    pub channel_max: [u8; 3],
+    /// measured colors per color temperature, sorted by color temperature
+    pub table: Vec<(u16, RGB8)>,
+    /// r,g,b color matching the white LEDs at full duty
+    pub white_point: RGB8,
+    pub white_strategy: WhiteStrategy,
+}
+
+impl Default for CalibrationData {
+    fn default() -> Self {
+        return CalibrationData {
+            channel_max: [255, 255, 255],
+            table: Vec::new(),
+            white_point: RGB8::new(255, 255, 255),
+            white_strategy: WhiteStrategy::Max,
+        };
+    }
+}
+
+pub struct Calibration {
+    data: CalibrationData,
+    storage: Arc<Storage>,
+}
+
+impl Calibration {
+    /// loads the previously saved calibration from the storage
+    pub fn load(storage: Arc<Storage>) -> Calibration {
+        let data = storage.load(STORAGE_KEY).unwrap_or_default();
+        return Calibration { data, storage };
+    }
+
+    pub fn data(&self) -> &CalibrationData {
+        return &self.data;
+    }
+
+    pub fn set_channel_max(&mut self, channel_max: [u8; 3]) -> Result<(), &'static str> {
+        self.data.channel_max = channel_max;
+        return self.persist();
+    }
+
+    /// adds or replaces the measured color for a color temperature
+    pub fn set_point(&mut self, kelvin: u16, color: RGB8) -> Result<(), &'static str> {
+        if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+            return Err("color temperature out of range");
+        }
+        match self.data.table.binary_search_by_key(&kelvin, |(k, _)| *k) {
+            Ok(idx) => self.data.table[idx].1 = color,
+            Err(idx) => {
+                if self.data.table.len() >= MAX_TABLE_POINTS {
+                    return Err("maximum number of calibration points reached");
+                }
+                self.data.table.insert(idx, (kelvin, color));
+            }
+        }
+        return self.persist();
+    }
+
+    pub fn set_white_point(&mut self, white_point: RGB8) -> Result<(), &'static str> {
+        self.data.white_point = white_point;
+        return self.persist();
+    }
+
+    pub fn set_white_strategy(&mut self, strategy: WhiteStrategy) -> Result<(), &'static str> {
+        self.data.white_strategy = strategy;
+        return self.persist();
+    }
+
+    /// converts the output color into the channel values of the layout, see `rgb_led::to_channels`
+    pub fn to_channels(&self, rgb: RGB8, white: WhiteChannels, layout: ChannelLayout) -> [u8; 5] {
+        return to_channels(
+            rgb,
+            white,
+            layout,
+            self.data.white_strategy,
+            self.data.white_point,
+        );
+    }
+
+    pub fn clear_table(&mut self) -> Result<(), &'static str> {
+        self.data.table.clear();
+        return self.persist();
+    }
+
+    /// converts a color temperature into calibrated r,g,b values for this stripe
+    pub fn ct_to_rgb(&self, kelvin: u16) -> RGB8 {
+        let kelvin = kelvin.clamp(MIN_KELVIN, MAX_KELVIN);
+        if self.data.table.is_empty() {
+            // no measurements, use the white balanced black body approximation
+            let color = kelvin_to_rgb(kelvin);
+            let scale = |value: u8, max: u8| -> u8 { (value as u16 * max as u16 / 255) as u8 };
+            return RGB8::new(
+                scale(color.r, self.data.channel_max[0]),
+                scale(color.g, self.data.channel_max[1]),
+                scale(color.b, self.data.channel_max[2]),
+            );
+        }
+
+        return match self.data.table.binary_search_by_key(&kelvin, |(k, _)| *k) {
+            Ok(idx) => self.data.table[idx].1,
+            // outside of the table, use the nearest point
+            Err(0) => self.data.table[0].1,
+            Err(idx) if idx == self.data.table.len() => self.data.table[idx - 1].1,
+            // interpolate between the surrounding points
+            Err(idx) => {
+                let (lower_k, lower) = self.data.table[idx - 1];
+                let (upper_k, upper) = self.data.table[idx];
+                let fraction = (kelvin - lower_k) as f32 / (upper_k - lower_k) as f32;
+                let lerp = |from: u8, to: u8| -> u8 {
+                    (from as f32 + (to as f32 - from as f32) * fraction).round() as u8
+                };
+                RGB8::new(
+                    lerp(lower.r, upper.r),
+                    lerp(lower.g, upper.g),
+                    lerp(lower.b, upper.b),
+                )
+            }
+        };
+    }
+
+    fn persist(&self) -> Result<(), &'static str> {
+        return self.storage.store(STORAGE_KEY, &self.data);
+    }
+}
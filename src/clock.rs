@@ -0,0 +1,157 @@
+//! Wall-clock time via SNTP including timezone/ DST handling
+//!
+//! The timezone is given as POSIX TZ string (e.g. `CET-1CEST,M3.5.0,M10.5.0/3`), so the libc
+//! takes care of daylight saving time. Location data is used for sunrise/ sunset calculation.
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
+use esp_idf_sys::{localtime_r, time_t, tm, tzset, EspError};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "time";
+
+/// timestamps before this year are treated as "not synchronized yet"
+const MIN_VALID_YEAR: i32 = 2023;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeConfig {
+    pub ntp_server: String,
+    pub timezone: String,
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+/// Broken-down local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    /// 1 - 12
+    pub month: u8,
+    /// 1 - 31
+    pub day: u8,
+    /// 0 (Sunday) - 6 (Saturday)
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// offset to UTC including DST
+    pub utc_offset_minutes: i32,
+}
+
+impl LocalTime {
+    pub fn minute_of_day(&self) -> u16 {
+        return self.hour as u16 * 60 + self.minute as u16;
+    }
+}
+
+pub struct Clock {
+    config: Mutex<TimeConfig>,
+    sntp: Mutex<Option<EspSntp>>,
+    storage: Arc<Storage>,
+}
+
+impl Clock {
+    /// loads the persisted time config (falling back to `default_config`) and starts SNTP
+    pub fn new(default_config: TimeConfig, storage: Arc<Storage>) -> Result<Clock, EspError> {
+        let config = storage.load(STORAGE_KEY).unwrap_or(default_config);
+        let clock = Clock {
+            config: Mutex::new(config.clone()),
+            sntp: Mutex::new(None),
+            storage,
+        };
+        clock.apply(&config)?;
+        return Ok(clock);
+    }
+
+    pub fn config(&self) -> Option<TimeConfig> {
+        return self.config.lock().ok().map(|config| config.clone());
+    }
+
+    /// applies and persists a new time config, SNTP is restarted with the new server
+    pub fn set_config(&self, new_config: TimeConfig) -> Result<(), &'static str> {
+        let mut config = self
+            .config
+            .lock()
+            .map_err(|_| "could not get config lock")?;
+        self.apply(&new_config)
+            .map_err(|_| "could not start SNTP")?;
+        self.storage.store(STORAGE_KEY, &new_config)?;
+        *config = new_config;
+        return Ok(());
+    }
+
+    fn apply(&self, config: &TimeConfig) -> Result<(), EspError> {
+        std::env::set_var("TZ", &config.timezone);
+        unsafe { tzset() };
+
+        let mut sntp = match self.sntp.lock() {
+            Ok(val) => val,
+            Err(_) => {
+                warn!("could not get SNTP lock!");
+                return Ok(());
+            }
+        };
+        // only one SNTP instance can exist at a time, so the old one has to be stopped first
+        *sntp = None;
+        let mut sntp_conf = SntpConf::default();
+        sntp_conf.servers[0] = &config.ntp_server;
+        *sntp = Some(EspSntp::new(&sntp_conf)?);
+        info!(
+            "Synchronizing time with {} (timezone {})",
+            config.ntp_server, config.timezone
+        );
+        return Ok(());
+    }
+
+    /// returns the current local time, None if the time was not synchronized yet
+    pub fn now(&self) -> Option<LocalTime> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        return local_time(timestamp as time_t);
+    }
+}
+
+fn local_time(timestamp: time_t) -> Option<LocalTime> {
+    let mut local: tm = Default::default();
+    if unsafe { localtime_r(&timestamp, &mut local) }.is_null() {
+        return None;
+    }
+
+    let year = local.tm_year + 1900;
+    if year < MIN_VALID_YEAR {
+        return None;
+    }
+
+    // the difference between the local time interpreted as UTC and the real timestamp
+    let local_seconds = days_from_civil(year, local.tm_mon + 1, local.tm_mday) * 86400
+        + local.tm_hour as i64 * 3600
+        + local.tm_min as i64 * 60
+        + local.tm_sec as i64;
+    let utc_offset_minutes = ((local_seconds - timestamp as i64) / 60) as i32;
+
+    return Some(LocalTime {
+        year,
+        month: (local.tm_mon + 1) as u8,
+        day: local.tm_mday as u8,
+        weekday: local.tm_wday as u8,
+        hour: local.tm_hour as u8,
+        minute: local.tm_min as u8,
+        second: local.tm_sec as u8,
+        utc_offset_minutes,
+    });
+}
+
+/// days since 1970-01-01 for the given date of the proleptic gregorian calendar
+fn days_from_civil(year: i32, month: i32, day: i32) -> i64 {
+    let year = (if month <= 2 { year - 1 } else { year }) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
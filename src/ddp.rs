@@ -0,0 +1,67 @@
+//! DDP (Distributed Display Protocol) realtime receiver
+//!
+//! xLights and other sequencers stream pixel data as DDP packets, every zone is one pixel, see
+//! `realtime`. The byte offset of a packet gives the position of its pixels, so a frame can be
+//! split into several packets. RGB and RGBW data (the white value is ignored) with 8 bit per
+//! channel is accepted, packets to other destinations than the display (e.g. config or status
+//! queries) are ignored.
+
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::realtime::{self, Realtime};
+use crate::rgb_led::RGB8;
+
+pub const DDP_PORT: u16 = 4048;
+const HEADER_LEN: usize = 10;
+/// a time code of 4 bytes follows the header
+const FLAG_TIMECODE: u8 = 0x10;
+const VERSION_MASK: u8 = 0xc0;
+const VERSION_1: u8 = 0x40;
+/// undefined data type, senders use it for RGB
+const TYPE_UNDEFINED: u8 = 0x00;
+const TYPE_RGB8: u8 = 0x0b;
+const TYPE_RGBW8: u8 = 0x1b;
+/// the default output device and all devices
+const DESTINATIONS: [u8; 3] = [0, 1, 255];
+
+/// parses a data packet into the first pixel and the colors, None for other packets
+fn parse_packet(packet: &[u8]) -> Option<(usize, Vec<RGB8>)> {
+    let header = packet.get(..HEADER_LEN)?;
+    let (flags, data_type, destination) = (header[0], header[2], header[3]);
+    if flags & VERSION_MASK != VERSION_1 || !DESTINATIONS.contains(&destination) {
+        return None;
+    }
+    let channels = match data_type {
+        TYPE_UNDEFINED | TYPE_RGB8 => 3,
+        TYPE_RGBW8 => 4,
+        _ => return None,
+    };
+    let offset = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let len = usize::from(u16::from_be_bytes([header[8], header[9]]));
+    let data_start = if flags & FLAG_TIMECODE != 0 {
+        HEADER_LEN + 4
+    } else {
+        HEADER_LEN
+    };
+    let data = packet.get(data_start..data_start + len)?;
+    let colors = data
+        .chunks_exact(channels)
+        .map(|chunk| RGB8::new(chunk[0], chunk[1], chunk[2]))
+        .collect();
+    return Some((offset / channels, colors));
+}
+
+/// Receives DDP packets in a background thread
+pub fn spawn(realtime: Arc<Realtime>) {
+    realtime::listen("DDP", DDP_PORT, move |packet| {
+        let (start, colors) = match parse_packet(packet) {
+            Some(val) => val,
+            None => return,
+        };
+        if let Err(e) = realtime.show("ddp", start, &colors, Some(realtime.timeout())) {
+            warn!("Could not show DDP frame: {}", e);
+        }
+    });
+}
@@ -0,0 +1,246 @@
+//! Thermal derating policy
+//!
+//! Maps a temperature reading to the maximum output level: full output below `start_c`,
+//! linearly reduced down to `min_level` until `critical_c` and cut off at `critical_c`. After a
+//! cut off, the output stays off until the temperature dropped `hysteresis_c` below `critical_c`.
+//! The policy has no hardware dependencies, readings are fed in by the sensor thread.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeratingConfig {
+    /// temperature in °C above which the output is reduced
+    pub start_c: f32,
+    /// temperature in °C at which the output is cut off
+    pub critical_c: f32,
+    /// maximum output level (0.0 - 1.0) right below the critical temperature
+    pub min_level: f32,
+    /// cooling in °C below the critical temperature needed to leave the cut off
+    pub hysteresis_c: f32,
+}
+
+impl Default for DeratingConfig {
+    fn default() -> Self {
+        return DeratingConfig {
+            start_c: 60.0,
+            critical_c: 80.0,
+            min_level: 0.25,
+            hysteresis_c: 5.0,
+        };
+    }
+}
+
+impl DeratingConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let values = [
+            self.start_c,
+            self.critical_c,
+            self.min_level,
+            self.hysteresis_c,
+        ];
+        if values.iter().any(|val| !val.is_finite()) {
+            return Err("derating values must be numbers");
+        }
+        if self.start_c >= self.critical_c {
+            return Err("start temperature must be below the critical temperature");
+        }
+        if !(0.0..=1.0).contains(&self.min_level) {
+            return Err("minimum level out of range");
+        }
+        if self.hysteresis_c < 0.0 {
+            return Err("hysteresis must not be negative");
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeratingStatus {
+    Normal,
+    Derating,
+    Critical,
+}
+
+impl DeratingStatus {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            DeratingStatus::Normal => "normal",
+            DeratingStatus::Derating => "derating",
+            DeratingStatus::Critical => "critical",
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Derating {
+    config: DeratingConfig,
+    status: DeratingStatus,
+    level: f32,
+}
+
+impl Derating {
+    pub fn new(config: DeratingConfig) -> Derating {
+        return Derating {
+            config,
+            status: DeratingStatus::Normal,
+            level: 1.0,
+        };
+    }
+
+    pub fn config(&self) -> &DeratingConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: DeratingConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.config = config;
+        return Ok(());
+    }
+
+    pub fn status(&self) -> DeratingStatus {
+        return self.status;
+    }
+
+    /// maximum output level from 0.0 (cut off) to 1.0 (full output)
+    pub fn level(&self) -> f32 {
+        return self.level;
+    }
+
+    /// updates the status with a new reading, returns the new maximum output level
+    pub fn update(&mut self, temperature_c: f32) -> f32 {
+        let config = &self.config;
+        let cooled_down = temperature_c <= config.critical_c - config.hysteresis_c;
+        (self.status, self.level) = if temperature_c >= config.critical_c
+            || (self.status == DeratingStatus::Critical && !cooled_down)
+        {
+            (DeratingStatus::Critical, 0.0)
+        } else if temperature_c > config.start_c {
+            let progress = (temperature_c - config.start_c) / (config.critical_c - config.start_c);
+            let level = 1.0 - (1.0 - config.min_level) * progress;
+            (DeratingStatus::Derating, level)
+        } else {
+            (DeratingStatus::Normal, 1.0)
+        };
+        return self.level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_level(expected: f32, actual: f32) {
+        assert!(
+            (expected - actual).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn full_output_below_start() {
+        let mut derating = Derating::new(DeratingConfig::default());
+        for temperature in [-20.0, 25.0, 59.9, 60.0] {
+            assert_eq!(derating.update(temperature), 1.0);
+            assert_eq!(derating.status(), DeratingStatus::Normal);
+        }
+    }
+
+    #[test]
+    fn linear_level_between_start_and_critical() {
+        // 60 °C to 80 °C, down to 0.25
+        let mut derating = Derating::new(DeratingConfig::default());
+        assert_level(0.8125, derating.update(65.0));
+        assert_eq!(derating.status(), DeratingStatus::Derating);
+        assert_level(0.625, derating.update(70.0));
+        assert_level(0.4375, derating.update(75.0));
+        assert_level(0.25, derating.update(79.9999));
+        // falling again follows the same line without hysteresis
+        assert_level(0.625, derating.update(70.0));
+        assert_eq!(derating.update(55.0), 1.0);
+        assert_eq!(derating.status(), DeratingStatus::Normal);
+    }
+
+    #[test]
+    fn cut_at_critical_until_cooled_down() {
+        let mut derating = Derating::new(DeratingConfig::default());
+        let readings = [
+            (50.0, DeratingStatus::Normal),
+            (70.0, DeratingStatus::Derating),
+            (80.0, DeratingStatus::Critical),
+            (90.0, DeratingStatus::Critical),
+            // within the hysteresis of 5 °C the output stays cut off
+            (79.0, DeratingStatus::Critical),
+            (75.1, DeratingStatus::Critical),
+            (75.0, DeratingStatus::Derating),
+            (60.0, DeratingStatus::Normal),
+        ];
+        for (temperature, status) in readings {
+            let level = derating.update(temperature);
+            assert_eq!(derating.status(), status, "at {} °C", temperature);
+            assert_eq!(level == 0.0, status == DeratingStatus::Critical);
+            assert_eq!(derating.level(), level);
+        }
+    }
+
+    #[test]
+    fn reaching_critical_again_cuts_immediately() {
+        let mut derating = Derating::new(DeratingConfig::default());
+        derating.update(85.0);
+        derating.update(70.0);
+        assert_eq!(derating.status(), DeratingStatus::Derating);
+        assert_eq!(derating.update(80.0), 0.0);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let valid = DeratingConfig::default();
+        assert_eq!(valid.validate(), Ok(()));
+        let invalid = [
+            DeratingConfig {
+                start_c: 80.0,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                start_c: 90.0,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                min_level: 1.5,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                min_level: -0.1,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                hysteresis_c: -1.0,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                critical_c: f32::NAN,
+                ..valid.clone()
+            },
+            DeratingConfig {
+                start_c: f32::NEG_INFINITY,
+                ..valid.clone()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn set_config_keeps_valid_config() {
+        let mut derating = Derating::new(DeratingConfig::default());
+        let invalid = DeratingConfig {
+            min_level: 2.0,
+            ..DeratingConfig::default()
+        };
+        assert!(derating.set_config(invalid).is_err());
+        assert_eq!(derating.config(), &DeratingConfig::default());
+    }
+}
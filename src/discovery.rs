@@ -0,0 +1,62 @@
+//! Announces the server in the local network via mDNS and DNS-SD
+//!
+//! Clients like StripeBuddy can then reach the device as `<hostname>.local` or browse for the
+//! advertised services instead of having to know the (DHCP assigned) IP address.
+
+use esp_idf_svc::mdns::EspMdns;
+use esp_idf_sys::EspError;
+use log::info;
+
+use crate::rgb_led::ChannelLayout;
+
+/// Version of the HTTP/ UDP API, increased whenever a breaking change is made
+pub const API_VERSION: &str = "1";
+
+/// Type of LED output driven by this firmware, announced in the TXT records
+pub fn led_type(layout: ChannelLayout) -> &'static str {
+    return match layout {
+        ChannelLayout::Rgb => "pwm-rgb",
+        ChannelLayout::Rgbw => "pwm-rgbw",
+        ChannelLayout::Rgbww => "pwm-rgbww",
+    };
+}
+
+/// A single DNS-SD service record, e.g. `_http._tcp` on port 80
+pub struct Service {
+    pub service_type: &'static str,
+    pub proto: &'static str,
+    pub port: u16,
+}
+
+/// Starts the mDNS responder with the given hostname and registers all given services with the
+/// `led` type in their TXT records.
+/// The returned `EspMdns` instance has to be kept alive, dropping it stops the responder.
+pub fn advertise(
+    hostname: &str,
+    led_type: &str,
+    services: &[Service],
+) -> Result<EspMdns, EspError> {
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(hostname)?;
+    mdns.set_instance_name(hostname)?;
+
+    let firmware_version = env!("CARGO_PKG_VERSION");
+    let txt = [
+        ("fw", firmware_version),
+        ("led", led_type),
+        ("api", API_VERSION),
+    ];
+
+    for service in services {
+        mdns.add_service(
+            Some(hostname),
+            service.service_type,
+            service.proto,
+            service.port,
+            &txt,
+        )?;
+    }
+
+    info!("Advertising as {}.local", hostname);
+    return Ok(mdns);
+}
@@ -0,0 +1,437 @@
+//! Local dimming with a rotary encoder
+//!
+//! The ESP32-C3 has no pulse counter (PCNT) peripheral, so the quadrature signal is decoded by
+//! a GPIO interrupt on every edge of both encoder pins. The interrupt counts quarter steps, a
+//! background thread collects them every `POLL_INTERVAL` and turns full detents into brightness,
+//! hue or saturation steps, depending on the current mode. Fast turns are accelerated.
+//! The optional push switch uses the gesture detection of the push button.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ::core::ffi::c_void;
+use esp_idf_hal::gpio::{AnyInputPin, Input, PinDriver, Pull};
+use esp_idf_sys::{
+    esp, gpio_get_level, gpio_install_isr_service, gpio_int_type_t_GPIO_INTR_ANYEDGE,
+    gpio_intr_enable, gpio_isr_handler_add, gpio_set_intr_type, EspError, ESP_ERR_INVALID_STATE,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::button::{Gesture, GestureDetector};
+use crate::pins;
+use crate::rgb_led::{apply_color_params, RGBA8};
+use crate::storage::Storage;
+use crate::transition;
+use crate::zones::{self, Zone, GROUP_TARGET};
+
+const STORAGE_KEY: &str = "encoder";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// step multiplier by the time per detent, the first matching entry applies
+const ACCELERATION: [(Duration, i32); 2] = [
+    (Duration::from_millis(30), 4),
+    (Duration::from_millis(80), 2),
+];
+/// turning left stops at this brightness, so the encoder never turns the stripe off
+const MIN_LEVEL: u8 = 1;
+
+/// direction of a state change, indexed by the previous and the current A/ B state. Changes of
+/// both pins at once (missed edges, bouncing) are not counted.
+const TRANSITIONS: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+
+/// GPIOs of the A and B pin, read by the interrupt
+static GPIOS: [AtomicU8; 2] = [AtomicU8::new(0), AtomicU8::new(0)];
+/// last state of the A (bit 1) and B (bit 0) pin
+static STATE: AtomicU8 = AtomicU8::new(0);
+/// quarter steps counted by the interrupt, taken by the polling thread
+static QUARTER_STEPS: AtomicI32 = AtomicI32::new(0);
+
+unsafe fn read_state() -> u8 {
+    let a = gpio_get_level(GPIOS[0].load(Ordering::Relaxed).into()) as u8;
+    let b = gpio_get_level(GPIOS[1].load(Ordering::Relaxed).into()) as u8;
+    return (a & 1) << 1 | (b & 1);
+}
+
+unsafe extern "C" fn edge(_arg: *mut c_void) {
+    let state = read_state();
+    let last = STATE.swap(state, Ordering::Relaxed);
+    let step = TRANSITIONS[(last << 2 | state) as usize];
+    if step != 0 {
+        QUARTER_STEPS.fetch_add(step.into(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderMode {
+    Brightness,
+    Hue,
+    Saturation,
+}
+
+impl EncoderMode {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            EncoderMode::Brightness => "brightness",
+            EncoderMode::Hue => "hue",
+            EncoderMode::Saturation => "saturation",
+        };
+    }
+
+    fn next(&self) -> EncoderMode {
+        return match self {
+            EncoderMode::Brightness => EncoderMode::Hue,
+            EncoderMode::Hue => EncoderMode::Saturation,
+            EncoderMode::Saturation => EncoderMode::Brightness,
+        };
+    }
+}
+
+impl FromStr for EncoderMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "brightness" => Ok(EncoderMode::Brightness),
+            "hue" => Ok(EncoderMode::Hue),
+            "saturation" => Ok(EncoderMode::Saturation),
+            _ => Err("unknown encoder mode"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushAction {
+    None,
+    Toggle,
+    Mode,
+}
+
+impl PushAction {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            PushAction::None => "none",
+            PushAction::Toggle => "toggle",
+            PushAction::Mode => "mode",
+        };
+    }
+}
+
+impl FromStr for PushAction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "none" => Ok(PushAction::None),
+            "toggle" => Ok(PushAction::Toggle),
+            "mode" => Ok(PushAction::Mode),
+            _ => Err("unknown push action"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderConfig {
+    pub click: PushAction,
+    pub double_click: PushAction,
+    pub long_press: PushAction,
+    /// addressed zones (zone id, zone name or `all`)
+    pub zone: String,
+    /// brightness change per detent
+    pub brightness_step: u8,
+    /// hue change per detent in degrees
+    pub hue_step: u16,
+    /// saturation change per detent in percent
+    pub saturation_step: u8,
+    /// quarter steps per detent, 4 for most encoders (e.g. EC11)
+    pub steps_per_detent: u8,
+    pub acceleration: bool,
+    /// swaps the direction, instead of swapping the A and B pin
+    pub reverse: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        return EncoderConfig {
+            click: PushAction::Toggle,
+            double_click: PushAction::Mode,
+            long_press: PushAction::None,
+            zone: GROUP_TARGET.to_string(),
+            brightness_step: 8,
+            hue_step: 10,
+            saturation_step: 5,
+            steps_per_detent: 4,
+            acceleration: true,
+            reverse: false,
+        };
+    }
+}
+
+impl EncoderConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.brightness_step == 0 || self.hue_step == 0 || self.saturation_step == 0 {
+            return Err("encoder step must not be 0");
+        }
+        if self.hue_step > 180 || self.saturation_step > 100 {
+            return Err("encoder step out of range");
+        }
+        if ![1, 2, 4].contains(&self.steps_per_detent) {
+            return Err("steps per detent must be 1, 2 or 4");
+        }
+        return Ok(());
+    }
+}
+
+pub struct Encoder {
+    config: EncoderConfig,
+    // the mode is not stored, the encoder always starts dimming
+    mode: EncoderMode,
+    storage: Arc<Storage>,
+}
+
+impl Encoder {
+    /// loads the previously saved encoder config from the storage
+    pub fn load(storage: Arc<Storage>) -> Encoder {
+        let config: EncoderConfig = storage.load(STORAGE_KEY).unwrap_or_default();
+        let config = match config.validate() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!(
+                    "Stored encoder config is invalid, using defaults! Error: {}",
+                    e
+                );
+                EncoderConfig::default()
+            }
+        };
+        return Encoder {
+            config,
+            mode: EncoderMode::Brightness,
+            storage,
+        };
+    }
+
+    pub fn config(&self) -> &EncoderConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: EncoderConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.storage.store(STORAGE_KEY, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+
+    pub fn mode(&self) -> EncoderMode {
+        return self.mode;
+    }
+
+    pub fn set_mode(&mut self, mode: EncoderMode) {
+        self.mode = mode;
+    }
+}
+
+/// Turns quarter steps into detents, keeps the steps of incomplete detents
+struct DetentCounter {
+    remainder: i32,
+    last_detent: Instant,
+}
+
+impl DetentCounter {
+    /// returns the steps of the new detents, multiplied on fast turns
+    fn update(&mut self, quarter_steps: i32, config: &EncoderConfig, now: Instant) -> i32 {
+        let total = self.remainder + quarter_steps;
+        let per_detent = i32::from(config.steps_per_detent);
+        let detents = total / per_detent;
+        self.remainder = total % per_detent;
+        if detents == 0 {
+            return 0;
+        }
+        let per_step = now.saturating_duration_since(self.last_detent) / detents.unsigned_abs();
+        self.last_detent = now;
+        let factor = if config.acceleration {
+            ACCELERATION
+                .iter()
+                .find(|(interval, _)| per_step < *interval)
+                .map_or(1, |(_, factor)| *factor)
+        } else {
+            1
+        };
+        let steps = detents * factor;
+        return if config.reverse { -steps } else { steps };
+    }
+}
+
+fn rotate(
+    zones: &[&Arc<Zone>],
+    mode: EncoderMode,
+    steps: i32,
+    config: &EncoderConfig,
+) -> Result<(), &'static str> {
+    if !zones::any_on(zones)? {
+        // turning right switches the zones on, otherwise changes are not visible
+        if mode == EncoderMode::Brightness && steps > 0 {
+            for zone in zones {
+                zones::set_power(zone, true)?;
+            }
+        }
+        return Ok(());
+    }
+    for zone in zones {
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let mut target = color;
+        match mode {
+            EncoderMode::Brightness => {
+                let level = i32::from(color.a) + steps * i32::from(config.brightness_step);
+                target = RGBA8 {
+                    a: level.clamp(MIN_LEVEL.into(), u8::MAX.into()) as u8,
+                    ..color
+                };
+            }
+            EncoderMode::Hue => {
+                let step = format!("{:+}", steps * i32::from(config.hue_step));
+                apply_color_params(&mut target, &[("h", step)])?;
+            }
+            EncoderMode::Saturation => {
+                let step = format!("{:+}", steps * i32::from(config.saturation_step));
+                apply_color_params(&mut target, &[("s", step)])?;
+            }
+        }
+        if target != color {
+            transition::start(&zone.rgba, &zone.transition, target, Duration::ZERO)?;
+        }
+    }
+    return Ok(());
+}
+
+fn push(
+    zones: &[&Arc<Zone>],
+    gesture: Gesture,
+    encoder: &Mutex<Encoder>,
+    config: &EncoderConfig,
+) -> Result<(), &'static str> {
+    let action = match gesture {
+        Gesture::Click => config.click,
+        Gesture::DoubleClick => config.double_click,
+        Gesture::LongPress => config.long_press,
+        _ => return Ok(()),
+    };
+    return match action {
+        PushAction::None => Ok(()),
+        PushAction::Toggle => zones::toggle_power(zones),
+        PushAction::Mode => {
+            let mut encoder = encoder.lock().map_err(|_| "could not get encoder lock")?;
+            let mode = encoder.mode().next();
+            info!("Encoder mode: {}", mode.name());
+            encoder.set_mode(mode);
+            Ok(())
+        }
+    };
+}
+
+fn input(gpio: u8) -> Result<PinDriver<'static, AnyInputPin, Input>, EspError> {
+    let mut input = PinDriver::input(pins::input_pin(gpio))?;
+    input.set_pull(Pull::Up)?;
+    return Ok(input);
+}
+
+fn start_interrupts(pins: [u8; 2]) -> Result<(), EspError> {
+    for (gpio, pin) in GPIOS.iter().zip(pins) {
+        gpio.store(pin, Ordering::Relaxed);
+    }
+    STATE.store(unsafe { read_state() }, Ordering::Relaxed);
+    // the service may already be installed by other drivers
+    match esp!(unsafe { gpio_install_isr_service(0) }) {
+        Err(e) if e.code() != ESP_ERR_INVALID_STATE as i32 => return Err(e),
+        _ => {}
+    }
+    for pin in pins {
+        esp!(unsafe { gpio_set_intr_type(pin.into(), gpio_int_type_t_GPIO_INTR_ANYEDGE) })?;
+        esp!(unsafe { gpio_isr_handler_add(pin.into(), Some(edge), std::ptr::null_mut()) })?;
+        esp!(unsafe { gpio_intr_enable(pin.into()) })?;
+    }
+    return Ok(());
+}
+
+/// Counts the encoder steps on the `pins` A and B and polls the optional push `switch` in a
+/// background thread
+pub fn spawn(
+    pins: [u8; 2],
+    switch: Option<u8>,
+    encoder: Arc<Mutex<Encoder>>,
+    zones: Arc<Vec<Arc<Zone>>>,
+) {
+    let inputs = match pins.map(input) {
+        [Ok(a), Ok(b)] => (a, b),
+        [Err(e), _] | [_, Err(e)] => {
+            error!(
+                "Could not set up the encoder on gpio {:?}! Error: {:?}",
+                pins, e
+            );
+            return;
+        }
+    };
+    if let Err(e) = start_interrupts(pins) {
+        error!("Could not start the encoder interrupts! Error: {:?}", e);
+        return;
+    }
+    let switch = switch.and_then(|gpio| match input(gpio) {
+        Ok(val) => Some(val),
+        Err(e) => {
+            error!(
+                "Could not set up the encoder switch on gpio {}! Error: {:?}",
+                gpio, e
+            );
+            None
+        }
+    });
+    info!("Listening to the encoder on gpio {:?}", pins);
+
+    thread::spawn(move || {
+        // the pin drivers reset the pins when dropped
+        let _inputs = inputs;
+        let mut counter = DetentCounter {
+            remainder: 0,
+            last_detent: Instant::now(),
+        };
+        let mut detector = GestureDetector::new(Instant::now());
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let now = Instant::now();
+            let quarter_steps = QUARTER_STEPS.swap(0, Ordering::Relaxed);
+            // the switch pulls the pin to ground
+            let gesture = match &switch {
+                Some(switch) => detector.update(switch.is_low(), now),
+                None => None,
+            };
+            if quarter_steps == 0 && gesture.is_none() {
+                continue;
+            }
+            let (config, mode) = match encoder.lock() {
+                Ok(encoder) => (encoder.config().clone(), encoder.mode()),
+                Err(_) => continue,
+            };
+            let addressed = match zones::resolve(&zones, &config.zone) {
+                Some(val) => val,
+                None => {
+                    warn!("Unknown encoder zone {:?}", config.zone);
+                    continue;
+                }
+            };
+            let steps = counter.update(quarter_steps, &config, now);
+            if steps != 0 {
+                if let Err(e) = rotate(&addressed, mode, steps, &config) {
+                    warn!("Could not handle encoder rotation: {}", e);
+                }
+            }
+            if let Some(gesture) = gesture {
+                if let Err(e) = push(&addressed, gesture, &encoder, &config) {
+                    warn!("Could not handle encoder {:?}: {}", gesture, e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,446 @@
+//! Synchronisation of the controllers of a room over UDP multicast
+//!
+//! Controllers with the same sync group share the state of their main zone (power, color, white
+//! values and running fades). Every member sends its state every `HEARTBEAT_INTERVAL` and right
+//! after a local change, the state with the highest version wins, so changing any member moves
+//! the whole group. The member with the highest rank (forced leader, priority, then the lowest
+//! hostname) heard within `LEADER_TIMEOUT` is the leader, its clock is the shared clock of the
+//! group. Followers estimate the offset to the shared clock from the least delayed heartbeats,
+//! fades end at the same shared time on every member, so they stay in step despite latency.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::rgb_led::{WhiteChannels, RGBA8};
+use crate::storage::Storage;
+use crate::transition;
+use crate::zones::Zone;
+
+const STORAGE_KEY: &str = "sync";
+const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 82, 71);
+pub const SYNC_PORT: u16 = 4210;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// a member is gone after missing three heartbeats
+const LEADER_TIMEOUT: Duration = Duration::from_millis(3500);
+/// heartbeats of the leader used to estimate the clock offset
+const CLOCK_SAMPLES: usize = 8;
+const MAX_GROUP_LENGTH: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncRole {
+    /// leads, if no member with a higher rank is present
+    Auto,
+    /// always leads, unless another forced leader has a higher rank
+    Leader,
+    /// never leads
+    Follower,
+}
+
+impl SyncRole {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            SyncRole::Auto => "auto",
+            SyncRole::Leader => "leader",
+            SyncRole::Follower => "follower",
+        };
+    }
+}
+
+impl FromStr for SyncRole {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "auto" => Ok(SyncRole::Auto),
+            "leader" => Ok(SyncRole::Leader),
+            "follower" => Ok(SyncRole::Follower),
+            _ => Err("unknown sync role"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    /// name of the sync group, empty disables the synchronisation
+    pub group: String,
+    pub role: SyncRole,
+    /// members with a higher priority are preferred as leader
+    pub priority: u8,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        return SyncConfig {
+            group: String::new(),
+            role: SyncRole::Auto,
+            priority: 100,
+        };
+    }
+}
+
+impl SyncConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.group.len() > MAX_GROUP_LENGTH {
+            return Err("sync group name too long");
+        }
+        return Ok(());
+    }
+}
+
+/// Synchronised state of the main zone
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct State {
+    on: bool,
+    color: RGBA8,
+    white: WhiteChannels,
+    /// end of the running color transition on the shared clock in ms, 0 without transition
+    color_end: u64,
+    /// end of the running power fade on the shared clock in ms, 0 without fade
+    power_end: u64,
+}
+
+impl State {
+    /// true, if the shown state is the same, ignoring the fade times
+    fn same_target(&self, other: &State) -> bool {
+        return self.on == other.on && self.color == other.color && self.white == other.white;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    group: String,
+    node: String,
+    role: SyncRole,
+    priority: u8,
+    /// shared clock of the sender in ms
+    clock: u64,
+    version: u32,
+    state: State,
+}
+
+/// rank of a member for the leader election, None if it never leads
+type Rank = Option<(bool, u8, Reverse<String>)>;
+
+fn rank(role: SyncRole, priority: u8, node: &str) -> Rank {
+    if role == SyncRole::Follower {
+        return None;
+    }
+    return Some((
+        role == SyncRole::Leader,
+        priority,
+        Reverse(node.to_string()),
+    ));
+}
+
+struct Peer {
+    node: String,
+    rank: Rank,
+    last_seen: Instant,
+}
+
+pub struct GroupSync {
+    config: SyncConfig,
+    node: String,
+    storage: Arc<Storage>,
+    base: Instant,
+    // shared clock minus local clock in ms
+    offset_ms: i64,
+    clock_samples: VecDeque<i64>,
+    peers: Vec<Peer>,
+    leader: Option<String>,
+    // version and sender of the current state, the sender breaks ties
+    version: (u32, String),
+    // last sent or applied state, differences are local changes
+    synced: Option<State>,
+}
+
+impl GroupSync {
+    /// loads the previously saved sync config from the storage, `node` is the unique hostname
+    pub fn load(storage: Arc<Storage>, node: &str) -> GroupSync {
+        let config: SyncConfig = storage.load(STORAGE_KEY).unwrap_or_default();
+        let config = match config.validate() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!(
+                    "Stored sync config is invalid, using defaults! Error: {}",
+                    e
+                );
+                SyncConfig::default()
+            }
+        };
+        return GroupSync {
+            config,
+            node: node.to_string(),
+            storage,
+            base: Instant::now(),
+            offset_ms: 0,
+            clock_samples: VecDeque::new(),
+            peers: Vec::new(),
+            leader: None,
+            version: (0, node.to_string()),
+            synced: None,
+        };
+    }
+
+    pub fn config(&self) -> &SyncConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: SyncConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.storage.store(STORAGE_KEY, &config)?;
+        if config.group != self.config.group {
+            info!("Joining sync group {:?}", config.group);
+            self.peers.clear();
+            self.leader = None;
+            self.clock_samples.clear();
+        }
+        self.config = config;
+        return Ok(());
+    }
+
+    pub fn node(&self) -> &str {
+        return &self.node;
+    }
+
+    /// the current leader, None without group
+    pub fn leader(&self) -> Option<&str> {
+        return self.leader.as_deref();
+    }
+
+    /// hostnames of the other members heard within the leader timeout
+    pub fn peers(&self) -> Vec<&str> {
+        return self.peers.iter().map(|peer| peer.node.as_str()).collect();
+    }
+
+    pub fn version(&self) -> u32 {
+        return self.version.0;
+    }
+
+    /// the shared clock of the group in ms, the local clock without leader
+    pub fn clock_ms(&self) -> u64 {
+        let local = self.base.elapsed().as_millis() as i64;
+        return (local + self.offset_ms).max(0) as u64;
+    }
+
+    fn to_clock(&self, instant: Option<Instant>) -> u64 {
+        return match instant {
+            Some(instant) => {
+                self.clock_ms()
+                    + instant
+                        .saturating_duration_since(Instant::now())
+                        .as_millis() as u64
+            }
+            None => 0,
+        };
+    }
+
+    fn remaining(&self, clock_ms: u64) -> Duration {
+        return Duration::from_millis(clock_ms.saturating_sub(self.clock_ms()));
+    }
+
+    fn read_state(&self, zone: &Zone) -> Result<State, &'static str> {
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let white = *zone.white.read().map_err(|_| "could not get read lock")?;
+        let color_end = match *zone
+            .transition
+            .lock()
+            .map_err(|_| "could not get transition lock")?
+        {
+            Some(running) if running.target() == color && !running.is_finished() => {
+                Some(running.end())
+            }
+            _ => None,
+        };
+        let power = zone.power.lock().map_err(|_| "could not get power lock")?;
+        return Ok(State {
+            on: power.is_on(),
+            color,
+            white,
+            color_end: self.to_clock(color_end),
+            power_end: self.to_clock(power.fade_end()),
+        });
+    }
+
+    fn apply_state(&self, state: &State, zone: &Zone) -> Result<(), &'static str> {
+        *zone.white.write().map_err(|_| "could not get write lock")? = state.white;
+        transition::start(
+            &zone.rgba,
+            &zone.transition,
+            state.color,
+            self.remaining(state.color_end),
+        )?;
+        zone.power
+            .lock()
+            .map_err(|_| "could not get power lock")?
+            .set(state.on, Some(self.remaining(state.power_end)));
+        return Ok(());
+    }
+
+    fn receive(&mut self, msg: Message, zone: &Zone) -> Result<(), &'static str> {
+        if msg.group != self.config.group || msg.node == self.node {
+            return Ok(());
+        }
+        let received = self.base.elapsed().as_millis() as i64;
+        let rank = rank(msg.role, msg.priority, &msg.node);
+        match self.peers.iter_mut().find(|peer| peer.node == msg.node) {
+            Some(peer) => {
+                peer.rank = rank;
+                peer.last_seen = Instant::now();
+            }
+            None => {
+                info!("Sync member {} joined", msg.node);
+                self.peers.push(Peer {
+                    node: msg.node.clone(),
+                    rank,
+                    last_seen: Instant::now(),
+                });
+            }
+        }
+        self.elect();
+        if self.leader.as_deref() == Some(msg.node.as_str()) {
+            // the least delayed heartbeat gives the best estimate of the offset
+            self.clock_samples.push_back(msg.clock as i64 - received);
+            if self.clock_samples.len() > CLOCK_SAMPLES {
+                self.clock_samples.pop_front();
+            }
+            if let Some(offset) = self.clock_samples.iter().max() {
+                self.offset_ms = *offset;
+            }
+        }
+        if (msg.version, &msg.node) > (self.version.0, &self.version.1) {
+            self.apply_state(&msg.state, zone)?;
+            self.version = (msg.version, msg.node);
+            self.synced = Some(msg.state);
+        }
+        return Ok(());
+    }
+
+    /// picks the member with the highest rank as leader
+    fn elect(&mut self) {
+        self.peers
+            .retain(|peer| peer.last_seen.elapsed() < LEADER_TIMEOUT);
+        let own = (
+            rank(self.config.role, self.config.priority, &self.node),
+            &self.node,
+        );
+        let leader = self
+            .peers
+            .iter()
+            .map(|peer| (peer.rank.clone(), &peer.node))
+            .chain([own])
+            .filter(|(rank, _)| rank.is_some())
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, node)| node.clone());
+        if leader != self.leader {
+            info!("Sync leader is {:?}", leader);
+            // the new leader continues with the current shared clock
+            self.clock_samples.clear();
+            self.leader = leader;
+        }
+    }
+
+    /// returns the message to send, if the state changed locally or a heartbeat is due
+    fn update(&mut self, zone: &Zone, heartbeat: bool) -> Result<Option<Message>, &'static str> {
+        self.elect();
+        let state = self.read_state(zone)?;
+        let changed = match &self.synced {
+            Some(synced) => !synced.same_target(&state),
+            // the state after a restart must not replace the state of the group
+            None => {
+                self.synced = Some(state.clone());
+                false
+            }
+        };
+        if changed {
+            self.version = (self.version.0.wrapping_add(1), self.node.clone());
+            self.synced = Some(state.clone());
+        } else if !heartbeat {
+            return Ok(None);
+        }
+        return Ok(Some(Message {
+            group: self.config.group.clone(),
+            node: self.node.clone(),
+            role: self.config.role,
+            priority: self.config.priority,
+            clock: self.clock_ms(),
+            version: self.version.0,
+            state,
+        }));
+    }
+}
+
+fn open_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SYNC_PORT))?;
+    socket.join_multicast_v4(&MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(false)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    return Ok(socket);
+}
+
+/// Exchanges the state of the `zone` with the sync group in a background thread
+pub fn spawn(sync: Arc<Mutex<GroupSync>>, zone: Arc<Zone>) {
+    let socket = match open_socket() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("Could not open the sync socket! Error: {:?}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        let mut buf = [0_u8; 512];
+        let mut last_heartbeat: Option<Instant> = None;
+        loop {
+            // the read timeout paces the loop
+            let received = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => serde_json::from_slice::<Message>(&buf[..len]).ok(),
+                Err(_) => None,
+            };
+            let mut sync = match sync.lock() {
+                Ok(val) => val,
+                Err(_) => continue,
+            };
+            if sync.config.group.is_empty() {
+                continue;
+            }
+            if let Some(msg) = received {
+                if let Err(e) = sync.receive(msg, &zone) {
+                    warn!("Could not apply the sync state: {}", e);
+                }
+            }
+            let heartbeat = last_heartbeat.map_or(true, |val| val.elapsed() >= HEARTBEAT_INTERVAL);
+            let msg = match sync.update(&zone, heartbeat) {
+                Ok(Some(val)) => val,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Could not read the sync state: {}", e);
+                    continue;
+                }
+            };
+            drop(sync);
+            last_heartbeat = Some(Instant::now());
+            let sent = serde_json::to_vec(&msg)
+                .map_err(|_| "could not serialize")
+                .and_then(|data| {
+                    socket
+                        .send_to(&data, (MULTICAST_ADDR, SYNC_PORT))
+                        .map_err(|_| "could not send")
+                });
+            if let Err(e) = sent {
+                warn!("Could not send the sync state: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,655 @@
+//! Emulation of a Philips Hue bridge (API v1)
+//!
+//! Hue apps, voice assistants with local Hue support and home automation tools find the
+//! controller via SSDP and control every zone as an extended color light (zone 0 is light 1).
+//! Clients pair like with a real bridge: while the link button is pressed (the push button
+//! action `link` or `/hue?link=1`), `POST /api` creates a user name for the client, the user
+//! names are stored in the NVS. Only the lights and the config are emulated, the other
+//! resources are empty.
+
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_sys::{esp, esp_fill_random, esp_mac_type_t_ESP_MAC_WIFI_STA, esp_read_mac};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::calibration::Calibration;
+use crate::rgb_led::{hsv_to_rgb, rgb_to_hsv, RGB8, RGBA8};
+use crate::storage::Storage;
+use crate::transition;
+use crate::zones::Zone;
+
+const STORAGE_KEY: &str = "hue";
+/// time the link button stays pressed, like on a real bridge
+pub const LINK_WINDOW: Duration = Duration::from_secs(30);
+const MAX_USERS: usize = 16;
+const MAX_DEVICETYPE_LEN: usize = 40;
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// search targets answered, clients look for one of them
+const SSDP_TARGETS: [&str; 2] = ["upnp:rootdevice", "urn:schemas-upnp-org:device:basic:1"];
+const API_VERSION: &str = "1.16.0";
+const BRIDGE_SW_VERSION: &str = "1916080100";
+const BRIDGE_MODEL: &str = "BSB002";
+const LIGHT_MODEL: &str = "LCT015";
+/// Hue brightness and saturation range from 1 and 0 to 254
+const MAX_BRI: u8 = 254;
+const MAX_SAT: u8 = 254;
+const MIN_MIREDS: u16 = 153;
+const MAX_MIREDS: u16 = 500;
+/// the color temperature reported for lights not set by color temperature (2700 K)
+const DEFAULT_MIREDS: u16 = 366;
+/// Hue transition times are given in 100 ms steps, 400 ms if not given
+const TRANSITION_STEP: Duration = Duration::from_millis(100);
+const DEFAULT_TRANSITION: u16 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct User {
+    name: String,
+    devicetype: String,
+}
+
+/// how the color of a light was set last, reported as `colormode`
+#[derive(Debug, Clone, Copy)]
+enum ColorMode {
+    Hs,
+    Xy([f32; 2]),
+    Ct(u16),
+}
+
+impl ColorMode {
+    fn name(&self) -> &'static str {
+        return match self {
+            ColorMode::Hs => "hs",
+            ColorMode::Xy(_) => "xy",
+            ColorMode::Ct(_) => "ct",
+        };
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LightUpdate {
+    on: Option<bool>,
+    bri: Option<u8>,
+    hue: Option<u16>,
+    sat: Option<u8>,
+    xy: Option<[f32; 2]>,
+    ct: Option<u16>,
+    bri_inc: Option<i16>,
+    transitiontime: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    devicetype: String,
+}
+
+/// a Hue error response, Hue reports errors with status 200
+fn error(kind: u16, address: &str, description: &str) -> Value {
+    return json!([{
+        "error": { "type": kind, "address": address, "description": description }
+    }]);
+}
+
+fn success(address: String, value: Value) -> Value {
+    let mut result = Map::new();
+    result.insert(address, value);
+    return json!({ "success": result });
+}
+
+fn to_hue_bri(brightness: u8) -> u8 {
+    return ((u16::from(brightness) * u16::from(MAX_BRI) + 127) / 255).max(1) as u8;
+}
+
+fn from_hue_bri(bri: u8) -> u8 {
+    let bri = bri.clamp(1, MAX_BRI);
+    return ((u16::from(bri) * 255 + u16::from(MAX_BRI) / 2) / u16::from(MAX_BRI)) as u8;
+}
+
+fn gamma_compress(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        return 12.92 * value;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+fn gamma_expand(value: f32) -> f32 {
+    if value <= 0.04045 {
+        return value / 12.92;
+    }
+    return ((value + 0.055) / 1.055).powf(2.4);
+}
+
+/// Converts a CIE xy chromaticity into r,g,b values with full brightness (wide gamut matrix of
+/// the Hue developer documentation), colors outside of the gamut are clipped
+pub fn xy_to_rgb(xy: [f32; 2]) -> RGB8 {
+    let [x, y] = xy;
+    let y = y.max(0.0001);
+    let (big_x, big_y, big_z) = (x / y, 1.0, (1.0 - x - y) / y);
+    let linear = [
+        big_x * 1.656492 - big_y * 0.354851 - big_z * 0.255038,
+        -big_x * 0.707196 + big_y * 1.655397 + big_z * 0.036152,
+        big_x * 0.051713 - big_y * 0.121364 + big_z * 1.01153,
+    ]
+    .map(|value| value.max(0.0));
+    let max = linear.iter().copied().fold(0.0_f32, f32::max);
+    if max <= 0.0 {
+        return RGB8::new(0, 0, 0);
+    }
+    let [r, g, b] = linear.map(|value| (gamma_compress(value / max) * 255.0).round() as u8);
+    return RGB8::new(r, g, b);
+}
+
+/// Converts r,g,b values into a CIE xy chromaticity, black is reported as the D65 white point
+pub fn rgb_to_xy(rgb: RGB8) -> [f32; 2] {
+    let [r, g, b] = [rgb.r, rgb.g, rgb.b].map(|value| gamma_expand(f32::from(value) / 255.0));
+    let big_x = r * 0.664511 + g * 0.154324 + b * 0.162028;
+    let big_y = r * 0.283881 + g * 0.668433 + b * 0.047685;
+    let big_z = r * 0.000088 + g * 0.072310 + b * 0.986039;
+    let sum = big_x + big_y + big_z;
+    if sum <= 0.0 {
+        return [0.3127, 0.329];
+    }
+    let round = |value: f32| (value * 10000.0).round() / 10000.0;
+    return [round(big_x / sum), round(big_y / sum)];
+}
+
+pub struct Hue {
+    zones: Arc<Vec<Arc<Zone>>>,
+    calibration: Arc<Mutex<Calibration>>,
+    storage: Arc<Storage>,
+    hostname: &'static str,
+    users: Mutex<Vec<User>>,
+    link_until: Mutex<Option<Instant>>,
+    /// color mode per zone, in the order of `zones`
+    modes: Mutex<Vec<ColorMode>>,
+    /// mac address as lowercase hex without separators
+    mac: String,
+}
+
+impl Hue {
+    pub fn load(
+        zones: Arc<Vec<Arc<Zone>>>,
+        calibration: Arc<Mutex<Calibration>>,
+        storage: Arc<Storage>,
+        hostname: &'static str,
+    ) -> Hue {
+        let users: Vec<User> = storage.load(STORAGE_KEY).unwrap_or_default();
+        let mut mac = [0_u8; 6];
+        if let Err(e) =
+            esp!(unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_WIFI_STA) })
+        {
+            warn!("Could not read the mac address! Error: {:?}", e);
+        }
+        return Hue {
+            modes: Mutex::new(vec![ColorMode::Hs; zones.len()]),
+            zones,
+            calibration,
+            storage,
+            hostname,
+            users: Mutex::new(users),
+            link_until: Mutex::new(None),
+            mac: mac.iter().map(|val| format!("{:02x}", val)).collect(),
+        };
+    }
+
+    /// presses the link button, clients can pair for `LINK_WINDOW`
+    pub fn press_link(&self) -> Result<(), &'static str> {
+        let mut link_until = self
+            .link_until
+            .lock()
+            .map_err(|_| "could not get link lock")?;
+        info!(
+            "Hue link button pressed, pairing for {} s",
+            LINK_WINDOW.as_secs()
+        );
+        *link_until = Some(Instant::now() + LINK_WINDOW);
+        return Ok(());
+    }
+
+    /// remaining time of the pressed link button, None if not pressed
+    pub fn link_remaining(&self) -> Option<Duration> {
+        let link_until = self.link_until.lock().ok()?;
+        let remaining = link_until
+            .as_ref()?
+            .saturating_duration_since(Instant::now());
+        return Some(remaining).filter(|val| !val.is_zero());
+    }
+
+    pub fn user_count(&self) -> usize {
+        return self.users.lock().map_or(0, |users| users.len());
+    }
+
+    /// unpairs all clients
+    pub fn clear_users(&self) -> Result<(), &'static str> {
+        let mut users = self.users.lock().map_err(|_| "could not get users lock")?;
+        self.storage.store(STORAGE_KEY, &Vec::<User>::new())?;
+        users.clear();
+        return Ok(());
+    }
+
+    /// bridge id derived from the mac address, like a real bridge
+    pub fn bridge_id(&self) -> String {
+        return format!("{}FFFE{}", &self.mac[..6], &self.mac[6..]).to_uppercase();
+    }
+
+    fn uuid(&self) -> String {
+        return format!("2f402f80-da50-11e1-9b23-{}", self.mac);
+    }
+
+    fn mac_address(&self) -> String {
+        return self
+            .mac
+            .as_bytes()
+            .chunks(2)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect::<Vec<String>>()
+            .join(":");
+    }
+
+    fn authorized(&self, user: &str) -> bool {
+        return self
+            .users
+            .lock()
+            .map_or(false, |users| users.iter().any(|val| val.name == user));
+    }
+
+    /// handles `POST /api`, a new user is created while the link button is pressed
+    pub fn pair(&self, request: &PairRequest) -> Result<Value, &'static str> {
+        if self.link_remaining().is_none() {
+            return Ok(error(101, "", "link button not pressed"));
+        }
+        if request.devicetype.is_empty() || request.devicetype.len() > MAX_DEVICETYPE_LEN {
+            return Ok(error(
+                7,
+                "/devicetype",
+                "invalid value, devicetype, for parameter, devicetype",
+            ));
+        }
+        let mut random = [0_u8; 16];
+        unsafe { esp_fill_random(random.as_mut_ptr().cast(), random.len()) };
+        let name: String = random.iter().map(|val| format!("{:02x}", val)).collect();
+
+        let mut users = self.users.lock().map_err(|_| "could not get users lock")?;
+        let mut updated = users.clone();
+        // the oldest client is unpaired, like a real bridge does with its whitelist
+        if updated.len() >= MAX_USERS {
+            updated.remove(0);
+        }
+        updated.push(User {
+            name: name.clone(),
+            devicetype: request.devicetype.clone(),
+        });
+        self.storage.store(STORAGE_KEY, &updated)?;
+        *users = updated;
+        info!("Hue client {:?} paired", request.devicetype);
+        return Ok(json!([{ "success": { "username": name } }]));
+    }
+
+    /// handles `GET /api/<user>/...`, `path` are the segments after the user name
+    pub fn get(&self, user: &str, path: &[&str]) -> Result<Value, &'static str> {
+        // the short config is readable without pairing, clients use it to verify the bridge
+        if user == "config" && path.is_empty() {
+            return Ok(self.config(false));
+        }
+        if !self.authorized(user) {
+            return Ok(error(
+                1,
+                &format!("/{}", path.join("/")),
+                "unauthorized user",
+            ));
+        }
+        return match path {
+            [] => Ok(json!({
+                "lights": self.lights()?,
+                "groups": {},
+                "config": self.config(true),
+                "schedules": {},
+                "scenes": {},
+                "rules": {},
+                "sensors": {},
+                "resourcelinks": {},
+            })),
+            ["lights"] => self.lights(),
+            ["lights", id] => match self.zone_idx(id) {
+                Some(idx) => self.light(idx),
+                None => Ok(not_available(path)),
+            },
+            ["config"] => Ok(self.config(true)),
+            ["groups" | "schedules" | "scenes" | "rules" | "sensors" | "resourcelinks"] => {
+                Ok(json!({}))
+            }
+            _ => Ok(not_available(path)),
+        };
+    }
+
+    /// handles `PUT /api/<user>/lights/<id>/state`
+    pub fn put(&self, user: &str, path: &[&str], body: &[u8]) -> Result<Value, &'static str> {
+        if !self.authorized(user) {
+            return Ok(error(
+                1,
+                &format!("/{}", path.join("/")),
+                "unauthorized user",
+            ));
+        }
+        let idx = match path {
+            ["lights", id, "state"] => match self.zone_idx(id) {
+                Some(val) => val,
+                None => return Ok(not_available(path)),
+            },
+            _ => {
+                return Ok(error(
+                    3,
+                    &format!("/{}", path.join("/")),
+                    "method not available",
+                ))
+            }
+        };
+        let update = match serde_json::from_slice::<LightUpdate>(body) {
+            Ok(val) => val,
+            Err(_) => return Ok(error(2, "", "body contains invalid json")),
+        };
+        return self.update(idx, &update);
+    }
+
+    fn zone_idx(&self, id: &str) -> Option<usize> {
+        let idx = id.parse::<usize>().ok()?.checked_sub(1)?;
+        return Some(idx).filter(|idx| *idx < self.zones.len());
+    }
+
+    fn config(&self, full: bool) -> Value {
+        let mut config = json!({
+            "name": self.hostname,
+            "datastoreversion": "98",
+            "swversion": BRIDGE_SW_VERSION,
+            "apiversion": API_VERSION,
+            "mac": self.mac_address(),
+            "bridgeid": self.bridge_id(),
+            "factorynew": false,
+            "replacesbridgeid": null,
+            "modelid": BRIDGE_MODEL,
+            "starterkitid": "",
+        });
+        if full {
+            let mut whitelist = Map::new();
+            if let Ok(users) = self.users.lock() {
+                for user in users.iter() {
+                    whitelist.insert(user.name.clone(), json!({ "name": user.devicetype }));
+                }
+            }
+            config["linkbutton"] = json!(self.link_remaining().is_some());
+            config["whitelist"] = Value::Object(whitelist);
+        }
+        return config;
+    }
+
+    fn lights(&self) -> Result<Value, &'static str> {
+        let mut lights = Map::new();
+        for idx in 0..self.zones.len() {
+            lights.insert((idx + 1).to_string(), self.light(idx)?);
+        }
+        return Ok(Value::Object(lights));
+    }
+
+    fn light(&self, idx: usize) -> Result<Value, &'static str> {
+        let zone = &self.zones[idx];
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let on = zone
+            .power
+            .lock()
+            .map_err(|_| "could not get power lock")?
+            .is_on();
+        let mode = self.modes.lock().map_err(|_| "could not get mode lock")?[idx];
+        let rgb = RGB8::new(color.r, color.g, color.b);
+        let (h, s, _) = rgb_to_hsv(rgb);
+        let xy = match mode {
+            ColorMode::Xy(xy) => xy,
+            _ => rgb_to_xy(rgb),
+        };
+        let ct = match mode {
+            ColorMode::Ct(ct) => ct,
+            _ => DEFAULT_MIREDS,
+        };
+        return Ok(json!({
+            "state": {
+                "on": on,
+                "bri": to_hue_bri(color.a),
+                "hue": (h / 360.0 * 65535.0).round() as u16,
+                "sat": (s / 100.0 * f32::from(MAX_SAT)).round() as u8,
+                "effect": "none",
+                "xy": xy,
+                "ct": ct,
+                "alert": "none",
+                "colormode": mode.name(),
+                "mode": "homeautomation",
+                "reachable": true,
+            },
+            "type": "Extended color light",
+            "name": zone.name,
+            "modelid": LIGHT_MODEL,
+            "manufacturername": "Signify Netherlands B.V.",
+            "productname": "Hue color lamp",
+            "capabilities": {
+                "certified": true,
+                "control": {
+                    "mindimlevel": 1000,
+                    "maxlumen": 800,
+                    "colorgamuttype": "C",
+                    "colorgamut": [[0.6915, 0.3083], [0.17, 0.7], [0.1532, 0.0475]],
+                    "ct": { "min": MIN_MIREDS, "max": MAX_MIREDS },
+                },
+                "streaming": { "renderer": false, "proxy": false },
+            },
+            "uniqueid": format!("{}:{:02x}-0b", self.mac_address(), idx + 1),
+            "swversion": "1.50.2_r30933",
+        }));
+    }
+
+    /// applies a state change, the response lists every applied value
+    fn update(&self, idx: usize, update: &LightUpdate) -> Result<Value, &'static str> {
+        let zone = &self.zones[idx];
+        let address = |key: &str| format!("/lights/{}/state/{}", idx + 1, key);
+        let duration = TRANSITION_STEP * update.transitiontime.unwrap_or(DEFAULT_TRANSITION).into();
+        let mut results = Vec::new();
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let mut target = color;
+        let mut modes = self.modes.lock().map_err(|_| "could not get mode lock")?;
+
+        // like on a real light, xy takes precedence over ct and ct over hue and saturation
+        if let Some(xy) = update.xy {
+            let xy = xy.map(|val| val.clamp(0.0, 1.0));
+            let rgb = xy_to_rgb(xy);
+            target = RGBA8::new(rgb.r, rgb.g, rgb.b, target.a);
+            modes[idx] = ColorMode::Xy(xy);
+            results.push(success(address("xy"), json!(xy)));
+        } else if let Some(ct) = update.ct {
+            let ct = ct.clamp(MIN_MIREDS, MAX_MIREDS);
+            let kelvin = (1_000_000 / u32::from(ct)) as u16;
+            let rgb = self
+                .calibration
+                .lock()
+                .map_err(|_| "could not get calibration lock")?
+                .ct_to_rgb(kelvin);
+            target = RGBA8::new(rgb.r, rgb.g, rgb.b, target.a);
+            modes[idx] = ColorMode::Ct(ct);
+            results.push(success(address("ct"), json!(ct)));
+        } else if update.hue.is_some() || update.sat.is_some() {
+            let (h, s, _) = rgb_to_hsv(RGB8::new(color.r, color.g, color.b));
+            let h = update.hue.map_or(h, |hue| f32::from(hue) / 65535.0 * 360.0);
+            let s = update.sat.map_or(s, |sat| {
+                f32::from(sat.min(MAX_SAT)) / f32::from(MAX_SAT) * 100.0
+            });
+            let rgb = hsv_to_rgb(h, s, 100.0);
+            target = RGBA8::new(rgb.r, rgb.g, rgb.b, target.a);
+            modes[idx] = ColorMode::Hs;
+            if let Some(hue) = update.hue {
+                results.push(success(address("hue"), json!(hue)));
+            }
+            if let Some(sat) = update.sat {
+                results.push(success(address("sat"), json!(sat.min(MAX_SAT))));
+            }
+        }
+        drop(modes);
+
+        if let Some(bri) = update.bri {
+            target.a = from_hue_bri(bri);
+            results.push(success(address("bri"), json!(bri.clamp(1, MAX_BRI))));
+        } else if let Some(bri_inc) = update.bri_inc {
+            let bri = (i16::from(to_hue_bri(target.a)) + bri_inc).clamp(1, MAX_BRI.into());
+            target.a = from_hue_bri(bri as u8);
+            results.push(success(address("bri_inc"), json!(bri_inc)));
+        }
+        if target != color {
+            transition::start(&zone.rgba, &zone.transition, target, duration)?;
+        }
+        if let Some(on) = update.on {
+            zone.power
+                .lock()
+                .map_err(|_| "could not get power lock")?
+                .set(on, Some(duration));
+            results.push(success(address("on"), json!(on)));
+        }
+        if let Some(transitiontime) = update.transitiontime {
+            results.push(success(address("transitiontime"), json!(transitiontime)));
+        }
+        return Ok(Value::Array(results));
+    }
+
+    /// UPnP device description served as `/description.xml`, `host` is the address the client
+    /// used to reach the controller
+    pub fn description(&self, host: &str) -> String {
+        return format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>
+<root xmlns=\"urn:schemas-upnp-org:device-1-0\">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<URLBase>http://{host}/</URLBase>
+<device>
+<deviceType>urn:schemas-upnp-org:device:Basic:1</deviceType>
+<friendlyName>{name} ({host})</friendlyName>
+<manufacturer>Signify</manufacturer>
+<manufacturerURL>http://www.philips-hue.com</manufacturerURL>
+<modelDescription>Philips hue Personal Wireless Lighting</modelDescription>
+<modelName>Philips hue bridge 2015</modelName>
+<modelNumber>{model}</modelNumber>
+<modelURL>http://www.philips-hue.com</modelURL>
+<serialNumber>{serial}</serialNumber>
+<UDN>uuid:{uuid}</UDN>
+<presentationURL>index.html</presentationURL>
+</device>
+</root>
+",
+            host = host,
+            name = self.hostname,
+            model = BRIDGE_MODEL,
+            serial = self.mac,
+            uuid = self.uuid(),
+        );
+    }
+
+    fn ssdp_response(&self, target: &str, ip: Ipv4Addr) -> String {
+        let usn = if target == SSDP_TARGETS[0] {
+            format!("uuid:{}::{}", self.uuid(), target)
+        } else {
+            format!("uuid:{}", self.uuid())
+        };
+        return format!(
+            "HTTP/1.1 200 OK\r\n\
+             HOST: {addr}:{port}\r\n\
+             EXT:\r\n\
+             CACHE-CONTROL: max-age=100\r\n\
+             LOCATION: http://{ip}:80/description.xml\r\n\
+             SERVER: Linux/3.14.0 UPnP/1.0 IpBridge/{version}\r\n\
+             hue-bridgeid: {bridge_id}\r\n\
+             ST: {target}\r\n\
+             USN: {usn}\r\n\r\n",
+            addr = SSDP_ADDR,
+            port = SSDP_PORT,
+            ip = ip,
+            version = API_VERSION,
+            bridge_id = self.bridge_id(),
+            target = target,
+            usn = usn,
+        );
+    }
+}
+
+fn not_available(path: &[&str]) -> Value {
+    let address = format!("/{}", path.join("/"));
+    return error(
+        3,
+        &address,
+        &format!("resource, {}, not available", address),
+    );
+}
+
+/// the search target of an SSDP `M-SEARCH` request, None for other messages
+fn search_target(msg: &str) -> Option<&str> {
+    if !msg.starts_with("M-SEARCH") {
+        return None;
+    }
+    return msg.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        Some(value.trim()).filter(|_| key.trim().eq_ignore_ascii_case("ST"))
+    });
+}
+
+/// the local address used to reach `peer`, the SSDP response points the client to it
+fn local_ip(peer: SocketAddr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    return match socket.local_addr().ok()? {
+        SocketAddr::V4(addr) => Some(*addr.ip()),
+        SocketAddr::V6(_) => None,
+    };
+}
+
+/// Answers SSDP searches for Hue bridges in a background thread
+pub fn spawn_ssdp(hue: Arc<Hue>) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).and_then(|socket| {
+        socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+        Ok(socket)
+    }) {
+        Ok(val) => val,
+        Err(e) => {
+            error!("Could not set up the SSDP socket! Error: {:?}", e);
+            return;
+        }
+    };
+    info!("Answering SSDP searches as Hue bridge {}", hue.bridge_id());
+
+    thread::spawn(move || {
+        // the default thread stack is too small for the receive buffer
+        let mut buf = vec![0_u8; 1024];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf) {
+                Ok(val) => val,
+                Err(e) => {
+                    warn!("Could not receive SSDP message! Error: {:?}", e);
+                    continue;
+                }
+            };
+            let msg = String::from_utf8_lossy(&buf[..len]);
+            let targets: Vec<&str> = match search_target(&msg) {
+                Some("ssdp:all") => SSDP_TARGETS.to_vec(),
+                Some(target) => SSDP_TARGETS
+                    .into_iter()
+                    .filter(|val| val.eq_ignore_ascii_case(target))
+                    .collect(),
+                None => continue,
+            };
+            let ip = match local_ip(peer) {
+                Some(val) => val,
+                None => continue,
+            };
+            for target in targets {
+                if let Err(e) = socket.send_to(hue.ssdp_response(target, ip).as_bytes(), peer) {
+                    warn!("Could not answer SSDP search! Error: {:?}", e);
+                }
+            }
+        }
+    });
+}
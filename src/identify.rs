@@ -0,0 +1,83 @@
+//! Locating a controller by blinking its outputs
+//!
+//! While identifying, the render loop shows the identify pattern on every zone instead of the
+//! zone color and the status LED shows its identify code. The zone state is not touched, so
+//! once identifying ends the zones show their state again, running transitions continue
+//! where they would be without identifying.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::rgb_led::{RGB8, RGBA8};
+use crate::status_led::{Pattern, StatusCode, StatusLed};
+
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+pub const MAX_DURATION: Duration = Duration::from_secs(300);
+/// three short flashes with full brightness, easy to tell apart from any color change
+const STRIPE_PATTERN: Pattern = Pattern::Blink {
+    color: RGB8::new(255, 255, 255),
+    count: 3,
+};
+
+struct Run {
+    start: Instant,
+    end: Instant,
+}
+
+pub struct Identify {
+    run: Mutex<Option<Run>>,
+    status_led: Arc<StatusLed>,
+}
+
+impl Identify {
+    pub fn new(status_led: Arc<StatusLed>) -> Identify {
+        return Identify {
+            run: Mutex::new(None),
+            status_led,
+        };
+    }
+
+    /// starts identifying, a running identify is restarted with the new duration
+    pub fn start(&self, duration: Duration) -> Result<(), &'static str> {
+        if duration > MAX_DURATION {
+            return Err("identify duration out of range");
+        }
+        let mut run = self.run.lock().map_err(|_| "could not get identify lock")?;
+        info!("Identifying for {} s", duration.as_secs());
+        let start = Instant::now();
+        *run = Some(Run {
+            start,
+            end: start + duration,
+        });
+        self.status_led.show_for(StatusCode::Identify, duration);
+        return Ok(());
+    }
+
+    pub fn stop(&self) -> Result<(), &'static str> {
+        let mut run = self.run.lock().map_err(|_| "could not get identify lock")?;
+        *run = None;
+        self.status_led.set(StatusCode::Identify, false);
+        return Ok(());
+    }
+
+    /// remaining identify time, None if not identifying
+    pub fn remaining(&self) -> Option<Duration> {
+        let run = self.run.lock().ok()?;
+        let remaining = run.as_ref()?.end.saturating_duration_since(Instant::now());
+        return Some(remaining).filter(|val| !val.is_zero());
+    }
+
+    /// the color shown on the zones instead of their state, None if not identifying
+    pub fn color(&self) -> Option<RGBA8> {
+        let run = self.run.lock().ok()?;
+        let run = run.as_ref()?;
+        let now = Instant::now();
+        if now >= run.end {
+            return None;
+        }
+        let rgb = STRIPE_PATTERN.color_at(now.saturating_duration_since(run.start));
+        return Some(rgb.alpha(u8::MAX));
+    }
+}
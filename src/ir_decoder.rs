@@ -0,0 +1,368 @@
+//! Decoding of NEC and RC5 infrared remote frames
+//!
+//! Works on the pulses of the demodulated receiver output in microseconds and has no hardware
+//! dependencies. NEC sends a 9 ms mark, a 4.5 ms space and 32 pulse distance coded bits
+//! (address, inverted address, command, inverted command, LSB first), a held key repeats a 9 ms
+//! mark with a 2.25 ms space. RC5 sends 14 Manchester coded bits of 1.778 ms: a start bit, a
+//! field bit (inverted 7th command bit), a toggle bit changing with every key press, 5 address
+//! bits and 6 command bits.
+
+use std::fmt;
+use std::str::FromStr;
+
+const NEC_LEADER_MARK_US: u32 = 9000;
+const NEC_LEADER_SPACE_US: u32 = 4500;
+const NEC_REPEAT_SPACE_US: u32 = 2250;
+const NEC_BIT_MARK_US: u32 = 560;
+const NEC_ZERO_SPACE_US: u32 = 560;
+const NEC_ONE_SPACE_US: u32 = 1690;
+const NEC_BITS: usize = 32;
+const RC5_HALF_BIT_US: u32 = 889;
+const RC5_BITS: usize = 14;
+
+/// one level of the receiver output, `mark` while the carrier is received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    pub mark: bool,
+    pub duration_us: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Nec,
+    Rc5,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Protocol::Nec => "nec",
+            Protocol::Rc5 => "rc5",
+        };
+    }
+}
+
+/// a key of a remote, formatted as `PROTOCOL:ADDRESS:COMMAND` with hex values, e.g. `nec:00:1a`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrCode {
+    pub protocol: Protocol,
+    /// 8 bit NEC address, 16 bit for extended NEC, 5 bit for RC5
+    pub address: u16,
+    pub command: u8,
+}
+
+impl fmt::Display for IrCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{}:{:02x}:{:02x}",
+            self.protocol.name(),
+            self.address,
+            self.command
+        );
+    }
+}
+
+impl FromStr for IrCode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let protocol = match parts.next() {
+            Some("nec") => Protocol::Nec,
+            Some("rc5") => Protocol::Rc5,
+            _ => return Err("unknown ir protocol"),
+        };
+        let (address, command) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(address), Some(command), None) => (address, command),
+            _ => return Err("ir code needs protocol, address and command"),
+        };
+        let address = u16::from_str_radix(address, 16).map_err(|_| "invalid ir address")?;
+        let command = u8::from_str_radix(command, 16).map_err(|_| "invalid ir command")?;
+        return Ok(IrCode {
+            protocol,
+            address,
+            command,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    Nec(IrCode),
+    /// NEC repeat frame of a held key, repeats the last code
+    NecRepeat,
+    /// the toggle bit only changes with a new key press
+    Rc5 {
+        code: IrCode,
+        toggle: bool,
+    },
+}
+
+/// true, if `duration` is within 25 % of `expected`
+fn near(duration: u32, expected: u32) -> bool {
+    return duration * 4 >= expected * 3 && duration * 4 <= expected * 5;
+}
+
+/// decodes the pulses of one frame, None for unknown or broken frames
+pub fn decode(pulses: &[Pulse]) -> Option<Frame> {
+    return decode_nec(pulses).or_else(|| decode_rc5(pulses));
+}
+
+fn decode_nec(pulses: &[Pulse]) -> Option<Frame> {
+    let (mark, space, rest) = match pulses {
+        [mark, space, rest @ ..] if mark.mark && !space.mark => (mark, space, rest),
+        _ => return None,
+    };
+    if !near(mark.duration_us, NEC_LEADER_MARK_US) {
+        return None;
+    }
+    if near(space.duration_us, NEC_REPEAT_SPACE_US) {
+        return Some(Frame::NecRepeat);
+    }
+    if !near(space.duration_us, NEC_LEADER_SPACE_US) || rest.len() < NEC_BITS * 2 {
+        return None;
+    }
+
+    let mut data: u32 = 0;
+    for (idx, bit) in rest.chunks_exact(2).take(NEC_BITS).enumerate() {
+        let (mark, space) = (bit[0], bit[1]);
+        if !mark.mark || space.mark || !near(mark.duration_us, NEC_BIT_MARK_US) {
+            return None;
+        }
+        if near(space.duration_us, NEC_ONE_SPACE_US) {
+            data |= 1 << idx;
+        } else if !near(space.duration_us, NEC_ZERO_SPACE_US) {
+            return None;
+        }
+    }
+    let [address, address_inv, command, command_inv] = data.to_le_bytes();
+    if command != !command_inv {
+        return None;
+    }
+    // extended NEC uses the inverted address byte as high byte of a 16 bit address
+    let address = if address == !address_inv {
+        address as u16
+    } else {
+        u16::from_le_bytes([address, address_inv])
+    };
+    return Some(Frame::Nec(IrCode {
+        protocol: Protocol::Nec,
+        address,
+        command,
+    }));
+}
+
+fn decode_rc5(pulses: &[Pulse]) -> Option<Frame> {
+    // the first half of the start bit is a space, which is not received
+    let mut halves = vec![false];
+    for pulse in pulses {
+        let count = if near(pulse.duration_us, RC5_HALF_BIT_US) {
+            1
+        } else if near(pulse.duration_us, RC5_HALF_BIT_US * 2) {
+            2
+        } else {
+            return None;
+        };
+        halves.resize(halves.len() + count, pulse.mark);
+    }
+    // a last 0 bit ends with a space, which merges with the idle receiver
+    if halves.len() == RC5_BITS * 2 - 1 {
+        halves.push(false);
+    }
+    if halves.len() != RC5_BITS * 2 {
+        return None;
+    }
+
+    let mut bits: u16 = 0;
+    for half in halves.chunks_exact(2) {
+        let bit = match (half[0], half[1]) {
+            (false, true) => 1,
+            (true, false) => 0,
+            _ => return None,
+        };
+        bits = bits << 1 | bit;
+    }
+    let bit = |idx: usize| bits >> (RC5_BITS - 1 - idx) & 1;
+    if bit(0) != 1 {
+        return None;
+    }
+    let address = (bits >> 6) & 0x1f;
+    // the field bit extends the command to 7 bits (RC5X)
+    let command = (bits & 0x3f) as u8 | ((bit(1) ^ 1) as u8) << 6;
+    return Some(Frame::Rc5 {
+        code: IrCode {
+            protocol: Protocol::Rc5,
+            address,
+            command,
+        },
+        toggle: bit(2) == 1,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir_remote::default_mappings;
+
+    fn pulse(mark: bool, duration_us: u32) -> Pulse {
+        return Pulse { mark, duration_us };
+    }
+
+    /// NEC frame of a 32 bit code as printed by common dumps, which show the first received bit
+    /// as the most significant one
+    fn nec_pulses(dump: u32) -> Vec<Pulse> {
+        let mut pulses = vec![
+            pulse(true, NEC_LEADER_MARK_US),
+            pulse(false, NEC_LEADER_SPACE_US),
+        ];
+        for idx in (0..NEC_BITS).rev() {
+            let space = if dump >> idx & 1 == 1 {
+                NEC_ONE_SPACE_US
+            } else {
+                NEC_ZERO_SPACE_US
+            };
+            pulses.push(pulse(true, NEC_BIT_MARK_US));
+            pulses.push(pulse(false, space));
+        }
+        pulses.push(pulse(true, NEC_BIT_MARK_US));
+        return pulses;
+    }
+
+    /// RC5 frame as seen by the receiver: without the leading space of the start bit and
+    /// without the trailing space of a last 0 bit
+    fn rc5_pulses(toggle: bool, address: u16, command: u8) -> Vec<Pulse> {
+        let field = (command >> 6 & 1) ^ 1;
+        let bits = 1 << 13
+            | (field as u16) << 12
+            | (toggle as u16) << 11
+            | (address & 0x1f) << 6
+            | (command & 0x3f) as u16;
+        let mut halves = Vec::new();
+        for idx in (0..RC5_BITS).rev() {
+            let one = bits >> idx & 1 == 1;
+            halves.extend([!one, one]);
+        }
+        halves.remove(0);
+        while halves.last() == Some(&false) {
+            halves.pop();
+        }
+        let mut pulses: Vec<Pulse> = Vec::new();
+        for mark in halves {
+            match pulses.last_mut() {
+                Some(last) if last.mark == mark => last.duration_us += RC5_HALF_BIT_US,
+                _ => pulses.push(pulse(mark, RC5_HALF_BIT_US)),
+            }
+        }
+        return pulses;
+    }
+
+    fn nec(address: u16, command: u8) -> IrCode {
+        return IrCode {
+            protocol: Protocol::Nec,
+            address,
+            command,
+        };
+    }
+
+    fn default_action(code: IrCode) -> Option<&'static str> {
+        return default_mappings()
+            .find(|(default, _)| *default == code)
+            .map(|(_, action)| action);
+    }
+
+    #[test]
+    fn decodes_keys_of_the_led_remote() {
+        let keys = [
+            (0x00FF3AC5, 0x5c, "a=+16"),
+            (0x00FFBA45, 0x5d, "a=-16"),
+            (0x00FF02FD, 0x40, "toggle"),
+            (0x00FF1AE5, 0x58, "hex=FF0000"),
+            (0x00FF9A65, 0x59, "hex=00FF00"),
+            (0x00FFA25D, 0x45, "hex=0000FF"),
+            (0x00FF30CF, 0x0c, "scene:diy1"),
+        ];
+        for (dump, command, action) in keys {
+            let code = nec(0x00, command);
+            assert_eq!(decode(&nec_pulses(dump)), Some(Frame::Nec(code)));
+            assert_eq!(default_action(code), Some(action), "{}", code);
+        }
+    }
+
+    #[test]
+    fn every_default_mapping_is_a_valid_action() {
+        for (code, action) in default_mappings() {
+            assert!(
+                crate::ir_remote::RemoteAction::from_str(action).is_ok(),
+                "{} -> {}",
+                code,
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_nec_repeat() {
+        let pulses = [
+            pulse(true, NEC_LEADER_MARK_US),
+            pulse(false, NEC_REPEAT_SPACE_US),
+            pulse(true, NEC_BIT_MARK_US),
+        ];
+        assert_eq!(decode(&pulses), Some(Frame::NecRepeat));
+    }
+
+    #[test]
+    fn decodes_extended_nec_address() {
+        // 24-key LED remote, address byte 00 is not followed by its inverse
+        let code = nec(0xef00, 0x03);
+        assert_eq!(decode(&nec_pulses(0x00F7C03F)), Some(Frame::Nec(code)));
+        assert_eq!(code.to_string(), "nec:ef00:03");
+        assert_eq!(default_action(code), None);
+    }
+
+    #[test]
+    fn decodes_nec_within_tolerance() {
+        let pulses: Vec<Pulse> = nec_pulses(0x00FF3AC5)
+            .into_iter()
+            .map(|val| pulse(val.mark, val.duration_us * 6 / 5))
+            .collect();
+        assert_eq!(decode(&pulses), Some(Frame::Nec(nec(0x00, 0x5c))));
+    }
+
+    #[test]
+    fn rejects_broken_nec_frames() {
+        // command and inverted command do not match
+        assert_eq!(decode(&nec_pulses(0x00FF3AC4)), None);
+        // truncated frame
+        assert_eq!(decode(&nec_pulses(0x00FF3AC5)[..40]), None);
+    }
+
+    #[test]
+    fn decodes_rc5_with_trailing_zero_bit() {
+        let code = IrCode {
+            protocol: Protocol::Rc5,
+            address: 0x00,
+            command: 0x10,
+        };
+        for toggle in [false, true] {
+            assert_eq!(
+                decode(&rc5_pulses(toggle, 0x00, 0x10)),
+                Some(Frame::Rc5 { code, toggle })
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_rc5_extended_command() {
+        // a cleared field bit adds 64 to the command
+        let code = IrCode {
+            protocol: Protocol::Rc5,
+            address: 0x05,
+            command: 0x4b,
+        };
+        assert_eq!(
+            decode(&rc5_pulses(true, 0x05, 0x4b)),
+            Some(Frame::Rc5 { code, toggle: true })
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Infrared remote control on the RMT receive channel
+//!
+//! The demodulated output of an IR receiver (e.g. TSOP38238, low while the carrier is received)
+//! is sampled by the RMT peripheral, a frame ends after `IDLE_THRESHOLD_US` without change.
+//! Decoded keys are looked up in the learned mappings first and in the built-in mapping of the
+//! common 44-key LED stripe remote afterwards. Held keys only repeat relative color actions.
+//!
+//! Actions are `on`, `off`, `toggle`, `none`, `scene:NAME`, `ct:KELVIN` or color parameters
+//! like in the UDP protocol, e.g. `a=+16` or `h=120,s=100`.
+
+use std::fmt;
+use std::ptr::null_mut;
+use std::slice;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_sys::{
+    esp, rmt_config, rmt_config_t, rmt_config_t__bindgen_ty_1, rmt_driver_install,
+    rmt_get_ringbuf_handle, rmt_item32_t, rmt_mode_t_RMT_MODE_RX, rmt_rx_config_t, rmt_rx_start,
+    vRingbufferReturnItem, xRingbufferReceive, EspError, RingbufHandle_t,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::{Calibration, MAX_KELVIN, MIN_KELVIN};
+use crate::ir_decoder::{self, Frame, IrCode, Protocol, Pulse};
+use crate::rgb_led::{apply_color_params, is_color_param, RGBA8};
+use crate::scenes::Scenes;
+use crate::storage::Storage;
+use crate::transition;
+use crate::zones::{self, Zone, GROUP_TARGET};
+
+const STORAGE_KEY: &str = "ir";
+/// the first RMT receive channel of the ESP32-C3, channel 0 and 1 can only transmit
+const RMT_RX_CHANNEL: u32 = 2;
+/// 80 MHz APB clock divided to 1 µs ticks
+const RMT_CLK_DIV: u8 = 80;
+const IDLE_THRESHOLD_US: u16 = 12000;
+/// glitches shorter than 100 APB clock ticks (1.25 µs) are ignored
+const FILTER_TICKS: u8 = 100;
+const RX_BUFFER_SIZE: usize = 1000;
+/// held keys repeat every 108 ms (NEC) or 114 ms (RC5), longer gaps start a new key press
+const REPEAT_TIMEOUT: Duration = Duration::from_millis(250);
+const SCENE_TRANSITION: Duration = Duration::from_millis(500);
+pub const MAX_MAPPINGS: usize = 48;
+pub const DEFAULT_LEARN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// address of the common 44-key LED stripe remote
+const LED_REMOTE_ADDRESS: u16 = 0x00;
+/// commands of the 44-key LED stripe remote as decoded (LSB first), old dumps that print the
+/// frame MSB first show them bit-reversed, e.g. `00FF3AC5` for brightness up (`0x5c`)
+const LED_REMOTE_MAPPING: [(u8, &str); 38] = [
+    // brightness up/ down, play/ pause, power
+    (0x5c, "a=+16"),
+    (0x5d, "a=-16"),
+    (0x41, "toggle"),
+    (0x40, "toggle"),
+    // color keys, row by row
+    (0x58, "hex=FF0000"),
+    (0x59, "hex=00FF00"),
+    (0x45, "hex=0000FF"),
+    (0x44, "hex=FFFFFF"),
+    (0x54, "hex=FF4000"),
+    (0x55, "hex=00FF40"),
+    (0x49, "hex=0040FF"),
+    (0x48, "ct:2700"),
+    (0x50, "hex=FF8000"),
+    (0x51, "hex=00FF80"),
+    (0x4d, "hex=8000FF"),
+    (0x4c, "ct:3000"),
+    (0x1c, "hex=FFC000"),
+    (0x1d, "hex=00FFFF"),
+    (0x1e, "hex=FF00FF"),
+    (0x1f, "ct:6500"),
+    (0x18, "hex=FFFF00"),
+    (0x19, "hex=00C0FF"),
+    (0x1a, "hex=FF4080"),
+    (0x1b, "ct:5000"),
+    // red, green and blue up/ down
+    (0x14, "r=+16"),
+    (0x15, "g=+16"),
+    (0x16, "b=+16"),
+    (0x10, "r=-16"),
+    (0x11, "g=-16"),
+    (0x12, "b=-16"),
+    // quick/ slow are used as coarse brightness steps, the effect keys are not mapped
+    (0x17, "a=+64"),
+    (0x13, "a=-64"),
+    // DIY keys recall the scenes diy1 - diy6
+    (0x0c, "scene:diy1"),
+    (0x0d, "scene:diy2"),
+    (0x0e, "scene:diy3"),
+    (0x08, "scene:diy4"),
+    (0x09, "scene:diy5"),
+    (0x0a, "scene:diy6"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteAction {
+    None,
+    On,
+    Off,
+    Toggle,
+    Scene(String),
+    Ct(u16),
+    /// color parameters as comma separated `key=value` pairs
+    Color(String),
+}
+
+impl RemoteAction {
+    /// held keys only repeat relative color changes, e.g. dimming
+    fn repeats(&self) -> bool {
+        return match self {
+            RemoteAction::Color(params) => color_pairs(params)
+                .iter()
+                .all(|(_, value)| value.starts_with(['+', '-'])),
+            _ => false,
+        };
+    }
+}
+
+fn color_pairs(params: &str) -> Vec<(&str, &str)> {
+    return params
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+}
+
+impl FromStr for RemoteAction {
+    type Err = &'static str;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "none" => return Ok(RemoteAction::None),
+            "on" => return Ok(RemoteAction::On),
+            "off" => return Ok(RemoteAction::Off),
+            "toggle" => return Ok(RemoteAction::Toggle),
+            _ => {}
+        }
+        if let Some(name) = action.strip_prefix("scene:") {
+            return Ok(RemoteAction::Scene(name.to_string()));
+        }
+        if let Some(kelvin) = action.strip_prefix("ct:") {
+            let kelvin = kelvin
+                .parse::<u16>()
+                .map_err(|_| "invalid color temperature")?;
+            if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+                return Err("color temperature out of range");
+            }
+            return Ok(RemoteAction::Ct(kelvin));
+        }
+        let pairs = color_pairs(action);
+        if pairs.is_empty() || pairs.len() != action.split(',').count() {
+            return Err("unknown action");
+        }
+        if pairs.iter().any(|(key, _)| !is_color_param(key)) {
+            return Err("unknown color parameter");
+        }
+        // reject invalid values when mapping, not when the key is pressed
+        apply_color_params(&mut RGBA8::default(), &pairs)?;
+        return Ok(RemoteAction::Color(action.to_string()));
+    }
+}
+
+impl fmt::Display for RemoteAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RemoteAction::None => write!(f, "none"),
+            RemoteAction::On => write!(f, "on"),
+            RemoteAction::Off => write!(f, "off"),
+            RemoteAction::Toggle => write!(f, "toggle"),
+            RemoteAction::Scene(name) => write!(f, "scene:{}", name),
+            RemoteAction::Ct(kelvin) => write!(f, "ct:{}", kelvin),
+            RemoteAction::Color(params) => write!(f, "{}", params),
+        };
+    }
+}
+
+/// learned mapping, kept as strings so the stored config stays small
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IrMapping {
+    pub code: String,
+    pub action: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IrConfig {
+    /// addressed zones (zone id, zone name or `all`)
+    pub zone: String,
+    pub mappings: Vec<IrMapping>,
+}
+
+impl Default for IrConfig {
+    fn default() -> Self {
+        return IrConfig {
+            zone: GROUP_TARGET.to_string(),
+            mappings: Vec::new(),
+        };
+    }
+}
+
+pub struct IrRemote {
+    config: IrConfig,
+    // action mapped to the next received code and the end of the learn mode
+    learning: Option<(RemoteAction, Instant)>,
+    last_code: Option<IrCode>,
+    storage: Arc<Storage>,
+}
+
+impl IrRemote {
+    /// loads the previously learned mappings from the storage
+    pub fn load(storage: Arc<Storage>) -> IrRemote {
+        let config = storage.load(STORAGE_KEY).unwrap_or_default();
+        return IrRemote {
+            config,
+            learning: None,
+            last_code: None,
+            storage,
+        };
+    }
+
+    pub fn zone(&self) -> &str {
+        return &self.config.zone;
+    }
+
+    pub fn set_zone(&mut self, zone: &str) -> Result<(), &'static str> {
+        let mut config = self.config.clone();
+        config.zone = zone.to_string();
+        return self.persist(config);
+    }
+
+    /// last received code, also while learning
+    pub fn last_code(&self) -> Option<IrCode> {
+        return self.last_code;
+    }
+
+    /// the action waiting for a code, None if the learn mode is off or timed out
+    pub fn learning(&self) -> Option<&RemoteAction> {
+        return match &self.learning {
+            Some((action, until)) if Instant::now() < *until => Some(action),
+            _ => None,
+        };
+    }
+
+    /// maps the next received code to the action
+    pub fn learn(&mut self, action: RemoteAction, timeout: Duration) {
+        info!("Learning ir code for {} ({:?})", action, timeout);
+        self.learning = Some((action, Instant::now() + timeout));
+    }
+
+    /// learned mappings followed by the built-in mappings, which are not overridden
+    pub fn mappings(&self) -> Vec<(String, String, bool)> {
+        let mut mappings: Vec<(String, String, bool)> = self
+            .config
+            .mappings
+            .iter()
+            .map(|mapping| (mapping.code.clone(), mapping.action.clone(), true))
+            .collect();
+        for (code, action) in default_mappings() {
+            let code = code.to_string();
+            if !mappings.iter().any(|(learned, _, _)| *learned == code) {
+                mappings.push((code, action.to_string(), false));
+            }
+        }
+        return mappings;
+    }
+
+    /// maps the code to the action, `none` disables a built-in mapping
+    pub fn map(&mut self, code: IrCode, action: &RemoteAction) -> Result<(), &'static str> {
+        let code = code.to_string();
+        let mut config = self.config.clone();
+        match config
+            .mappings
+            .iter_mut()
+            .find(|mapping| mapping.code == code)
+        {
+            Some(mapping) => mapping.action = action.to_string(),
+            None => {
+                if config.mappings.len() >= MAX_MAPPINGS {
+                    return Err("maximum number of ir mappings reached");
+                }
+                config.mappings.push(IrMapping {
+                    code,
+                    action: action.to_string(),
+                });
+            }
+        }
+        return self.persist(config);
+    }
+
+    /// deletes a learned mapping, the built-in mapping of the code applies again
+    pub fn delete(&mut self, code: IrCode) -> Result<(), &'static str> {
+        let code = code.to_string();
+        let mut config = self.config.clone();
+        let idx = config
+            .mappings
+            .iter()
+            .position(|mapping| mapping.code == code)
+            .ok_or("no learned mapping for this code")?;
+        config.mappings.remove(idx);
+        return self.persist(config);
+    }
+
+    fn action(&self, code: IrCode) -> Option<RemoteAction> {
+        let formatted = code.to_string();
+        if let Some(mapping) = self
+            .config
+            .mappings
+            .iter()
+            .find(|mapping| mapping.code == formatted)
+        {
+            return RemoteAction::from_str(&mapping.action).ok();
+        }
+        return default_mappings()
+            .find(|(default, _)| *default == code)
+            .and_then(|(_, action)| RemoteAction::from_str(action).ok());
+    }
+
+    /// handles a new key press, returns the action to execute unless it was learned
+    fn received(&mut self, code: IrCode) -> Option<RemoteAction> {
+        self.last_code = Some(code);
+        if let Some(action) = self.learning().cloned() {
+            self.learning = None;
+            match self.map(code, &action) {
+                Ok(_) => info!("Learned ir code {} for {}", code, action),
+                Err(e) => warn!("Could not learn ir code {}: {}", code, e),
+            }
+            return None;
+        }
+        return self.action(code);
+    }
+
+    fn persist(&mut self, config: IrConfig) -> Result<(), &'static str> {
+        self.storage.store(STORAGE_KEY, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+}
+
+/// codes of the built-in remote with their actions
+pub fn default_mappings() -> impl Iterator<Item = (IrCode, &'static str)> {
+    return LED_REMOTE_MAPPING.iter().map(|(command, action)| {
+        let code = IrCode {
+            protocol: Protocol::Nec,
+            address: LED_REMOTE_ADDRESS,
+            command: *command,
+        };
+        (code, *action)
+    });
+}
+
+/// turns decoded frames into key presses and repeats of held keys
+struct KeyTracker {
+    // code, rc5 toggle bit and time of the last frame
+    last: Option<(IrCode, Option<bool>, Instant)>,
+}
+
+impl KeyTracker {
+    /// returns the code and true, if it is a repeat of a held key
+    fn key(&mut self, frame: Frame, now: Instant) -> Option<(IrCode, bool)> {
+        let recent = self
+            .last
+            .filter(|(_, _, time)| now.saturating_duration_since(*time) < REPEAT_TIMEOUT);
+        let (code, toggle, repeat) = match frame {
+            Frame::Nec(code) => (code, None, false),
+            Frame::NecRepeat => {
+                let (code, _, _) = recent?;
+                (code, None, true)
+            }
+            Frame::Rc5 { code, toggle } => {
+                let repeat = matches!(recent, Some((last, Some(last_toggle), _)) if last == code && last_toggle == toggle);
+                (code, Some(toggle), repeat)
+            }
+        };
+        self.last = Some((code, toggle, now));
+        return Some((code, repeat));
+    }
+}
+
+/// executes remote actions on the addressed zones
+fn execute(
+    action: &RemoteAction,
+    zones: &[&Arc<Zone>],
+    scenes: &Mutex<Scenes>,
+    calibration: &Mutex<Calibration>,
+) -> Result<(), &'static str> {
+    match action {
+        RemoteAction::None => return Ok(()),
+        RemoteAction::On | RemoteAction::Off => {
+            for zone in zones {
+                zones::set_power(zone, *action == RemoteAction::On)?;
+            }
+            return Ok(());
+        }
+        RemoteAction::Toggle => return zones::toggle_power(zones),
+        _ => {}
+    }
+    for zone in zones {
+        let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+        let (target, duration) = match action {
+            RemoteAction::Scene(name) => {
+                let scenes = scenes.lock().map_err(|_| "could not get scenes lock")?;
+                let scene = scenes.get(name).ok_or("unknown scene")?;
+                (scene.color, SCENE_TRANSITION)
+            }
+            RemoteAction::Ct(kelvin) => {
+                let calibration = calibration
+                    .lock()
+                    .map_err(|_| "could not get calibration lock")?;
+                (
+                    calibration.ct_to_rgb(*kelvin).alpha(color.a),
+                    Duration::ZERO,
+                )
+            }
+            RemoteAction::Color(params) => {
+                let mut target = color;
+                apply_color_params(&mut target, &color_pairs(params))?;
+                (target, Duration::ZERO)
+            }
+            _ => continue,
+        };
+        transition::start(&zone.rgba, &zone.transition, target, duration)?;
+        // relative changes like dimming do not turn the zone on
+        if !action.repeats() {
+            zones::set_power(zone, true)?;
+        }
+    }
+    return Ok(());
+}
+
+fn start_receiver(gpio: u8) -> Result<RingbufHandle_t, EspError> {
+    let config = rmt_config_t {
+        rmt_mode: rmt_mode_t_RMT_MODE_RX,
+        channel: RMT_RX_CHANNEL,
+        gpio_num: gpio.into(),
+        clk_div: RMT_CLK_DIV,
+        mem_block_num: 1,
+        flags: 0,
+        __bindgen_anon_1: rmt_config_t__bindgen_ty_1 {
+            rx_config: rmt_rx_config_t {
+                idle_threshold: IDLE_THRESHOLD_US,
+                filter_ticks_thresh: FILTER_TICKS,
+                filter_en: true,
+                // the receiver already removes the carrier
+                rm_carrier: false,
+                carrier_freq_hz: 38000,
+                carrier_duty_percent: 33,
+                carrier_level: 0,
+            },
+        },
+    };
+    let mut ringbuf: RingbufHandle_t = null_mut();
+    unsafe {
+        esp!(rmt_config(&config))?;
+        esp!(rmt_driver_install(config.channel, RX_BUFFER_SIZE, 0))?;
+        esp!(rmt_get_ringbuf_handle(config.channel, &mut ringbuf))?;
+        esp!(rmt_rx_start(config.channel, true))?;
+    }
+    return Ok(ringbuf);
+}
+
+/// waits for the next received frame and converts its items into pulses
+fn receive_pulses(ringbuf: RingbufHandle_t) -> Vec<Pulse> {
+    let mut size = 0;
+    let mut pulses = Vec::new();
+    // wait without timeout (portMAX_DELAY)
+    let items = unsafe { xRingbufferReceive(ringbuf, &mut size, u32::MAX) } as *mut rmt_item32_t;
+    if items.is_null() {
+        return pulses;
+    }
+    let count = size / std::mem::size_of::<rmt_item32_t>();
+    for item in unsafe { slice::from_raw_parts(items, count) } {
+        let item = unsafe { item.__bindgen_anon_1.__bindgen_anon_1 };
+        for (level, duration) in [
+            (item.level0(), item.duration0()),
+            (item.level1(), item.duration1()),
+        ] {
+            // a zero duration marks the end of the frame
+            if duration == 0 {
+                break;
+            }
+            pulses.push(Pulse {
+                mark: level == 0,
+                duration_us: duration,
+            });
+        }
+    }
+    unsafe { vRingbufferReturnItem(ringbuf, items as *mut _) };
+    return pulses;
+}
+
+/// Receives and executes remote keys on `gpio` in a background thread
+pub fn spawn(
+    gpio: u8,
+    remote: Arc<Mutex<IrRemote>>,
+    zones: Arc<Vec<Arc<Zone>>>,
+    scenes: Arc<Mutex<Scenes>>,
+    calibration: Arc<Mutex<Calibration>>,
+) {
+    let ringbuf = match start_receiver(gpio) {
+        Ok(val) => val,
+        Err(e) => {
+            error!(
+                "Could not start the ir receiver on gpio {}! Error: {:?}",
+                gpio, e
+            );
+            return;
+        }
+    };
+    info!("Listening to the ir receiver on gpio {}", gpio);
+
+    // the ring buffer handle is only used by this thread
+    let ringbuf = ringbuf as usize;
+    thread::spawn(move || {
+        let ringbuf = ringbuf as RingbufHandle_t;
+        let mut tracker = KeyTracker { last: None };
+        loop {
+            let pulses = receive_pulses(ringbuf);
+            let frame = match ir_decoder::decode(&pulses) {
+                Some(val) => val,
+                None => continue,
+            };
+            let (code, repeat) = match tracker.key(frame, Instant::now()) {
+                Some(val) => val,
+                None => continue,
+            };
+            let (action, target) = match remote.lock() {
+                Ok(mut remote) => {
+                    let action = if repeat {
+                        remote.action(code)
+                    } else {
+                        remote.received(code)
+                    };
+                    (action, remote.zone().to_string())
+                }
+                Err(_) => continue,
+            };
+            let action = match action {
+                Some(action) if !repeat || action.repeats() => action,
+                _ => continue,
+            };
+            let result = match zones::resolve(&zones, &target) {
+                Some(zones) => execute(&action, &zones, &scenes, &calibration),
+                None => Err("unknown zone"),
+            };
+            if let Err(e) = result {
+                warn!("Could not execute ir action {} of {}: {}", action, code, e);
+            }
+        }
+    });
+}
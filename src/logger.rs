@@ -0,0 +1,266 @@
+//! Logging backend for the `log` facade
+//!
+//! Every record that passes the configured level filter is written to the serial console, kept
+//! in an in-memory ring buffer (readable via `/logs`) and, if configured, sent to a remote
+//! syslog server as RFC 5424 message over UDP.
+//!
+//! # Example
+//! ```
+//! logger::init("ledstripe");
+//! logger::logger().set_filter("info,api_handler=debug").unwrap();
+//! log::info!("Hello from the LED stripe");
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use esp_idf_sys::esp_timer_get_time;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Maximum number of log lines kept in memory
+const RING_BUFFER_SIZE: usize = 64;
+
+/// Syslog facility local0
+const SYSLOG_FACILITY: u8 = 16;
+
+const CRATE_PREFIX: &str = concat!(env!("CARGO_CRATE_NAME"), "::");
+
+static LOGGER: Logger = Logger {
+    hostname: OnceLock::new(),
+    filter: Mutex::new(Filter {
+        default: LevelFilter::Info,
+        directives: Vec::new(),
+    }),
+    buffer: Mutex::new(VecDeque::new()),
+    syslog: Mutex::new(None),
+};
+
+pub struct LogEntry {
+    pub level: Level,
+    pub line: String,
+}
+
+struct Filter {
+    default: LevelFilter,
+    // (target prefix, level)
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let short_target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+        // the longest matching target prefix wins
+        return self
+            .directives
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix) || short_target.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default);
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        return self
+            .directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max);
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.to_string().to_lowercase())?;
+        for (target, level) in self.directives.iter() {
+            write!(f, ",{}={}", target, level.to_string().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parses a filter spec like `info,api_handler=debug,esp_idf_svc=warn`
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            directives: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = LevelFilter::from_str(level)
+                        .map_err(|_| format!("unknown log level: {}", level))?;
+                    filter.directives.push((target.to_string(), level));
+                }
+                None => {
+                    filter.default = LevelFilter::from_str(directive)
+                        .map_err(|_| format!("unknown log level: {}", directive))?;
+                }
+            }
+        }
+        return Ok(filter);
+    }
+}
+
+struct SyslogSink {
+    socket: UdpSocket,
+    addr: SocketAddr,
+}
+
+pub struct Logger {
+    hostname: OnceLock<String>,
+    filter: Mutex<Filter>,
+    buffer: Mutex<VecDeque<LogEntry>>,
+    syslog: Mutex<Option<SyslogSink>>,
+}
+
+/// Installs the logger as backend of the `log` facade, should be called once at startup
+pub fn init(hostname: &str) {
+    let _ = LOGGER.hostname.set(hostname.to_string());
+    if log::set_logger(&LOGGER).is_ok() {
+        LOGGER.update_max_level();
+    }
+}
+
+pub fn logger() -> &'static Logger {
+    return &LOGGER;
+}
+
+impl Logger {
+    pub fn filter(&self) -> String {
+        return match self.filter.lock() {
+            Ok(filter) => filter.to_string(),
+            Err(_) => String::new(),
+        };
+    }
+
+    pub fn set_filter(&self, spec: &str) -> Result<(), String> {
+        let new_filter = Filter::from_str(spec)?;
+        let mut filter = self
+            .filter
+            .lock()
+            .map_err(|_| "could not get filter lock")?;
+        *filter = new_filter;
+        drop(filter);
+        self.update_max_level();
+        return Ok(());
+    }
+
+    /// returns the address of the configured syslog server, if any
+    pub fn syslog(&self) -> Option<SocketAddr> {
+        return match self.syslog.lock() {
+            Ok(syslog) => syslog.as_ref().map(|sink| sink.addr),
+            Err(_) => None,
+        };
+    }
+
+    /// Sets the remote syslog server, `None` disables the syslog sink
+    pub fn set_syslog(&self, host: Option<(&str, u16)>) -> Result<(), String> {
+        let sink = match host {
+            Some(host) => {
+                let addr = host
+                    .to_socket_addrs()
+                    .map_err(|e| format!("could not resolve syslog host: {}", e))?
+                    .next()
+                    .ok_or("could not resolve syslog host")?;
+                let socket = UdpSocket::bind("0.0.0.0:0")
+                    .map_err(|e| format!("could not bind syslog socket: {}", e))?;
+                Some(SyslogSink { socket, addr })
+            }
+            None => None,
+        };
+        let mut syslog = self
+            .syslog
+            .lock()
+            .map_err(|_| "could not get syslog lock")?;
+        *syslog = sink;
+        return Ok(());
+    }
+
+    /// Calls `f` for every buffered log entry, oldest first
+    pub fn for_each_entry(&self, f: impl FnMut(&LogEntry)) {
+        if let Ok(buffer) = self.buffer.lock() {
+            buffer.iter().for_each(f);
+        }
+    }
+
+    fn update_max_level(&self) {
+        if let Ok(filter) = self.filter.lock() {
+            log::set_max_level(filter.max_level());
+        }
+    }
+
+    fn send_syslog(&self, record: &Record) {
+        let syslog = match self.syslog.lock() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        let sink = match syslog.as_ref() {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        let severity: u8 = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+        let msg = format!(
+            "<{}>1 - {} {} - - - {}: {}",
+            SYSLOG_FACILITY * 8 + severity,
+            self.hostname.get().map(String::as_str).unwrap_or("-"),
+            env!("CARGO_PKG_NAME"),
+            record.target(),
+            record.args()
+        );
+        // a lost syslog message must never break the firmware
+        let _ = sink.socket.send_to(msg.as_bytes(), sink.addr);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        return match self.filter.lock() {
+            Ok(filter) => metadata.level() <= filter.level_for(metadata.target()),
+            Err(_) => false,
+        };
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let uptime_ms = unsafe { esp_timer_get_time() } / 1000;
+        let line = format!(
+            "[{:>6}.{:03}] {:<5} {}: {}",
+            uptime_ms / 1000,
+            uptime_ms % 1000,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        println!("{}", line);
+
+        if let Ok(mut buffer) = self.buffer.lock() {
+            if buffer.len() == RING_BUFFER_SIZE {
+                buffer.pop_front();
+            }
+            buffer.push_back(LogEntry {
+                level: record.level(),
+                line,
+            });
+        }
+
+        self.send_syslog(record);
+    }
+
+    fn flush(&self) {}
+}
@@ -1,7 +1,11 @@
 use esp_idf_sys::{self as _, EspError}; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
 use esp_idf_hal::{
-    modem::WifiModemPeripheral, peripheral::Peripheral, peripherals::Peripherals, prelude::*,
+    ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver},
+    modem::WifiModemPeripheral,
+    peripheral::Peripheral,
+    peripherals::Peripherals,
+    prelude::*,
 };
 
 use esp_idf_svc::{
@@ -13,31 +17,132 @@ use esp_idf_svc::{
 
 use embedded_svc::{
     http::Method,
-    io::Write,
     wifi::{ClientConfiguration, Configuration, Wifi},
 };
-use rgb::{RGB8, RGBA8};
+use rgb::RGBA8;
 
-use std::{net::UdpSocket, sync::RwLock};
+use std::{
+    net::UdpSocket,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+};
 use std::{num::NonZeroI32, sync::Arc};
-use std::{thread::sleep, time::Duration};
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 mod rmt_rgb_led;
 use crate::{
-    rgb_led::RGBABrightnessExt,
-    rmt_rgb_led::{show_failure, show_success, WS2812RMT},
+    rgb_led::{
+        apply_color_params, apply_white_params, is_color_param, is_white_param, Adjustment,
+        WhiteChannels,
+    },
+    rmt_rgb_led::WS2812RMT,
 };
 
 mod rgb_led;
 
-mod pwm_rgb_led;
+mod status_led;
+use status_led::{StatusCode, StatusLed};
+
+mod pwm_led;
 
 mod api_handler;
-use api_handler::{GetRGBAHandler, HelpHandler, SetRGBAHandler};
+use api_handler::{
+    AlarmAction, AlarmHandler, ButtonHandler, CalibrationHandler, EncoderHandler, GetRGBAHandler,
+    GetStateHandler, HealthHandler, HelpHandler, HueApiAction, HueApiHandler, HueHandler,
+    IdentifyHandler, IrAction, IrHandler, LogConfigHandler, LogsHandler, MeteredHandler,
+    MetricsHandler, PinsHandler, PowerAction, PowerHandler, RealtimeHandler, SceneAction,
+    SceneHandler, ScheduleAction, ScheduleHandler, SetCTHandler, SetRGBAHandler, SyncHandler,
+    ThermalHandler, TimeHandler, WhiteHandler, WifiHandler, WledAction, WledHandler, ZoneAction,
+    ZoneHandler,
+};
+
+mod discovery;
+use discovery::Service;
+
+mod metrics;
+use metrics::Metrics;
+
+mod logger;
+
+mod storage;
+use storage::Storage;
+
+mod transition;
+use transition::Transition;
+
+mod scenes;
+use scenes::Scenes;
+
+mod clock;
+use clock::{Clock, TimeConfig};
+
+mod sun;
+
+mod derating;
+use derating::DeratingStatus;
+
+mod thermal;
+use thermal::{NtcConfig, Thermal};
+
+mod scheduler;
+use scheduler::Scheduler;
+
+mod alarm;
+use alarm::Alarm;
+
+mod calibration;
+use calibration::{Calibration, MAX_KELVIN, MIN_KELVIN};
+
+mod power;
+use power::Power;
 
-use self::pwm_rgb_led::PwmRgbLed;
+mod power_budget;
+
+mod pins;
+use pins::{output_pin, ChannelOrder, Pins};
+
+mod zones;
+use zones::{Output, Zone, ZoneRenderer};
+
+mod button;
+use button::Button;
+
+mod provisioning;
+use provisioning::WifiCredentials;
+
+mod ir_decoder;
+
+mod ir_remote;
+use ir_remote::IrRemote;
+
+mod encoder;
+use encoder::Encoder;
+
+mod identify;
+use identify::Identify;
+
+mod group_sync;
+use group_sync::GroupSync;
+
+mod realtime;
+use realtime::Realtime;
+
+mod wled;
+use wled::Wled;
+
+mod ddp;
+mod tpm2;
+
+mod hue;
+use hue::Hue;
+
+use self::pwm_led::PwmLed;
 
 use atoi::atoi;
+use log::{error, info, warn};
 
 #[toml_cfg::toml_config]
 struct Settings {
@@ -49,34 +154,73 @@ struct Settings {
     wifi_timeout_wait_seconds: u16,
     #[default(5)]
     wifi_connection_attempts: u16,
+    #[default("ledstripe")]
+    hostname: &'static str,
+    #[default("pool.ntp.org")]
+    ntp_server: &'static str,
+    #[default("UTC0")]
+    timezone: &'static str,
+    #[default(0.0)]
+    latitude: f32,
+    #[default(0.0)]
+    longitude: f32,
+    #[default(3)]
+    led_channels: u8,
+    #[default(false)]
+    hardware_fade: bool,
+    #[default("main")]
+    zone_name: &'static str,
+    #[default("")]
+    zone2: &'static str,
+    #[default("zone2")]
+    zone2_name: &'static str,
+    #[default(10)]
+    zone2_pin: u8,
+    #[default(30)]
+    zone2_pixels: u16,
+    #[default(-1)]
+    ntc_adc_channel: i8,
+    #[default(10000)]
+    ntc_series_ohm: u32,
+    #[default(10000)]
+    ntc_nominal_ohm: u32,
+    #[default(3950)]
+    ntc_beta: u32,
 }
 
+const HTTP_PORT: u16 = 80;
+const UDP_PORT: u16 = 80;
+const WIFI_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CONNECTED_DISPLAY_DURATION: Duration = Duration::from_secs(1);
+const ERROR_DISPLAY_DURATION: Duration = Duration::from_secs(2);
+
 fn create_wifi_driver<M: WifiModemPeripheral>(
     modem: impl Peripheral<P = M> + 'static,
+    nvs: EspDefaultNvsPartition,
+    credentials: &WifiCredentials,
 ) -> Result<EspWifi<'static>, EspError> {
-    println!("Creating wifi driver");
+    info!("Creating wifi driver");
     let sys_loop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
 
     let mut wifi_driver = EspWifi::new(modem, sys_loop, Some(nvs))?;
 
     wifi_driver.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: SETTINGS.ssid.into(),
-        password: SETTINGS.passphrase.into(),
+        ssid: credentials.ssid.as_str().into(),
+        password: credentials.passphrase.as_str().into(),
         ..Default::default()
     }))?;
     return Ok(wifi_driver);
 }
 
-fn connect_to_wifi(wifi_driver: &mut EspWifi) -> Result<(), EspError> {
-    println!("Connecting to wifi: {:?}", SETTINGS.ssid);
+fn connect_to_wifi(wifi_driver: &mut EspWifi, ssid: &str) -> Result<(), EspError> {
+    info!("Connecting to wifi: {:?}", ssid);
     wifi_driver.start()?;
     wifi_driver.connect()?;
 
     // wait until connection is established or time has passed
     for _ in 0..SETTINGS.wifi_timeout_wait_seconds {
         if wifi_driver.is_connected()? {
-            println!("Connection established");
+            info!("Connection established");
             return Ok(());
         } else {
             sleep(Duration::from_secs(1));
@@ -87,7 +231,322 @@ fn connect_to_wifi(wifi_driver: &mut EspWifi) -> Result<(), EspError> {
     return Err(EspError::from_non_zero(NonZeroI32::new(12295).unwrap()));
 }
 
-fn update_rgba_from_udp_msg(msg_arr: &[u8], rgba: &mut RGBA8) {
+/// serves `/wifi` on the setup access point until new credentials restart the controller
+fn run_provisioning(
+    mut wifi_driver: EspWifi,
+    storage: Arc<Storage>,
+    status_led: &StatusLed,
+) -> Result<(), EspError> {
+    provisioning::start_access_point(&mut wifi_driver, SETTINGS.hostname)?;
+    status_led.set(StatusCode::Provisioning, true);
+
+    let mut esp_server = EspHttpServer::new(&HttpConfiguration {
+        http_port: HTTP_PORT,
+        ..Default::default()
+    })?;
+    esp_server.handler(
+        "/wifi",
+        Method::Get,
+        WifiHandler::new(storage, SETTINGS.ssid, true),
+    )?;
+
+    sleep(provisioning::ACCESS_POINT_TIMEOUT);
+    warn!("No wifi credentials received, restarting");
+    unsafe { esp_idf_sys::esp_restart() };
+    return Ok(());
+}
+
+/// Handles color temperature messages, returns None if the message is not a ct message
+fn handle_udp_ct_msg(
+    msg_arr: &[u8],
+    rgba: &RwLock<RGBA8>,
+    calibration: &Mutex<Calibration>,
+) -> Option<bool> {
+    // Message format is:
+    // ct=KELVIN,a=VALUE (brightness is optional and can be relative)
+    let msg = std::str::from_utf8(msg_arr).ok()?.trim_end();
+    if !msg.starts_with("ct=") {
+        return None;
+    }
+
+    let mut kelvin = None;
+    let mut brightness = None;
+    for pair in msg.split(',') {
+        let parsed = match pair.split_once('=') {
+            Some(("ct", value)) => match value.parse::<u16>() {
+                Ok(val) if (MIN_KELVIN..=MAX_KELVIN).contains(&val) => {
+                    kelvin = Some(val);
+                    true
+                }
+                _ => false,
+            },
+            Some(("a", value)) => Adjustment::from_str(value)
+                .map(|val| brightness = Some(val))
+                .is_ok(),
+            _ => false,
+        };
+        if !parsed {
+            warn!("received invalid ct message part: {:?}", pair);
+            return Some(false);
+        }
+    }
+
+    let rgb = match (kelvin, calibration.lock()) {
+        (Some(kelvin), Ok(calibration)) => calibration.ct_to_rgb(kelvin),
+        _ => return Some(false),
+    };
+    return match rgba.write() {
+        Ok(mut rgba) => {
+            let brightness = match brightness.map(|val| val.apply_channel(rgba.a)) {
+                Some(Ok(val)) => val,
+                Some(Err(e)) => {
+                    warn!("received invalid ct brightness: {}", e);
+                    return Some(false);
+                }
+                None => rgba.a,
+            };
+            *rgba = rgb.alpha(brightness);
+            Some(true)
+        }
+        Err(e) => {
+            error!("could not get write lock for rgba_udp! Error: {}", e);
+            Some(false)
+        }
+    };
+}
+
+/// Handles identify messages, returns None if the message is not an identify message
+fn handle_udp_identify_msg(msg_arr: &[u8], identify: &Identify) -> Option<bool> {
+    // Message format is:
+    // identify / identify=SECONDS (0 ends a running identify)
+    let msg = std::str::from_utf8(msg_arr).ok()?.trim_end();
+    let duration = match msg.strip_prefix("identify")? {
+        "" => identify::DEFAULT_DURATION,
+        params => match params.strip_prefix('=').map(|val| val.parse::<u64>()) {
+            Some(Ok(seconds)) => Duration::from_secs(seconds),
+            _ => {
+                warn!("received invalid identify message: {:?}", msg);
+                return Some(false);
+            }
+        },
+    };
+    if let Err(e) = identify.start(duration) {
+        warn!("could not identify! Error: {}", e);
+        return Some(false);
+    }
+    return Some(true);
+}
+
+/// Handles power messages, returns None if the message is not a power message
+fn handle_udp_power_msg(msg_arr: &[u8], power: &Mutex<Power>) -> Option<bool> {
+    // Message format is:
+    // on,t=MILLISECONDS / off,t=MILLISECONDS / toggle,t=MILLISECONDS (fade time is optional)
+    let msg = std::str::from_utf8(msg_arr).ok()?.trim_end();
+    let (command, params) = match msg.split_once(',') {
+        Some((command, params)) => (command, Some(params)),
+        None => (msg, None),
+    };
+    if !matches!(command, "on" | "off" | "toggle") {
+        return None;
+    }
+
+    let duration = match params.map(|params| params.split_once('=')) {
+        None => None,
+        Some(Some(("t", value))) => match value.parse::<u64>() {
+            Ok(ms) => Some(Duration::from_millis(ms)),
+            Err(_) => {
+                warn!("could not convert {:?} to transition time!", value);
+                return Some(false);
+            }
+        },
+        Some(_) => {
+            warn!("received invalid power message: {:?}", msg);
+            return Some(false);
+        }
+    };
+    let mut power = match power.lock() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("could not get power lock! Error: {}", e);
+            return Some(false);
+        }
+    };
+    match command {
+        "on" => power.set(true, duration),
+        "off" => power.set(false, duration),
+        _ => {
+            power.toggle(duration);
+        }
+    }
+    return Some(true);
+}
+
+/// Handles hsv/ hsl/ hex color messages and relative steps,
+/// returns None if the message is a plain r,g,b,a message
+fn handle_udp_color_msg(
+    msg_arr: &[u8],
+    rgba: &RwLock<RGBA8>,
+    white: &RwLock<WhiteChannels>,
+) -> Option<bool> {
+    // Message format is:
+    // h=VALUE,s=VALUE,v=VALUE,a=VALUE (hsv, use l instead of v for hsl)
+    // hex=RRGGBB[AA]
+    // a=+VALUE,r=-VALUE (relative steps, every value except hex can be relative)
+    // w=VALUE,ww=VALUE,cw=VALUE (white channels, can be combined with the other formats)
+    let msg = std::str::from_utf8(msg_arr).ok()?.trim_end();
+    let pairs: Vec<(&str, &str)> = msg
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let is_plain_rgba = pairs.iter().all(|(key, value)| {
+        matches!(*key, "r" | "g" | "b" | "a") && !value.starts_with(['+', '-'])
+    });
+    if is_plain_rgba {
+        return None;
+    }
+    if let Some((key, _)) = pairs
+        .iter()
+        .find(|(key, _)| !is_color_param(key) && !is_white_param(key))
+    {
+        warn!("received unknown channel type: {:?}", key);
+        return Some(false);
+    }
+
+    let mut rgba = match rgba.write() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("could not get write lock for rgba_udp! Error: {}", e);
+            return Some(false);
+        }
+    };
+    let mut white = match white.write() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("could not get write lock for white_udp! Error: {}", e);
+            return Some(false);
+        }
+    };
+    // only apply the new color, if all values are valid
+    let mut color = *rgba;
+    let mut new_white = *white;
+    let result = apply_color_params(&mut color, &pairs)
+        .and_then(|_| apply_white_params(&mut new_white, &pairs));
+    if let Err(e) = result {
+        warn!("received invalid color message: {}", e);
+        return Some(false);
+    }
+    *rgba = color;
+    *white = new_white;
+    return Some(true);
+}
+
+/// Handles scene messages, returns None if the message is not a scene message
+fn handle_udp_scene_msg(
+    msg_arr: &[u8],
+    rgba: &RwLock<RGBA8>,
+    transition: &Mutex<Option<Transition>>,
+    scenes: &Mutex<Scenes>,
+) -> Option<bool> {
+    // Message format is:
+    // scene=NAME,t=MILLISECONDS (recall a scene, transition time is optional)
+    // savescene=NAME (saves the current color as scene)
+    let msg = std::str::from_utf8(msg_arr).ok()?.trim_end();
+    if !msg.starts_with("scene=") && !msg.starts_with("savescene=") {
+        return None;
+    }
+
+    let mut recall_name = None;
+    let mut save_name = None;
+    let mut duration = Duration::ZERO;
+    for pair in msg.split(',') {
+        match pair.split_once('=') {
+            Some(("scene", name)) => recall_name = Some(name),
+            Some(("savescene", name)) => save_name = Some(name),
+            Some(("t", value)) => match value.parse::<u64>() {
+                Ok(ms) => duration = Duration::from_millis(ms),
+                Err(_) => {
+                    warn!("could not convert {:?} to transition time!", value);
+                    return Some(false);
+                }
+            },
+            _ => {
+                warn!("received invalid scene message: {:?}", pair);
+                return Some(false);
+            }
+        }
+    }
+
+    let mut scenes = match scenes.lock() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("could not get scenes lock! Error: {}", e);
+            return Some(false);
+        }
+    };
+    let result = match (recall_name, save_name) {
+        (Some(name), None) => match scenes.get(name) {
+            Some(scene) => transition::start(rgba, transition, scene.color, duration),
+            None => Err("unknown scene"),
+        },
+        (None, Some(name)) => match rgba.read() {
+            Ok(color) => scenes.save(name, *color),
+            Err(_) => Err("could not get read lock"),
+        },
+        _ => Err("invalid scene message"),
+    };
+    if let Err(e) = result {
+        warn!("could not handle scene message: {}", e);
+        return Some(false);
+    }
+    return Some(true);
+}
+
+/// Handles a message for a single zone, returns false if the message was not accepted
+fn handle_udp_msg(
+    msg_arr: &[u8],
+    zone: &Zone,
+    scenes: &Mutex<Scenes>,
+    calibration: &Mutex<Calibration>,
+) -> bool {
+    if let Some(accepted) = handle_udp_scene_msg(msg_arr, &zone.rgba, &zone.transition, scenes) {
+        return accepted;
+    }
+    if let Some(accepted) = handle_udp_ct_msg(msg_arr, &zone.rgba, calibration) {
+        return accepted;
+    }
+    if let Some(accepted) = handle_udp_power_msg(msg_arr, &zone.power) {
+        return accepted;
+    }
+    if let Some(accepted) = handle_udp_color_msg(msg_arr, &zone.rgba, &zone.white) {
+        return accepted;
+    }
+    let mut rgba = match zone.rgba.write() {
+        Ok(val) => val,
+        Err(e) => {
+            error!("could not get write lock for rgba_udp! Error: {}", e);
+            return false;
+        }
+    };
+    return update_rgba_from_udp_msg(msg_arr, &mut rgba);
+}
+
+/// Splits the optional zone prefix from a message, messages without prefix address the first zone
+fn split_udp_zone_prefix(msg_arr: &[u8]) -> (&str, &[u8]) {
+    // Message format is:
+    // zone=ID,MESSAGE (ID is the zone id, the zone name or all)
+    let rest = match msg_arr.strip_prefix(b"zone=") {
+        Some(val) => val,
+        None => return ("0", msg_arr),
+    };
+    let (target, msg) = match rest.iter().position(|val| *val == b',') {
+        Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+        None => (rest, &rest[rest.len()..]),
+    };
+    return (std::str::from_utf8(target).unwrap_or(""), msg);
+}
+
+/// returns false, if the message contained invalid data or did not update any channel
+fn update_rgba_from_udp_msg(msg_arr: &[u8], rgba: &mut RGBA8) -> bool {
     // Message format is:
     // r=VALUE,g=VALUE,b=VALUE,a=VALUE
 
@@ -101,6 +560,8 @@ fn update_rgba_from_udp_msg(msg_arr: &[u8], rgba: &mut RGBA8) {
     // 'a' -> 97
     let mut last_equal_sign_idx: usize = 0;
     let mut curr_channel_type: u8 = 0;
+    let mut is_valid = true;
+    let mut updated_channel = false;
 
     for (idx, val) in msg_arr.iter().enumerate() {
         // found '=' -> update channel type
@@ -115,10 +576,12 @@ fn update_rgba_from_udp_msg(msg_arr: &[u8], rgba: &mut RGBA8) {
                     curr_channel_type = msg_arr[idx - 1];
                     last_equal_sign_idx = idx;
                 } else {
-                    eprintln!("received unknown channel type: {}", msg_arr[idx - 1]);
+                    warn!("received unknown channel type: {}", msg_arr[idx - 1]);
+                    is_valid = false;
                 }
             } else {
-                eprintln!("received invalid data frame format!");
+                warn!("received invalid data frame format!");
+                is_valid = false;
             }
         }
         // found ',' or newline (\n) -> update channel value and set matching rgba field
@@ -134,82 +597,243 @@ fn update_rgba_from_udp_msg(msg_arr: &[u8], rgba: &mut RGBA8) {
                         98 => rgba.b = curr_channel_value,
                         97 => rgba.a = curr_channel_value,
                         _ => {
-                            eprintln!("found non matching channel type: {}", curr_channel_type);
+                            warn!("found non matching channel type: {}", curr_channel_type);
+                            is_valid = false;
+                            continue;
                         }
                     }
+                    updated_channel = true;
                 } else {
-                    eprintln!(
+                    warn!(
                         "could not convert {:?} to u8 integer!",
                         &msg_arr[last_equal_sign_idx + 1..idx]
                     );
+                    is_valid = false;
                 };
             }
         }
     }
+    return is_valid && updated_channel;
 }
 
 fn main() -> Result<(), EspError> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
     esp_idf_sys::link_patches();
+    logger::init(SETTINGS.hostname);
 
     let peripherals =
         Peripherals::take().expect("could not take esp peripherals, should be available");
 
-    let mut rgb_led = WS2812RMT::new(8).expect("RGB LED should be creatable!");
+    let nvs = EspDefaultNvsPartition::take()?;
+    let storage = Arc::new(Storage::new(nvs.clone())?);
+
+    // a pwm zone2 uses the LEDC channels of the white channels, so both can't be combined
+    let zone2_pwm = SETTINGS.zone2 == "pwm" && SETTINGS.led_channels <= 3;
+    if SETTINGS.zone2 == "pwm" && !zone2_pwm {
+        error!("pwm zone2 needs the LEDC channels of the white channels, disabling zone2");
+    }
+    let mut extra_pins = match SETTINGS.zone2 {
+        "ws2812" => vec![SETTINGS.zone2_pin],
+        _ => Vec::new(),
+    };
+    // ADC1 channel 0 - 4 is GPIO 0 - 4 on the ESP32-C3
+    let ntc = match u8::try_from(SETTINGS.ntc_adc_channel) {
+        Ok(channel) if channel <= 4 => Some(NtcConfig {
+            adc_channel: channel,
+            series_ohm: SETTINGS.ntc_series_ohm as f32,
+            nominal_ohm: SETTINGS.ntc_nominal_ohm as f32,
+            beta: SETTINGS.ntc_beta as f32,
+        }),
+        Ok(channel) => {
+            error!(
+                "NTC adc channel {} does not exist, disabling the NTC",
+                channel
+            );
+            None
+        }
+        Err(_) => None,
+    };
+    if let Some(ntc) = &ntc {
+        extra_pins.push(ntc.adc_channel);
+    }
+    let pins = Arc::new(Pins::load(
+        storage.clone(),
+        SETTINGS.led_channels.into(),
+        zone2_pwm,
+        extra_pins,
+    ));
+    let pin_config = pins.active().clone();
+
+    let status_led = status_led::spawn(
+        WS2812RMT::new(pin_config.status_pin.into()).expect("RGB LED should be creatable!"),
+    );
 
-    let mut pwm_led = PwmRgbLed::new(
-        1.kHz().into(),
+    let timer_driver = LedcTimerDriver::new(
         peripherals.ledc.timer0,
-        peripherals.ledc.channel0,
-        peripherals.ledc.channel1,
-        peripherals.ledc.channel2,
-        peripherals.pins.gpio1,
-        peripherals.pins.gpio2,
-        peripherals.pins.gpio3,
+        &TimerConfig::default().frequency(pin_config.frequency_hz.Hz()),
     )
-    .expect("could not instantiate PwmRgbLed struct from peripherals!");
+    .expect("could not instantiate LEDC timer driver from peripherals!");
+    let mut pwm_drivers = vec![
+        LedcDriver::new(
+            peripherals.ledc.channel0,
+            &timer_driver,
+            output_pin(pin_config.pins[0]),
+        ),
+        LedcDriver::new(
+            peripherals.ledc.channel1,
+            &timer_driver,
+            output_pin(pin_config.pins[1]),
+        ),
+        LedcDriver::new(
+            peripherals.ledc.channel2,
+            &timer_driver,
+            output_pin(pin_config.pins[2]),
+        ),
+    ];
+    let mut zone2_drivers = Vec::new();
+    if zone2_pwm {
+        zone2_drivers = vec![
+            LedcDriver::new(
+                peripherals.ledc.channel3,
+                &timer_driver,
+                output_pin(pin_config.zone2_pins[0]),
+            ),
+            LedcDriver::new(
+                peripherals.ledc.channel4,
+                &timer_driver,
+                output_pin(pin_config.zone2_pins[1]),
+            ),
+            LedcDriver::new(
+                peripherals.ledc.channel5,
+                &timer_driver,
+                output_pin(pin_config.zone2_pins[2]),
+            ),
+        ];
+    } else {
+        // white (RGBW) or warm white and cold white (RGBWW) channels
+        if SETTINGS.led_channels >= 4 {
+            pwm_drivers.push(LedcDriver::new(
+                peripherals.ledc.channel3,
+                &timer_driver,
+                output_pin(pin_config.pins[3]),
+            ));
+        }
+        if SETTINGS.led_channels >= 5 {
+            pwm_drivers.push(LedcDriver::new(
+                peripherals.ledc.channel4,
+                &timer_driver,
+                output_pin(pin_config.pins[4]),
+            ));
+        }
+    }
+    let mut pwm_led = pwm_drivers
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|drivers| PwmLed::new(drivers, pin_config.order, pin_config.inverted))
+        .expect("could not instantiate PwmLed struct from peripherals!");
+    let layout = pwm_led.layout();
+    info!(
+        "Driving {:?} stripe on gpio {:?} ({} order, {} Hz)",
+        layout,
+        &pin_config.pins[..SETTINGS.led_channels.into()],
+        pin_config.order.name(),
+        pin_config.frequency_hz
+    );
 
     pwm_led.set_off().expect("could not turn pwm LEDs off!");
 
-    let mut wifi_driver = match create_wifi_driver(peripherals.modem) {
+    // falls back to software fades, if the fade service is not available
+    let hardware_fade = SETTINGS.hardware_fade
+        && match pwm_led::install_fade_service().and_then(|_| pwm_led.enable_hardware_fade()) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Could not enable hardware fades! Error: {:?}", e);
+                false
+            }
+        };
+
+    // the status LED uses the first RMT channel
+    let zone2_output = match SETTINGS.zone2 {
+        "pwm" if zone2_pwm => zone2_drivers
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|drivers| PwmLed::new(drivers, ChannelOrder::Rgb, [false; 5]))
+            .and_then(|mut pwm_led| {
+                if hardware_fade {
+                    pwm_led.enable_hardware_fade()?;
+                }
+                Ok(pwm_led)
+            })
+            .map(Output::Pwm)
+            .map_err(|e| error!("Could not create pwm zone2! Error: {:?}", e))
+            .ok(),
+        "ws2812" => WS2812RMT::with_channel(SETTINGS.zone2_pin.into(), 1)
+            .map(|strip| Output::Ws2812 {
+                strip,
+                pixels: SETTINGS.zone2_pixels.into(),
+            })
+            .map_err(|e| error!("Could not create ws2812 zone2! Error: {:?}", e))
+            .ok(),
+        "" | "pwm" => None,
+        other => {
+            error!("Unknown zone2 type {:?}, disabling zone2", other);
+            None
+        }
+    };
+
+    // stored credentials replace the ones from the cfg.toml
+    let credentials = provisioning::load_credentials(&storage).unwrap_or(WifiCredentials {
+        ssid: SETTINGS.ssid.to_string(),
+        passphrase: SETTINGS.passphrase.to_string(),
+    });
+    let mut wifi_driver = match create_wifi_driver(peripherals.modem, nvs, &credentials) {
         Ok(x) => x,
         Err(e) => {
             // when the wifi driver creation fails, the program should stop
-            eprintln!("Could not create esp32 wifi driver! Error: {:?}", e,);
-            show_failure(&mut rgb_led);
+            error!("Could not create esp32 wifi driver! Error: {:?}", e,);
+            status_led.set(StatusCode::WifiError, true);
+            // give the status LED some time to show the error
+            sleep(ERROR_DISPLAY_DURATION);
             return Err(e);
         }
     };
 
+    if provisioning::take_request(&storage) {
+        return run_provisioning(wifi_driver, storage, &status_led);
+    }
+
+    let metrics = Arc::new(Metrics::new());
+
+    status_led.set(StatusCode::Connecting, true);
     // try multiple times to connect to wifi if first one did not suceed
     for i in 0..SETTINGS.wifi_connection_attempts {
-        match connect_to_wifi(&mut wifi_driver) {
+        match connect_to_wifi(&mut wifi_driver, &credentials.ssid) {
             Ok(_) => {
-                println!("Successfully connected to wifi!");
-                show_success(&mut rgb_led);
+                info!("Successfully connected to wifi!");
+                status_led.show_for(StatusCode::Connected, CONNECTED_DISPLAY_DURATION);
                 break;
             }
             Err(e) => {
-                eprintln!(
+                warn!(
                     "Could not yet connect to wifi - trying again ({:?}/{:?})! Error {:?}",
                     i + 1,
                     SETTINGS.wifi_connection_attempts,
                     e
                 );
                 if i + 1 == SETTINGS.wifi_connection_attempts {
-                    eprintln!(
+                    error!(
                         "Could not connect to wifi after {:?} attemps, quitting...",
                         SETTINGS.wifi_connection_attempts
                     );
-                    show_failure(&mut rgb_led);
+                    status_led.set(StatusCode::WifiError, true);
                 }
             }
         };
     }
 
-    let mut udp_buf = [0 as u8; 24];
-    let listener = UdpSocket::bind("0.0.0.0:80").expect("Could not bind TCP listener!");
+    let mut udp_buf = [0 as u8; 64];
+    let listener = UdpSocket::bind(("0.0.0.0", UDP_PORT)).expect("Could not bind UDP listener!");
     listener
         .set_nonblocking(false)
         .expect("could not set blocking mode for udp socket!");
@@ -217,75 +841,613 @@ fn main() -> Result<(), EspError> {
         .set_read_timeout(None)
         .expect("setting read timeout feailed!");
 
-    let mut esp_server = EspHttpServer::new(&HttpConfiguration::default()).unwrap();
+    let mut esp_server = EspHttpServer::new(&HttpConfiguration {
+        http_port: HTTP_PORT,
+        max_uri_handlers: 64,
+        uri_match_wildcard: true,
+        ..Default::default()
+    })
+    .unwrap();
 
-    let rgba_values = Arc::new(RwLock::new(RGBA8::new(0, 0, 0, 255)));
+    // the first zone is the one addressed by all zone-less endpoints and messages
+    let main_zone = Arc::new(Zone::new(0, SETTINGS.zone_name, storage.clone()));
+    let rgba_values = main_zone.rgba.clone();
+    let power = main_zone.power.clone();
+    let white_values = main_zone.white.clone();
+    let transition = main_zone.transition.clone();
+    let budget = main_zone.budget.clone();
+    let mut zone_list = vec![main_zone.clone()];
+    let mut renderers = vec![ZoneRenderer::new(main_zone, Output::Pwm(pwm_led))];
+    if let Some(output) = zone2_output {
+        let zone = Arc::new(Zone::new(1, SETTINGS.zone2_name, storage.clone()));
+        info!(
+            "Driving zone {} ({}) as {}",
+            zone.id, zone.name, SETTINGS.zone2
+        );
+        zone_list.push(zone.clone());
+        renderers.push(ZoneRenderer::new(zone, output));
+    }
+    let zones = Arc::new(zone_list);
+    let scenes = Arc::new(Mutex::new(Scenes::load(storage.clone())));
+    let clock = Arc::new(Clock::new(
+        TimeConfig {
+            ntp_server: SETTINGS.ntp_server.to_string(),
+            timezone: SETTINGS.timezone.to_string(),
+            latitude: SETTINGS.latitude,
+            longitude: SETTINGS.longitude,
+        },
+        storage.clone(),
+    )?);
+    let scheduler = Arc::new(Mutex::new(Scheduler::load(storage.clone())));
+    let alarm = Arc::new(Mutex::new(Alarm::load(storage.clone())));
+    let calibration = Arc::new(Mutex::new(Calibration::load(storage.clone())));
+    let thermal = Arc::new(Mutex::new(Thermal::load(storage.clone())));
+    thermal::spawn(thermal.clone(), ntc, metrics.clone());
+    let hue = Arc::new(Hue::load(
+        zones.clone(),
+        calibration.clone(),
+        storage.clone(),
+        SETTINGS.hostname,
+    ));
+    hue::spawn_ssdp(hue.clone());
+    let button = Arc::new(Mutex::new(Button::load(storage.clone())));
+    if let Some(gpio) = pin_config.button_pin {
+        button::spawn(
+            gpio,
+            button.clone(),
+            zones.clone(),
+            scenes.clone(),
+            storage.clone(),
+            hue.clone(),
+        );
+    }
+    let ir_remote = Arc::new(Mutex::new(IrRemote::load(storage.clone())));
+    if let Some(gpio) = pin_config.ir_pin {
+        ir_remote::spawn(
+            gpio,
+            ir_remote.clone(),
+            zones.clone(),
+            scenes.clone(),
+            calibration.clone(),
+        );
+    }
+    let identify = Arc::new(Identify::new(status_led.clone()));
+    let group_sync = Arc::new(Mutex::new(GroupSync::load(
+        storage.clone(),
+        SETTINGS.hostname,
+    )));
+    group_sync::spawn(group_sync.clone(), zones[0].clone());
+    let realtime = Arc::new(Realtime::load(storage.clone(), zones.len()));
+    wled::spawn_realtime(realtime.clone());
+    tpm2::spawn(realtime.clone());
+    ddp::spawn(realtime.clone());
+    let wled = Arc::new(Wled::new(
+        zones.clone(),
+        realtime.clone(),
+        SETTINGS.hostname,
+        SETTINGS.led_channels >= 4,
+    ));
+    let encoder = Arc::new(Mutex::new(Encoder::load(storage.clone())));
+    if let Some(gpios) = pin_config.encoder_pins {
+        encoder::spawn(
+            gpios,
+            pin_config.encoder_switch_pin,
+            encoder.clone(),
+            zones.clone(),
+        );
+    }
 
     esp_server
         .handler(
             "/getRGBA",
             Method::Get,
-            GetRGBAHandler::new(rgba_values.clone()),
+            MeteredHandler::new(
+                "/getRGBA",
+                GetRGBAHandler::new(rgba_values.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/getState",
+            Method::Get,
+            MeteredHandler::new(
+                "/getState",
+                GetStateHandler::new(rgba_values.clone(), power.clone(), budget.clone()),
+                metrics.clone(),
+            ),
         )
         .unwrap();
 
+    for (route, action) in [
+        ("/on", PowerAction::On),
+        ("/off", PowerAction::Off),
+        ("/toggle", PowerAction::Toggle),
+        ("/power", PowerAction::Config),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    PowerHandler::new(action, rgba_values.clone(), power.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
     esp_server
         .handler(
             "/setRGBA",
             Method::Get,
-            SetRGBAHandler::new(rgba_values.clone()),
+            MeteredHandler::new(
+                "/setRGBA",
+                SetRGBAHandler::new(rgba_values.clone(), white_values.clone(), power.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    for method in [Method::Get, Method::Post] {
+        esp_server
+            .handler(
+                "/setCT",
+                method,
+                MeteredHandler::new(
+                    "/setCT",
+                    SetCTHandler::new(rgba_values.clone(), calibration.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    esp_server
+        .handler(
+            "/white",
+            Method::Get,
+            MeteredHandler::new(
+                "/white",
+                WhiteHandler::new(white_values.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    for (route, action) in [
+        ("/zones", ZoneAction::List),
+        ("/zones/*", ZoneAction::State),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    ZoneHandler::new(action, zones.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    esp_server
+        .handler(
+            "/thermal",
+            Method::Get,
+            MeteredHandler::new(
+                "/thermal",
+                ThermalHandler::new(thermal.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/calibration",
+            Method::Get,
+            MeteredHandler::new(
+                "/calibration",
+                CalibrationHandler::new(calibration.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/pins",
+            Method::Get,
+            MeteredHandler::new("/pins", PinsHandler::new(pins.clone()), metrics.clone()),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/button",
+            Method::Get,
+            MeteredHandler::new(
+                "/button",
+                ButtonHandler::new(button.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/encoder",
+            Method::Get,
+            MeteredHandler::new(
+                "/encoder",
+                EncoderHandler::new(encoder.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/identify",
+            Method::Get,
+            MeteredHandler::new(
+                "/identify",
+                IdentifyHandler::new(identify.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/sync",
+            Method::Get,
+            MeteredHandler::new(
+                "/sync",
+                SyncHandler::new(group_sync.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    for (route, action) in [
+        ("/json", WledAction::All),
+        ("/json/state", WledAction::State),
+        ("/json/info", WledAction::Info),
+        ("/json/eff", WledAction::Effects),
+        ("/json/pal", WledAction::Palettes),
+    ] {
+        for method in [Method::Get, Method::Post] {
+            esp_server
+                .handler(
+                    route,
+                    method,
+                    MeteredHandler::new(
+                        route,
+                        WledHandler::new(action, wled.clone()),
+                        metrics.clone(),
+                    ),
+                )
+                .unwrap();
+        }
+    }
+
+    esp_server
+        .handler(
+            "/realtime",
+            Method::Get,
+            MeteredHandler::new(
+                "/realtime",
+                RealtimeHandler::new(realtime.clone()),
+                metrics.clone(),
+            ),
         )
         .unwrap();
 
     esp_server
-        .fn_handler("/health", Method::Get, |request| {
-            let mut response = request.into_ok_response()?;
-            response.write_all(b"I am alive")?;
-            response.flush()?;
-            Ok(())
-        })
+        .handler(
+            "/hue",
+            Method::Get,
+            MeteredHandler::new("/hue", HueHandler::new(hue.clone()), metrics.clone()),
+        )
+        .unwrap();
+
+    for (route, method, action) in [
+        ("/description.xml", Method::Get, HueApiAction::Description),
+        ("/api", Method::Post, HueApiAction::Pair),
+        ("/api/*", Method::Get, HueApiAction::Resource),
+        ("/api/*", Method::Put, HueApiAction::Resource),
+    ] {
+        esp_server
+            .handler(
+                route,
+                method,
+                MeteredHandler::new(
+                    route,
+                    HueApiHandler::new(action, hue.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    for (route, action) in [
+        ("/ir", IrAction::Status),
+        ("/ir/codes", IrAction::Codes),
+        ("/ir/learn", IrAction::Learn),
+        ("/ir/map", IrAction::Map),
+        ("/ir/delete", IrAction::Delete),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    IrHandler::new(action, ir_remote.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    esp_server
+        .handler(
+            "/wifi",
+            Method::Get,
+            MeteredHandler::new(
+                "/wifi",
+                WifiHandler::new(storage.clone(), SETTINGS.ssid, false),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+
+    esp_server
+        .handler(
+            "/health",
+            Method::Get,
+            MeteredHandler::new("/health", HealthHandler::new(), metrics.clone()),
+        )
+        .unwrap();
+    esp_server
+        .handler(
+            "/help",
+            Method::Get,
+            MeteredHandler::new("/help", HelpHandler::new(), metrics.clone()),
+        )
+        .unwrap();
+    esp_server
+        .handler(
+            "/metrics",
+            Method::Get,
+            MeteredHandler::new(
+                "/metrics",
+                MetricsHandler::new(rgba_values.clone(), metrics.clone()),
+                metrics.clone(),
+            ),
+        )
+        .unwrap();
+    esp_server
+        .handler(
+            "/logs",
+            Method::Get,
+            MeteredHandler::new("/logs", LogsHandler::new(), metrics.clone()),
+        )
+        .unwrap();
+    esp_server
+        .handler(
+            "/logs/config",
+            Method::Get,
+            MeteredHandler::new("/logs/config", LogConfigHandler::new(), metrics.clone()),
+        )
         .unwrap();
+
+    // keep the mDNS responder alive for the whole runtime, failing to advertise is not fatal
+    let _mdns = match discovery::advertise(
+        SETTINGS.hostname,
+        discovery::led_type(layout),
+        &[
+            Service {
+                service_type: "_http",
+                proto: "_tcp",
+                port: HTTP_PORT,
+            },
+            Service {
+                service_type: "_stripebuddy",
+                proto: "_udp",
+                port: UDP_PORT,
+            },
+            // lets WLED apps discover the controller
+            Service {
+                service_type: "_wled",
+                proto: "_tcp",
+                port: HTTP_PORT,
+            },
+        ],
+    ) {
+        Ok(mdns) => Some(mdns),
+        Err(e) => {
+            error!("Could not start mDNS advertisement! Error: {:?}", e);
+            None
+        }
+    };
+
+    for (route, action) in [
+        ("/scenes", SceneAction::List),
+        ("/scenes/save", SceneAction::Save),
+        ("/scenes/recall", SceneAction::Recall),
+        ("/scenes/rename", SceneAction::Rename),
+        ("/scenes/delete", SceneAction::Delete),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    SceneHandler::new(
+                        action,
+                        rgba_values.clone(),
+                        transition.clone(),
+                        scenes.clone(),
+                    ),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
     esp_server
-        .handler("/help", Method::Get, HelpHandler::new())
+        .handler(
+            "/time",
+            Method::Get,
+            MeteredHandler::new("/time", TimeHandler::new(clock.clone()), metrics.clone()),
+        )
         .unwrap();
 
-    let rgba_udp = rgba_values.clone();
+    for (route, action) in [
+        ("/schedules", ScheduleAction::List),
+        ("/schedules/add", ScheduleAction::Add),
+        ("/schedules/enable", ScheduleAction::Enable),
+        ("/schedules/delete", ScheduleAction::Delete),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    ScheduleHandler::new(action, scheduler.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    scheduler::spawn(
+        scheduler.clone(),
+        clock.clone(),
+        rgba_values.clone(),
+        transition.clone(),
+        scenes.clone(),
+        power.clone(),
+    );
+
+    for (route, action) in [
+        ("/alarm", AlarmAction::Config),
+        ("/alarm/start", AlarmAction::Start),
+        ("/alarm/snooze", AlarmAction::Snooze),
+        ("/alarm/cancel", AlarmAction::Cancel),
+    ] {
+        esp_server
+            .handler(
+                route,
+                Method::Get,
+                MeteredHandler::new(
+                    route,
+                    AlarmHandler::new(action, alarm.clone()),
+                    metrics.clone(),
+                ),
+            )
+            .unwrap();
+    }
+
+    alarm::spawn(
+        alarm.clone(),
+        clock.clone(),
+        rgba_values.clone(),
+        transition.clone(),
+        power.clone(),
+    );
+
+    let zones_udp = zones.clone();
+    let scenes_udp = scenes.clone();
+    let calibration_udp = calibration.clone();
+    let metrics_udp = metrics.clone();
+    let identify_udp = identify.clone();
     std::thread::spawn(move || loop {
         let (number_of_bytes, _) = listener.recv_from(&mut udp_buf).unwrap();
         if number_of_bytes < 1 {
+            metrics_udp.record_udp_packet(false);
             continue;
         }
-        let mut rgba_rwlock = match rgba_udp.write() {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("could not get write lock for rgba_udp! Error: {}", e);
-                continue;
+        // identifying addresses the whole controller, not a zone
+        if let Some(accepted) = handle_udp_identify_msg(&udp_buf[0..number_of_bytes], &identify_udp)
+        {
+            metrics_udp.record_udp_packet(accepted);
+            continue;
+        }
+        let (target, msg) = split_udp_zone_prefix(&udp_buf[0..number_of_bytes]);
+        let accepted = match zones::resolve(&zones_udp, target) {
+            Some(zones) => {
+                // every addressed zone gets the message, even if it was rejected by another one
+                let mut accepted = true;
+                for zone in zones {
+                    accepted &= handle_udp_msg(msg, zone, &scenes_udp, &calibration_udp);
+                }
+                accepted
+            }
+            None => {
+                warn!("received message for unknown zone: {:?}", target);
+                false
             }
         };
-        update_rgba_from_udp_msg(&udp_buf[0..number_of_bytes], &mut rgba_rwlock);
-        drop(rgba_rwlock);
+        metrics_udp.record_udp_packet(accepted);
     });
 
-    let mut curr_rgb = RGB8 { r: 0, g: 0, b: 0 };
-    let mut last_rgb = RGB8 { r: 0, g: 0, b: 0 };
+    let mut last_wifi_check = Instant::now();
 
     loop {
-        let rgba_read = match rgba_values.read() {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("could not get read lock for rgba_read! Error: {}", e);
-                continue;
+        let frame_start = Instant::now();
+
+        // periodically check the wifi connection and reconnect if it was lost
+        if last_wifi_check.elapsed() >= WIFI_CHECK_INTERVAL {
+            last_wifi_check = Instant::now();
+            let connected = wifi_driver.is_connected().unwrap_or(false);
+            status_led.set(StatusCode::Connecting, !connected);
+            if connected {
+                status_led.set(StatusCode::WifiError, false);
+            } else {
+                warn!("Lost wifi connection, reconnecting...");
+                metrics.record_wifi_reconnect();
+                if let Err(e) = wifi_driver.connect() {
+                    error!("Could not reconnect to wifi! Error: {:?}", e);
+                }
+            }
+        }
+
+        let identify_color = identify.color();
+        let max_level = match thermal.lock() {
+            Ok(thermal) => {
+                status_led.set(
+                    StatusCode::Overheated,
+                    thermal.status() == DeratingStatus::Critical,
+                );
+                thermal.level()
             }
+            Err(_) => 1.0,
         };
-        (*rgba_read).update_channels(&mut curr_rgb);
-        drop(rgba_read);
-
-        if curr_rgb != last_rgb {
-            pwm_led
-                .set_color(&curr_rgb)
-                .expect("could not set color for pwm!");
-            last_rgb = curr_rgb;
+        for renderer in renderers.iter_mut() {
+            let override_color = identify_color.or_else(|| realtime.color(renderer.zone().id));
+            if let Err(e) = renderer.render(&calibration, max_level, override_color) {
+                error!("could not render zone {}! Error: {}", renderer.zone().id, e);
+            }
+            if let Ok(budget) = renderer.zone().budget.lock() {
+                metrics.record_power_draw(
+                    renderer.zone().id,
+                    budget.estimate_w(),
+                    budget.is_limited(),
+                );
+            }
         }
+        metrics.record_frame_time(frame_start.elapsed());
+
         // sleeping for 25ms, so we can reach ~30 updates per second
         std::thread::sleep(Duration::from_millis(25));
     }
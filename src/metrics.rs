@@ -0,0 +1,294 @@
+//! Runtime telemetry exposed in the Prometheus text exposition format
+//!
+//! Counters are collected by the HTTP handlers, the UDP thread and the render loop and are
+//! rendered on request by the `/metrics` endpoint.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use esp_idf_sys::{esp, esp_get_free_heap_size, esp_timer_get_time, esp_wifi_sta_get_ap_info};
+
+use crate::rgb_led::RGBA8;
+
+const PREFIX: &str = "ledstripe";
+
+#[derive(Default)]
+struct FrameTime {
+    sum_seconds: f64,
+    count: u32,
+    last_seconds: f64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    // (route, status code, count)
+    http_requests: Mutex<Vec<(&'static str, u16, u32)>>,
+    udp_received: AtomicU32,
+    udp_rejected: AtomicU32,
+    wifi_reconnects: AtomicU32,
+    frame_time: Mutex<FrameTime>,
+    // (zone id, estimated watts, limited)
+    power_draw: Mutex<Vec<(u8, f32, bool)>>,
+    thermal: Mutex<Option<ThermalReading>>,
+}
+
+#[derive(Clone, Copy)]
+struct ThermalReading {
+    chip_c: Option<f32>,
+    ntc_c: Option<f32>,
+    // output level after derating
+    level: f32,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        return Metrics::default();
+    }
+
+    pub fn record_http_request(&self, route: &'static str, status: u16) {
+        let mut requests = match self.http_requests.lock() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        match requests
+            .iter_mut()
+            .find(|(r, s, _)| *r == route && *s == status)
+        {
+            Some((_, _, count)) => *count += 1,
+            None => requests.push((route, status, 1)),
+        }
+    }
+
+    pub fn record_udp_packet(&self, accepted: bool) {
+        self.udp_received.fetch_add(1, Ordering::Relaxed);
+        if !accepted {
+            self.udp_rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_wifi_reconnect(&self) {
+        self.wifi_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_frame_time(&self, duration: Duration) {
+        if let Ok(mut frame_time) = self.frame_time.lock() {
+            let seconds = duration.as_secs_f64();
+            frame_time.sum_seconds += seconds;
+            frame_time.count = frame_time.count.wrapping_add(1);
+            frame_time.last_seconds = seconds;
+        }
+    }
+
+    pub fn record_power_draw(&self, zone_id: u8, watts: f32, limited: bool) {
+        let mut power_draw = match self.power_draw.lock() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+        match power_draw.iter_mut().find(|(id, _, _)| *id == zone_id) {
+            Some(entry) => *entry = (zone_id, watts, limited),
+            None => power_draw.push((zone_id, watts, limited)),
+        }
+    }
+
+    pub fn record_thermal(&self, chip_c: Option<f32>, ntc_c: Option<f32>, level: f32) {
+        if let Ok(mut thermal) = self.thermal.lock() {
+            *thermal = Some(ThermalReading {
+                chip_c,
+                ntc_c,
+                level,
+            });
+        }
+    }
+
+    /// Renders all metrics plus the current system readings and channel values
+    pub fn render(&self, rgba: &RGBA8) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "HTTP requests by route and status",
+        );
+        if let Ok(requests) = self.http_requests.lock() {
+            for (route, status, count) in requests.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                    PREFIX, route, status, count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "udp_packets_received_total",
+            "counter",
+            "Received UDP control packets",
+        );
+        sample(
+            &mut out,
+            "udp_packets_received_total",
+            self.udp_received.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "udp_packets_rejected_total",
+            "counter",
+            "Rejected malformed UDP control packets",
+        );
+        sample(
+            &mut out,
+            "udp_packets_rejected_total",
+            self.udp_rejected.load(Ordering::Relaxed),
+        );
+
+        if let Ok(frame_time) = self.frame_time.lock() {
+            header(
+                &mut out,
+                "render_frame_seconds",
+                "summary",
+                "Time spent per render loop frame",
+            );
+            sample(&mut out, "render_frame_seconds_sum", frame_time.sum_seconds);
+            sample(&mut out, "render_frame_seconds_count", frame_time.count);
+            header(
+                &mut out,
+                "render_last_frame_seconds",
+                "gauge",
+                "Time spent in the last render loop frame",
+            );
+            sample(
+                &mut out,
+                "render_last_frame_seconds",
+                frame_time.last_seconds,
+            );
+        }
+
+        if let Ok(power_draw) = self.power_draw.lock() {
+            header(
+                &mut out,
+                "power_draw_watts",
+                "gauge",
+                "Estimated power draw per zone",
+            );
+            for (zone, watts, _) in power_draw.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}_power_draw_watts{{zone=\"{}\"}} {:.2}",
+                    PREFIX, zone, watts
+                );
+            }
+            header(
+                &mut out,
+                "power_limited",
+                "gauge",
+                "1 if the output of the zone is scaled down to the power budget",
+            );
+            for (zone, _, limited) in power_draw.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}_power_limited{{zone=\"{}\"}} {}",
+                    PREFIX, zone, *limited as u8
+                );
+            }
+        }
+
+        let thermal = self.thermal.lock().ok().and_then(|thermal| *thermal);
+        if let Some(reading) = thermal {
+            header(
+                &mut out,
+                "temperature_celsius",
+                "gauge",
+                "Temperature of the on-chip sensor and the NTC",
+            );
+            for (sensor, celsius) in [("chip", reading.chip_c), ("ntc", reading.ntc_c)] {
+                if let Some(celsius) = celsius {
+                    let _ = writeln!(
+                        out,
+                        "{}_temperature_celsius{{sensor=\"{}\"}} {:.1}",
+                        PREFIX, sensor, celsius
+                    );
+                }
+            }
+            header(
+                &mut out,
+                "thermal_output_level",
+                "gauge",
+                "Maximum output level after thermal derating (0 - 1)",
+            );
+            sample(&mut out, "thermal_output_level", reading.level);
+        }
+
+        if let Some(rssi) = wifi_rssi() {
+            header(
+                &mut out,
+                "wifi_rssi_dbm",
+                "gauge",
+                "Signal strength of the connected access point",
+            );
+            sample(&mut out, "wifi_rssi_dbm", rssi);
+        }
+        header(
+            &mut out,
+            "wifi_reconnects_total",
+            "counter",
+            "Reconnects after the wifi connection was lost",
+        );
+        sample(
+            &mut out,
+            "wifi_reconnects_total",
+            self.wifi_reconnects.load(Ordering::Relaxed),
+        );
+
+        header(
+            &mut out,
+            "free_heap_bytes",
+            "gauge",
+            "Currently free heap memory",
+        );
+        sample(&mut out, "free_heap_bytes", unsafe {
+            esp_get_free_heap_size()
+        });
+        header(&mut out, "uptime_seconds", "gauge", "Time since boot");
+        sample(
+            &mut out,
+            "uptime_seconds",
+            unsafe { esp_timer_get_time() } / 1_000_000,
+        );
+
+        header(
+            &mut out,
+            "channel_value",
+            "gauge",
+            "Currently set channel values",
+        );
+        for (channel, value) in [("r", rgba.r), ("g", rgba.g), ("b", rgba.b), ("a", rgba.a)] {
+            let _ = writeln!(
+                out,
+                "{}_channel_value{{channel=\"{}\"}} {}",
+                PREFIX, channel, value
+            );
+        }
+
+        return out;
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, metric_type);
+}
+
+fn sample(out: &mut String, name: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{}_{} {}", PREFIX, name, value);
+}
+
+/// returns the RSSI of the currently connected access point, None when not connected
+pub fn wifi_rssi() -> Option<i8> {
+    let mut ap_info = Default::default();
+    esp!(unsafe { esp_wifi_sta_get_ap_info(&mut ap_info) }).ok()?;
+    return Some(ap_info.rssi);
+}
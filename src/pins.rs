@@ -0,0 +1,216 @@
+//! Pin mapping of the PWM outputs, the status LED and the local inputs
+//!
+//! The mapping is stored in the NVS and only read at boot, so changes take effect after a
+//! restart. Pins the ESP32-C3 needs for the SPI flash (GPIO 11 - 17) or USB (GPIO 18/ 19) are
+//! rejected.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use esp_idf_hal::gpio::{AnyInputPin, AnyOutputPin};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "pins";
+/// highest GPIO number of the ESP32-C3
+const MAX_GPIO: u8 = 21;
+/// GPIOs used by the SPI flash (11 - 17) and the USB serial/ JTAG controller (18/ 19)
+const RESERVED_GPIOS: [u8; 9] = [11, 12, 13, 14, 15, 16, 17, 18, 19];
+pub const MIN_FREQUENCY_HZ: u32 = 100;
+pub const MAX_FREQUENCY_HZ: u32 = 40_000;
+
+/// color shown on each of the first three outputs, e.g. `Grb` drives green on the first pin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ChannelOrder {
+    /// index of the r,g,b channel shown on each output
+    pub fn indices(&self) -> [usize; 3] {
+        return match self {
+            ChannelOrder::Rgb => [0, 1, 2],
+            ChannelOrder::Rbg => [0, 2, 1],
+            ChannelOrder::Grb => [1, 0, 2],
+            ChannelOrder::Gbr => [1, 2, 0],
+            ChannelOrder::Brg => [2, 0, 1],
+            ChannelOrder::Bgr => [2, 1, 0],
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ChannelOrder::Rgb => "rgb",
+            ChannelOrder::Rbg => "rbg",
+            ChannelOrder::Grb => "grb",
+            ChannelOrder::Gbr => "gbr",
+            ChannelOrder::Brg => "brg",
+            ChannelOrder::Bgr => "bgr",
+        };
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "rgb" => Ok(ChannelOrder::Rgb),
+            "rbg" => Ok(ChannelOrder::Rbg),
+            "grb" => Ok(ChannelOrder::Grb),
+            "gbr" => Ok(ChannelOrder::Gbr),
+            "brg" => Ok(ChannelOrder::Brg),
+            "bgr" => Ok(ChannelOrder::Bgr),
+            _ => Err("unknown channel order"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinConfig {
+    /// GPIO per PWM output: the three color outputs, then white/ warm white and cold white
+    pub pins: [u8; 5],
+    pub order: ChannelOrder,
+    /// inverted outputs (common anode stripes, P-channel MOSFETs), in the order of `pins`
+    pub inverted: [bool; 5],
+    pub frequency_hz: u32,
+    /// GPIO of the WS2812 status LED
+    pub status_pin: u8,
+    /// GPIOs of a pwm zone2, in r, g, b order
+    pub zone2_pins: [u8; 3],
+    /// GPIO of the push button (to ground, uses the internal pull-up), None without button
+    pub button_pin: Option<u8>,
+    /// GPIO of the demodulated IR receiver output, None without receiver
+    pub ir_pin: Option<u8>,
+    /// GPIOs of the A and B pin of a rotary encoder, None without encoder
+    pub encoder_pins: Option<[u8; 2]>,
+    /// GPIO of the encoder push switch (to ground, uses the internal pull-up)
+    pub encoder_switch_pin: Option<u8>,
+}
+
+impl Default for PinConfig {
+    fn default() -> Self {
+        return PinConfig {
+            pins: [1, 2, 3, 4, 5],
+            order: ChannelOrder::Rgb,
+            inverted: [false; 5],
+            frequency_hz: 1000,
+            status_pin: 8,
+            zone2_pins: [6, 7, 10],
+            button_pin: None,
+            ir_pin: None,
+            encoder_pins: None,
+            encoder_switch_pin: None,
+        };
+    }
+}
+
+/// Pin config of the running outputs and validation of new configs
+pub struct Pins {
+    active: PinConfig,
+    // number of PWM outputs of the main stripe
+    channels: usize,
+    zone2_pwm: bool,
+    // pins used outside of the config, e.g. by a ws2812 zone2
+    extra_pins: Vec<u8>,
+    storage: Arc<Storage>,
+}
+
+impl Pins {
+    /// loads the saved pin config, falls back to the default config if it is invalid
+    pub fn load(
+        storage: Arc<Storage>,
+        channels: usize,
+        zone2_pwm: bool,
+        extra_pins: Vec<u8>,
+    ) -> Pins {
+        let mut pins = Pins {
+            active: PinConfig::default(),
+            channels,
+            zone2_pwm,
+            extra_pins,
+            storage,
+        };
+        let config: PinConfig = pins.storage.load(STORAGE_KEY).unwrap_or_default();
+        match pins.validate(&config) {
+            Ok(_) => pins.active = config,
+            Err(e) => error!("Stored pin config is invalid, using defaults! Error: {}", e),
+        }
+        return pins;
+    }
+
+    /// the config the outputs were created with
+    pub fn active(&self) -> &PinConfig {
+        return &self.active;
+    }
+
+    /// the config used after the next restart
+    pub fn pending(&self) -> PinConfig {
+        return self.storage.load(STORAGE_KEY).unwrap_or_default();
+    }
+
+    /// validates and saves the config for the next restart
+    pub fn store(&self, config: &PinConfig) -> Result<(), &'static str> {
+        self.validate(config)?;
+        return self.storage.store(STORAGE_KEY, config);
+    }
+
+    fn validate(&self, config: &PinConfig) -> Result<(), &'static str> {
+        if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&config.frequency_hz) {
+            return Err("pwm frequency out of range");
+        }
+        let zone2_pins: &[u8] = if self.zone2_pwm {
+            &config.zone2_pins
+        } else {
+            &[]
+        };
+        let mut used: Vec<u8> = Vec::new();
+        for pin in config
+            .pins
+            .iter()
+            .take(self.channels)
+            .chain([&config.status_pin])
+            .chain(zone2_pins)
+            .chain(&config.button_pin)
+            .chain(&config.ir_pin)
+            .chain(config.encoder_pins.iter().flatten())
+            .chain(&config.encoder_switch_pin)
+            .chain(&self.extra_pins)
+        {
+            if *pin > MAX_GPIO {
+                return Err("unknown gpio");
+            }
+            if RESERVED_GPIOS.contains(pin) {
+                return Err("gpio is reserved for flash/ usb");
+            }
+            if used.contains(pin) {
+                return Err("gpio is used twice");
+            }
+            used.push(*pin);
+        }
+        return Ok(());
+    }
+}
+
+// Safety: only gpios of the active config are passed in, which passed `Pins::validate` at boot.
+// So each gpio exists, is not reserved and is claimed by exactly one input or output.
+
+/// creates the input pin for a gpio of the active pin config
+pub fn input_pin(gpio: u8) -> AnyInputPin {
+    return unsafe { AnyInputPin::new(gpio.into()) };
+}
+
+/// creates the output pin for a gpio of the active pin config
+pub fn output_pin(gpio: u8) -> AnyOutputPin {
+    return unsafe { AnyOutputPin::new(gpio.into()) };
+}
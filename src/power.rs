@@ -0,0 +1,143 @@
+//! On/off power state, kept separately from the set color
+//!
+//! Turning the stripe off does not touch the set `RGBA8`, so the brightness is restored when
+//! turning it on again. Switching fades the output over the configured (or given) duration, the
+//! render loop scales the brightness with the current fade level.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rgb_led::RGBA8;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "power";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerConfig {
+    /// default fade duration when turning on, in milliseconds
+    pub fade_on_ms: u32,
+    /// default fade duration when turning off, in milliseconds
+    pub fade_off_ms: u32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        return PowerConfig {
+            fade_on_ms: 500,
+            fade_off_ms: 500,
+        };
+    }
+}
+
+pub struct Power {
+    on: bool,
+    // fade level (0.0 - 1.0) when the last switch started
+    from_level: f32,
+    start: Instant,
+    duration: Duration,
+    config: PowerConfig,
+    storage_key: String,
+    storage: Arc<Storage>,
+}
+
+impl Power {
+    /// loads the previously saved power config of the zone from the storage,
+    /// the zone starts powered on
+    pub fn load(storage: Arc<Storage>, zone_id: u8) -> Power {
+        // the first zone keeps the key used before zones existed
+        let storage_key = match zone_id {
+            0 => STORAGE_KEY.to_string(),
+            id => format!("{}{}", STORAGE_KEY, id),
+        };
+        let config = storage.load(&storage_key).unwrap_or_default();
+        return Power {
+            on: true,
+            from_level: 1.0,
+            start: Instant::now(),
+            duration: Duration::ZERO,
+            config,
+            storage_key,
+            storage,
+        };
+    }
+
+    pub fn is_on(&self) -> bool {
+        return self.on;
+    }
+
+    pub fn config(&self) -> &PowerConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: PowerConfig) -> Result<(), &'static str> {
+        self.storage.store(&self.storage_key, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+
+    /// switches the power state, fading over `duration` or the configured default duration
+    pub fn set(&mut self, on: bool, duration: Option<Duration>) {
+        if on == self.on {
+            return;
+        }
+        let default_ms = if on {
+            self.config.fade_on_ms
+        } else {
+            self.config.fade_off_ms
+        };
+        // continue from the current level, if the last fade is still running
+        self.from_level = self.level();
+        self.start = Instant::now();
+        self.duration = duration.unwrap_or(Duration::from_millis(default_ms as u64));
+        self.on = on;
+    }
+
+    /// switches to the opposite power state, returns the new state
+    pub fn toggle(&mut self, duration: Option<Duration>) -> bool {
+        self.set(!self.on, duration);
+        return self.on;
+    }
+
+    /// point in time at which the running fade ends, None if no fade is running
+    pub fn fade_end(&self) -> Option<Instant> {
+        if self.start.elapsed() >= self.duration {
+            return None;
+        }
+        return Some(self.start + self.duration);
+    }
+
+    /// current fade level from 0.0 (off) to 1.0 (on)
+    pub fn level(&self) -> f32 {
+        return self.level_at(Instant::now());
+    }
+
+    /// fade level at the given point in time
+    fn level_at(&self, instant: Instant) -> f32 {
+        let target = if self.on { 1.0 } else { 0.0 };
+        let elapsed = instant.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return target;
+        }
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        return self.from_level + (target - self.from_level) * progress;
+    }
+
+    /// returns the color to show for the given color, scaled by the current fade level
+    pub fn apply(&self, color: RGBA8) -> RGBA8 {
+        return self.apply_at(color, Instant::now());
+    }
+
+    /// returns the color to show at the given point in time, scaled by the fade level
+    pub fn apply_at(&self, color: RGBA8, instant: Instant) -> RGBA8 {
+        let level = self.level_at(instant);
+        if level >= 1.0 {
+            return color;
+        }
+        return RGBA8 {
+            a: (color.a as f32 * level).round() as u8,
+            ..color
+        };
+    }
+}
@@ -0,0 +1,115 @@
+//! Power budget for long stripes
+//!
+//! The draw of a stripe is estimated from the output duty of every channel, the stripe length
+//! and the watts per meter each channel draws at full duty. When the estimate exceeds the
+//! budget of the supply, all channels are scaled down proportionally, so the color stays the
+//! same and only the brightness is reduced.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "budget";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// watts the supply can deliver to the stripe, 0 disables the limit
+    pub supply_watts: f32,
+    pub length_m: f32,
+    /// watts per meter of every channel (r, g, b, w/ww, cw) at full duty
+    pub watts_per_meter: [f32; 5],
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        // a common 14.4 W/m 5050 stripe, 5 m long
+        return BudgetConfig {
+            supply_watts: 0.0,
+            length_m: 5.0,
+            watts_per_meter: [4.8; 5],
+        };
+    }
+}
+
+pub struct PowerBudget {
+    config: BudgetConfig,
+    // estimated draw of the last rendered frame, after limiting
+    estimate_w: f32,
+    limited: bool,
+    storage_key: String,
+    storage: Arc<Storage>,
+}
+
+impl PowerBudget {
+    /// loads the previously saved budget of the zone from the storage
+    pub fn load(storage: Arc<Storage>, zone_id: u8) -> PowerBudget {
+        let storage_key = match zone_id {
+            0 => STORAGE_KEY.to_string(),
+            id => format!("{}{}", STORAGE_KEY, id),
+        };
+        let config = storage.load(&storage_key).unwrap_or_default();
+        return PowerBudget {
+            config,
+            estimate_w: 0.0,
+            limited: false,
+            storage_key,
+            storage,
+        };
+    }
+
+    pub fn config(&self) -> &BudgetConfig {
+        return &self.config;
+    }
+
+    pub fn set_config(&mut self, config: BudgetConfig) -> Result<(), &'static str> {
+        let values = [config.supply_watts, config.length_m];
+        if values
+            .iter()
+            .chain(config.watts_per_meter.iter())
+            .any(|val| !val.is_finite() || *val < 0.0)
+        {
+            return Err("budget values must be positive numbers");
+        }
+        self.storage.store(&self.storage_key, &config)?;
+        self.config = config;
+        return Ok(());
+    }
+
+    /// estimated draw of the last rendered frame in watts
+    pub fn estimate_w(&self) -> f32 {
+        return self.estimate_w;
+    }
+
+    /// true, if the last rendered frame was scaled down
+    pub fn is_limited(&self) -> bool {
+        return self.limited;
+    }
+
+    /// estimated draw in watts for the given channel duties
+    pub fn draw(&self, channels: &[u8; 5]) -> f32 {
+        return channels
+            .iter()
+            .zip(self.config.watts_per_meter)
+            .map(|(duty, watts)| *duty as f32 / 255.0 * watts * self.config.length_m)
+            .sum();
+    }
+
+    /// scales the channels down proportionally, if their estimated draw exceeds the budget
+    pub fn limit(&mut self, channels: &mut [u8; 5]) {
+        let estimate = self.draw(channels);
+        self.limited = self.config.supply_watts > 0.0 && estimate > self.config.supply_watts;
+        if self.limited {
+            let scale = self.config.supply_watts / estimate;
+            for channel in channels.iter_mut() {
+                // round down, so the result stays within the budget
+                *channel = (*channel as f32 * scale) as u8;
+            }
+            self.estimate_w = self.draw(channels);
+        } else {
+            self.estimate_w = estimate;
+        }
+    }
+}
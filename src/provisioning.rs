@@ -0,0 +1,84 @@
+//! Wifi provisioning through a temporary access point
+//!
+//! A provisioning request (very long button press or `/wifi?provision=1`) is stored in the NVS
+//! and the controller restarts into an open access point `HOSTNAME-setup`, which only serves
+//! `/wifi`. Stored credentials take precedence over the ones from `cfg.toml`. Without new
+//! credentials, the controller restarts into normal operation after `ACCESS_POINT_TIMEOUT`.
+
+use std::time::Duration;
+
+use embedded_svc::wifi::{AccessPointConfiguration, AuthMethod, Configuration, Wifi};
+use esp_idf_svc::wifi::EspWifi;
+use esp_idf_sys::EspError;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+const CREDENTIALS_KEY: &str = "wifi";
+const REQUEST_KEY: &str = "provision";
+pub const ACCESS_POINT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WifiCredentials {
+    pub ssid: String,
+    pub passphrase: String,
+}
+
+impl WifiCredentials {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err("invalid ssid length");
+        }
+        // WPA2 passphrases have 8 - 63 characters, 64 characters are a hex key
+        if !self.passphrase.is_empty() && !(8..=64).contains(&self.passphrase.len()) {
+            return Err("invalid passphrase length");
+        }
+        return Ok(());
+    }
+}
+
+/// returns the stored credentials, None if there are none
+pub fn load_credentials(storage: &Storage) -> Option<WifiCredentials> {
+    let credentials: WifiCredentials = storage.load(CREDENTIALS_KEY)?;
+    return credentials.validate().ok().map(|_| credentials);
+}
+
+/// validates and stores the credentials used after the next restart
+pub fn store_credentials(
+    storage: &Storage,
+    credentials: &WifiCredentials,
+) -> Result<(), &'static str> {
+    credentials.validate()?;
+    return storage.store(CREDENTIALS_KEY, credentials);
+}
+
+/// stores a provisioning request for the next restart
+pub fn request(storage: &Storage) -> Result<(), &'static str> {
+    info!("Provisioning requested");
+    return storage.store(REQUEST_KEY, &true);
+}
+
+/// returns true, if provisioning was requested, and clears the request
+pub fn take_request(storage: &Storage) -> bool {
+    let requested = storage.load(REQUEST_KEY).unwrap_or(false);
+    if requested {
+        // a failing access point must not keep the controller in provisioning forever
+        if let Err(e) = storage.store(REQUEST_KEY, &false) {
+            error!("Could not clear the provisioning request! Error: {}", e);
+        }
+    }
+    return requested;
+}
+
+/// starts the open access point `HOSTNAME-setup`
+pub fn start_access_point(wifi_driver: &mut EspWifi, hostname: &str) -> Result<(), EspError> {
+    let ssid = format!("{}-setup", hostname);
+    info!("Starting provisioning access point {:?}", ssid);
+    wifi_driver.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: ssid.as_str().into(),
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    }))?;
+    return wifi_driver.start();
+}
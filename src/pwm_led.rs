@@ -0,0 +1,175 @@
+use std::num::NonZeroI32;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use ::core::ffi::c_void;
+use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_sys::{
+    esp, ledc_cb_event_t_LEDC_FADE_END_EVT, ledc_cb_param_t, ledc_cb_register, ledc_cbs_t,
+    ledc_fade_func_install, ledc_fade_mode_t_LEDC_FADE_NO_WAIT, ledc_fade_start,
+    ledc_mode_t_LEDC_LOW_SPEED_MODE, ledc_set_fade_with_time, EspError,
+};
+
+use crate::pins::ChannelOrder;
+use crate::rgb_led::ChannelLayout;
+
+/// running hardware fade per LEDC channel, cleared by the fade end interrupt
+static FADING: [AtomicBool; 6] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+unsafe extern "C" fn fade_end(param: *const ledc_cb_param_t, _user_arg: *mut c_void) -> bool {
+    if let Some(param) = param.as_ref() {
+        if param.event == ledc_cb_event_t_LEDC_FADE_END_EVT {
+            if let Some(fading) = FADING.get(param.channel as usize) {
+                fading.store(false, Ordering::Release);
+            }
+        }
+    }
+    // no task has to be woken up
+    return false;
+}
+
+/// installs the LEDC fade service, has to be called once before enabling hardware fades
+pub fn install_fade_service() -> Result<(), EspError> {
+    return esp!(unsafe { ledc_fade_func_install(0) });
+}
+
+/// PWM output with one LEDC channel per color channel, the three color channels in the given
+/// order and optionally white (RGBW) or warm white and cold white (RGBWW)
+pub struct PwmLed<'a> {
+    drivers: Vec<LedcDriver<'a>>,
+    layout: ChannelLayout,
+    order: ChannelOrder,
+    // inverted duty per driver, for common anode stripes
+    inverted: [bool; 5],
+    // last set duty per driver, the driver does not know about hardware fades
+    duties: [u8; 5],
+    hardware_fade: bool,
+}
+
+impl<'a> PwmLed<'a> {
+    pub fn new(
+        drivers: Vec<LedcDriver<'a>>,
+        order: ChannelOrder,
+        inverted: [bool; 5],
+    ) -> Result<PwmLed<'a>, EspError> {
+        let layout = match ChannelLayout::from_channels(drivers.len()) {
+            Some(val) => val,
+            // 258 - ESP_ERR_INVALID_ARG
+            None => return Err(EspError::from_non_zero(NonZeroI32::new(258).unwrap())),
+        };
+        Ok(PwmLed {
+            drivers,
+            layout,
+            order,
+            inverted,
+            duties: [0; 5],
+            hardware_fade: false,
+        })
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        return self.layout;
+    }
+
+    /// registers the fade end notification of all channels, needs the installed fade service
+    pub fn enable_hardware_fade(&mut self) -> Result<(), EspError> {
+        for driver in self.drivers.iter() {
+            let mut callbacks = ledc_cbs_t {
+                fade_cb: Some(fade_end),
+            };
+            esp!(unsafe {
+                ledc_cb_register(
+                    ledc_mode_t_LEDC_LOW_SPEED_MODE,
+                    driver.channel(),
+                    &mut callbacks,
+                    null_mut(),
+                )
+            })?;
+        }
+        self.hardware_fade = true;
+        Ok(())
+    }
+
+    pub fn supports_fade(&self) -> bool {
+        return self.hardware_fade;
+    }
+
+    /// true while a hardware fade of any channel is running
+    pub fn is_fading(&self) -> bool {
+        return self
+            .drivers
+            .iter()
+            .any(|driver| FADING[driver.channel() as usize].load(Ordering::Acquire));
+    }
+
+    /// duty of the driver at `idx` for the channel values, in channel order and inverted
+    fn duty(&self, idx: usize, values: &[u8]) -> u8 {
+        let order = self.order.indices();
+        let channel = if idx < order.len() { order[idx] } else { idx };
+        let value = values.get(channel).copied().unwrap_or(0);
+        if self.inverted[idx] {
+            return u8::MAX - value;
+        }
+        return value;
+    }
+
+    /// sets the duty of every channel (r, g, b, w/ww, cw), additional values are ignored
+    pub fn set_channels(&mut self, values: &[u8]) -> Result<(), EspError> {
+        for idx in 0..self.drivers.len() {
+            let duty = self.duty(idx, values);
+            self.drivers[idx].set_duty(duty.into())?;
+            self.duties[idx] = duty;
+        }
+        Ok(())
+    }
+
+    /// fades every channel linearly to the given values in hardware, the duty must not be set
+    /// until `is_fading` returns false
+    pub fn fade_to(&mut self, values: &[u8], duration: Duration) -> Result<(), EspError> {
+        let fade_ms = duration.as_millis().min(i32::MAX as u128) as i32;
+        for idx in 0..self.drivers.len() {
+            let duty = self.duty(idx, values);
+            // unchanged channels would not report the end of the fade
+            if duty == self.duties[idx] {
+                continue;
+            }
+            let channel = self.drivers[idx].channel();
+            FADING[channel as usize].store(true, Ordering::Release);
+            let result = esp!(unsafe {
+                ledc_set_fade_with_time(
+                    ledc_mode_t_LEDC_LOW_SPEED_MODE,
+                    channel,
+                    duty.into(),
+                    fade_ms,
+                )
+            })
+            .and_then(|_| {
+                esp!(unsafe {
+                    ledc_fade_start(
+                        ledc_mode_t_LEDC_LOW_SPEED_MODE,
+                        channel,
+                        ledc_fade_mode_t_LEDC_FADE_NO_WAIT,
+                    )
+                })
+            });
+            if let Err(e) = result {
+                FADING[channel as usize].store(false, Ordering::Release);
+                return Err(e);
+            }
+            self.duties[idx] = duty;
+        }
+        Ok(())
+    }
+
+    pub fn set_off(&mut self) -> Result<(), EspError> {
+        return self.set_channels(&[0; 5]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Realtime streaming override of the zone colors
+//!
+//! Realtime protocols stream frames of pixel colors, every zone is one pixel (the main stripe
+//! is pixel 0, zone2 pixel 1). While frames arrive, the render loop shows them instead of the
+//! zone state, the zone state itself is not touched. Once no frame arrived for the timeout of
+//! the last frame, the zones show their state again. Protocols without own timeout (TPM2.net,
+//! DDP) use the configured timeout.
+
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::rgb_led::{RGB8, RGBA8};
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "realtime";
+const MIN_TIMEOUT_MS: u32 = 100;
+const MAX_TIMEOUT_MS: u32 = 600_000;
+/// largest datagram of the realtime protocols, one ethernet frame
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    /// time without frames until the zones show their state again
+    pub timeout_ms: u32,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        return RealtimeConfig { timeout_ms: 2500 };
+    }
+}
+
+impl RealtimeConfig {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !(MIN_TIMEOUT_MS..=MAX_TIMEOUT_MS).contains(&self.timeout_ms) {
+            return Err("realtime timeout out of range");
+        }
+        return Ok(());
+    }
+}
+
+struct Frame {
+    /// protocol of the last frame, e.g. `wled`
+    source: &'static str,
+    colors: Vec<Option<RGB8>>,
+    /// None streams until stopped
+    until: Option<Instant>,
+}
+
+pub struct Realtime {
+    frame: Mutex<Option<Frame>>,
+    /// number of zones, colors for further pixels are dropped
+    pixels: usize,
+    config: Mutex<RealtimeConfig>,
+    storage: Arc<Storage>,
+}
+
+impl Realtime {
+    /// loads the previously saved realtime config from the storage
+    pub fn load(storage: Arc<Storage>, pixels: usize) -> Realtime {
+        let config: RealtimeConfig = storage.load(STORAGE_KEY).unwrap_or_default();
+        let config = match config.validate() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!(
+                    "Stored realtime config is invalid, using defaults! Error: {}",
+                    e
+                );
+                RealtimeConfig::default()
+            }
+        };
+        return Realtime {
+            frame: Mutex::new(None),
+            pixels,
+            config: Mutex::new(config),
+            storage,
+        };
+    }
+
+    pub fn config(&self) -> RealtimeConfig {
+        return self
+            .config
+            .lock()
+            .map_or_else(|_| RealtimeConfig::default(), |config| config.clone());
+    }
+
+    pub fn set_config(&self, config: RealtimeConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        let mut current = self
+            .config
+            .lock()
+            .map_err(|_| "could not get realtime config lock")?;
+        self.storage.store(STORAGE_KEY, &config)?;
+        *current = config;
+        return Ok(());
+    }
+
+    /// the configured timeout for protocols without own timeout
+    pub fn timeout(&self) -> Duration {
+        return Duration::from_millis(self.config().timeout_ms.into());
+    }
+
+    /// shows `colors` on the zones from pixel `start` on, zones without color in this frame keep
+    /// the color of an earlier frame, `timeout` None streams until stopped
+    pub fn show(
+        &self,
+        source: &'static str,
+        start: usize,
+        colors: &[RGB8],
+        timeout: Option<Duration>,
+    ) -> Result<(), &'static str> {
+        let mut frame = self
+            .frame
+            .lock()
+            .map_err(|_| "could not get realtime lock")?;
+        let now = Instant::now();
+        let expired = frame.as_ref().map_or(true, |frame| {
+            frame.until.map_or(false, |until| until <= now)
+        });
+        if expired {
+            info!("Realtime streaming via {} started", source);
+        }
+        let frame = match &mut *frame {
+            Some(frame) if !expired => frame,
+            frame => frame.insert(Frame {
+                source,
+                colors: vec![None; self.pixels],
+                until: None,
+            }),
+        };
+        frame.source = source;
+        frame.until = timeout.map(|timeout| now + timeout);
+        if let Some(targets) = frame.colors.get_mut(start..) {
+            for (target, color) in targets.iter_mut().zip(colors) {
+                *target = Some(*color);
+            }
+        }
+        return Ok(());
+    }
+
+    /// ends streaming, the zones show their state again
+    pub fn stop(&self) -> Result<(), &'static str> {
+        let mut frame = self
+            .frame
+            .lock()
+            .map_err(|_| "could not get realtime lock")?;
+        if frame.take().is_some() {
+            info!("Realtime streaming stopped");
+        }
+        return Ok(());
+    }
+
+    /// protocol of the running stream, None if not streaming
+    pub fn source(&self) -> Option<&'static str> {
+        let frame = self.frame.lock().ok()?;
+        let frame = frame.as_ref()?;
+        if frame.until.map_or(false, |until| until <= Instant::now()) {
+            return None;
+        }
+        return Some(frame.source);
+    }
+
+    /// the streamed color of the zone, None if not streaming or no color was sent for it
+    pub fn color(&self, zone_id: u8) -> Option<RGBA8> {
+        self.source()?;
+        let frame = self.frame.lock().ok()?;
+        let color = (*frame.as_ref()?.colors.get(usize::from(zone_id))?)?;
+        return Some(color.alpha(u8::MAX));
+    }
+}
+
+/// Binds a UDP socket for a realtime protocol and passes every received datagram to `handle`
+/// in a background thread
+pub fn listen<F>(protocol: &'static str, port: u16, mut handle: F)
+where
+    F: FnMut(&[u8]) + Send + 'static,
+{
+    let socket = match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(val) => val,
+        Err(e) => {
+            error!("Could not bind the {} socket! Error: {:?}", protocol, e);
+            return;
+        }
+    };
+    info!("Listening for {} packets on port {}", protocol, port);
+
+    thread::spawn(move || {
+        // one datagram (up to 1500 bytes) is too large for the default thread stack
+        let mut buf = vec![0_u8; MAX_DATAGRAM_SIZE];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, _)) => handle(&buf[..len]),
+                Err(e) => warn!("Could not receive {} packet! Error: {:?}", protocol, e),
+            }
+        }
+    });
+}
@@ -1,3 +1,7 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
 pub use rgb::{RGB8, RGBA8};
 
 pub trait RGBABrightnessExt {
@@ -12,3 +16,654 @@ impl RGBABrightnessExt for RGBA8 {
         rgb.b = ((self.b as f64) * rel_brightness).round() as u8;
     }
 }
+
+/// Converts a perceived lightness (0.0 - 1.0, CIE 1976 L*) into the relative luminance
+/// (0.0 - 1.0) the LEDs have to emit, so brightness ramps look even to the human eye
+pub fn lightness_to_luminance(lightness: f64) -> f64 {
+    let l = lightness.clamp(0.0, 1.0) * 100.0;
+    if l <= 8.0 {
+        return l / 903.3;
+    }
+    return ((l + 16.0) / 116.0).powi(3);
+}
+
+/// Approximates the color of a black body radiator with the given color temperature,
+/// valid from 1000 K to 40000 K (algorithm by Tanner Helland)
+pub fn kelvin_to_rgb(kelvin: u16) -> RGB8 {
+    let temp = kelvin.clamp(1000, 40000) as f64 / 100.0;
+
+    let r = if temp <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (temp - 60.0).powf(-0.1332047592)
+    };
+    let g = if temp <= 66.0 {
+        99.4708025861 * temp.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (temp - 60.0).powf(-0.0755148492)
+    };
+    let b = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (temp - 10.0).ln() - 305.0447927307
+    };
+
+    return RGB8::new(
+        r.clamp(0.0, 255.0).round() as u8,
+        g.clamp(0.0, 255.0).round() as u8,
+        b.clamp(0.0, 255.0).round() as u8,
+    );
+}
+
+/// Converts hue (0 - 360 degrees), saturation and value (0 - 100 %) into r,g,b values
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> RGB8 {
+    let (s, v) = (s / 100.0, v / 100.0);
+    let chroma = v * s;
+    return hue_to_rgb(h, chroma, v - chroma);
+}
+
+/// Converts hue (0 - 360 degrees), saturation and lightness (0 - 100 %) into r,g,b values
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> RGB8 {
+    let (s, l) = (s / 100.0, l / 100.0);
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    return hue_to_rgb(h, chroma, l - chroma / 2.0);
+}
+
+/// returns hue (0 - 360 degrees), saturation and value (0 - 100 %)
+pub fn rgb_to_hsv(rgb: RGB8) -> (f32, f32, f32) {
+    let (max, min) = max_min(rgb);
+    let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+    return (hue(rgb, max, min), s * 100.0, max * 100.0);
+}
+
+/// returns hue (0 - 360 degrees), saturation and lightness (0 - 100 %)
+pub fn rgb_to_hsl(rgb: RGB8) -> (f32, f32, f32) {
+    let (max, min) = max_min(rgb);
+    let l = (max + min) / 2.0;
+    let s = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+    };
+    return (hue(rgb, max, min), s * 100.0, l * 100.0);
+}
+
+/// parses `RRGGBB` or `RRGGBBAA` with an optional leading `#`
+pub fn parse_hex(hex: &str) -> Option<(RGB8, Option<u8>)> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    // from_str_radix alone would accept a sign, e.g. `+F`
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let byte = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).ok();
+    let rgb = RGB8::new(byte(0)?, byte(2)?, byte(4)?);
+    let alpha = match hex.len() {
+        8 => Some(byte(6)?),
+        _ => None,
+    };
+    return Some((rgb, alpha));
+}
+
+fn max_min(rgb: RGB8) -> (f32, f32) {
+    let max = rgb.r.max(rgb.g).max(rgb.b) as f32 / 255.0;
+    let min = rgb.r.min(rgb.g).min(rgb.b) as f32 / 255.0;
+    return (max, min);
+}
+
+fn hue(rgb: RGB8, max: f32, min: f32) -> f32 {
+    let delta = max - min;
+    if delta == 0.0 {
+        return 0.0;
+    }
+    let (r, g, b) = (
+        rgb.r as f32 / 255.0,
+        rgb.g as f32 / 255.0,
+        rgb.b as f32 / 255.0,
+    );
+    let sector = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    return sector * 60.0;
+}
+
+fn hue_to_rgb(h: f32, chroma: f32, m: f32) -> RGB8 {
+    let sector = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let to_u8 = |value: f32| ((value + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    return RGB8::new(to_u8(r), to_u8(g), to_u8(b));
+}
+
+/// Output formats for the current color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// `r,g,b,a`
+    Rgba,
+    /// `h,s,v,a`
+    Hsv,
+    /// `h,s,l,a`
+    Hsl,
+    /// `#RRGGBBAA`
+    Hex,
+}
+
+impl FromStr for ColorFormat {
+    type Err = &'static str;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        return match format {
+            "rgba" => Ok(ColorFormat::Rgba),
+            "hsv" => Ok(ColorFormat::Hsv),
+            "hsl" => Ok(ColorFormat::Hsl),
+            "hex" => Ok(ColorFormat::Hex),
+            _ => Err("unknown color format"),
+        };
+    }
+}
+
+impl ColorFormat {
+    pub fn format(&self, color: &RGBA8) -> String {
+        return match self {
+            ColorFormat::Rgba => format!("{},{},{},{}", color.r, color.g, color.b, color.a),
+            ColorFormat::Hsv => {
+                let (h, s, v) = rgb_to_hsv(color.rgb());
+                format!("{:.1},{:.1},{:.1},{}", h, s, v, color.a)
+            }
+            ColorFormat::Hsl => {
+                let (h, s, l) = rgb_to_hsl(color.rgb());
+                format!("{:.1},{:.1},{:.1},{}", h, s, l, color.a)
+            }
+            ColorFormat::Hex => format!(
+                "#{:02X}{:02X}{:02X}{:02X}",
+                color.r, color.g, color.b, color.a
+            ),
+        };
+    }
+}
+
+/// An absolute value or a relative step, parsed from `20`, `+20` or `-10`
+///
+/// Relative steps never fail: channels saturate at 0/ 255, saturation, value and lightness at
+/// 0/ 100 % and the hue wraps around at 360 degrees. Absolute values out of range are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "AdjustmentValue")]
+pub enum Adjustment {
+    Absolute(f32),
+    Relative(f32),
+}
+
+// JSON APIs accept absolute values as numbers and both absolute and relative values as strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AdjustmentValue {
+    Number(f32),
+    Text(String),
+}
+
+impl TryFrom<AdjustmentValue> for Adjustment {
+    type Error = &'static str;
+
+    fn try_from(value: AdjustmentValue) -> Result<Self, Self::Error> {
+        return match value {
+            AdjustmentValue::Number(val) => Ok(Adjustment::Absolute(val)),
+            AdjustmentValue::Text(val) => Adjustment::from_str(&val),
+        };
+    }
+}
+
+impl FromStr for Adjustment {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // an unencoded `+` in a query string is decoded as space
+        let is_relative = value.starts_with(['+', '-', ' ']);
+        let parsed = value.trim().parse::<f32>().map_err(|_| "invalid value")?;
+        if !parsed.is_finite() {
+            return Err("invalid value");
+        }
+        if is_relative {
+            return Ok(Adjustment::Relative(parsed));
+        }
+        return Ok(Adjustment::Absolute(parsed));
+    }
+}
+
+impl Adjustment {
+    /// applies the adjustment to a channel value (0 - 255)
+    pub fn apply_channel(&self, value: u8) -> Result<u8, &'static str> {
+        return match *self {
+            Adjustment::Absolute(val) if val.fract() == 0.0 && (0.0..=255.0).contains(&val) => {
+                Ok(val as u8)
+            }
+            Adjustment::Absolute(_) => Err("channel value out of range"),
+            Adjustment::Relative(step) => Ok((value as f32 + step).round().clamp(0.0, 255.0) as u8),
+        };
+    }
+
+    /// applies the adjustment to a percent value (0 - 100)
+    pub fn apply_percent(&self, value: f32) -> Result<f32, &'static str> {
+        return match *self {
+            Adjustment::Absolute(val) if (0.0..=100.0).contains(&val) => Ok(val),
+            Adjustment::Absolute(_) => Err("percent value out of range"),
+            Adjustment::Relative(step) => Ok((value + step).clamp(0.0, 100.0)),
+        };
+    }
+
+    /// applies the adjustment to a hue (0 - 360 degrees), relative steps rotate the hue
+    pub fn apply_hue(&self, value: f32) -> Result<f32, &'static str> {
+        return match *self {
+            Adjustment::Absolute(val) if (0.0..=360.0).contains(&val) => Ok(val),
+            Adjustment::Absolute(_) => Err("hue value out of range"),
+            Adjustment::Relative(step) => Ok((value + step).rem_euclid(360.0)),
+        };
+    }
+}
+
+/// returns true for the parameter keys handled by `apply_color_params`
+pub fn is_color_param(key: &str) -> bool {
+    return matches!(key, "r" | "g" | "b" | "a" | "h" | "s" | "v" | "l" | "hex");
+}
+
+/// Applies the channel (`r`, `g`, `b`, `a`), hsv (`h`, `s`, `v`), hsl (`h`, `s`, `l`) and `hex`
+/// parameters to the color, other keys are ignored. All values except hex can be relative
+/// (see `Adjustment`). The single channels are applied first, missing or relative hsv/ hsl
+/// components are taken from the resulting color, so e.g. `h=+30` only rotates the hue.
+/// Returns false, if no color parameter was given.
+pub fn apply_color_params<K: AsRef<str>, V: AsRef<str>>(
+    color: &mut RGBA8,
+    params: &[(K, V)],
+) -> Result<bool, &'static str> {
+    let mut updated = false;
+    let mut h = None;
+    let mut s = None;
+    let mut v = None;
+    let mut l = None;
+    let mut hex = None;
+    for (key, value) in params {
+        let value = value.as_ref();
+        let channel = match key.as_ref() {
+            "r" => &mut color.r,
+            "g" => &mut color.g,
+            "b" => &mut color.b,
+            "a" => &mut color.a,
+            "h" => {
+                h = Some(Adjustment::from_str(value)?);
+                continue;
+            }
+            "s" => {
+                s = Some(Adjustment::from_str(value)?);
+                continue;
+            }
+            "v" => {
+                v = Some(Adjustment::from_str(value)?);
+                continue;
+            }
+            "l" => {
+                l = Some(Adjustment::from_str(value)?);
+                continue;
+            }
+            "hex" => {
+                hex = Some(parse_hex(value).ok_or("invalid hex color")?);
+                continue;
+            }
+            _ => continue,
+        };
+        *channel = Adjustment::from_str(value)?.apply_channel(*channel)?;
+        updated = true;
+    }
+
+    if hex.is_some() && (h.is_some() || s.is_some() || v.is_some() || l.is_some()) {
+        return Err("hex can not be combined with hsv/ hsl");
+    }
+    if let Some((rgb, alpha)) = hex {
+        *color = rgb.alpha(alpha.unwrap_or(color.a));
+        return Ok(true);
+    }
+
+    let rgb = match (v, l) {
+        (Some(_), Some(_)) => return Err("v and l can not be combined"),
+        (None, Some(l)) => {
+            let (curr_h, curr_s, curr_l) = rgb_to_hsl(color.rgb());
+            hsl_to_rgb(
+                apply_optional(h, curr_h, Adjustment::apply_hue)?,
+                apply_optional(s, curr_s, Adjustment::apply_percent)?,
+                l.apply_percent(curr_l)?,
+            )
+        }
+        _ if h.is_none() && s.is_none() && v.is_none() => return Ok(updated),
+        _ => {
+            let (curr_h, curr_s, curr_v) = rgb_to_hsv(color.rgb());
+            hsv_to_rgb(
+                apply_optional(h, curr_h, Adjustment::apply_hue)?,
+                apply_optional(s, curr_s, Adjustment::apply_percent)?,
+                apply_optional(v, curr_v, Adjustment::apply_percent)?,
+            )
+        }
+    };
+    *color = rgb.alpha(color.a);
+    return Ok(true);
+}
+
+fn apply_optional(
+    adjustment: Option<Adjustment>,
+    current: f32,
+    apply: fn(&Adjustment, f32) -> Result<f32, &'static str>,
+) -> Result<f32, &'static str> {
+    return match adjustment {
+        Some(adjustment) => apply(&adjustment, current),
+        None => Ok(current),
+    };
+}
+
+/// Output channels of the connected stripe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Rgb,
+    /// r, g, b and one white channel
+    Rgbw,
+    /// r, g, b, warm white and cold white
+    Rgbww,
+}
+
+impl ChannelLayout {
+    pub fn from_channels(channels: usize) -> Option<ChannelLayout> {
+        return match channels {
+            3 => Some(ChannelLayout::Rgb),
+            4 => Some(ChannelLayout::Rgbw),
+            5 => Some(ChannelLayout::Rgbww),
+            _ => None,
+        };
+    }
+}
+
+/// Explicitly set white channel values, added to the white extracted from the color.
+/// `w` drives all white channels, `ww` and `cw` only the warm/ cold white channel of RGBWW
+/// stripes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhiteChannels {
+    pub w: u8,
+    pub ww: u8,
+    pub cw: u8,
+}
+
+impl WhiteChannels {
+    /// scales the white values with the brightness, like `update_channels` does for r,g,b
+    pub fn scaled(&self, brightness: u8) -> WhiteChannels {
+        let scale = |value: u8| (value as u16 * brightness as u16 + 127) / 255;
+        return WhiteChannels {
+            w: scale(self.w) as u8,
+            ww: scale(self.ww) as u8,
+            cw: scale(self.cw) as u8,
+        };
+    }
+}
+
+/// How the white channels are derived from the r,g,b color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhiteStrategy {
+    /// the white channels are only driven by explicitly set white values
+    Off,
+    /// the common part of r, g and b is moved to the white channel
+    Max,
+    /// like `Max`, but the white is extracted with the measured color of the white LEDs
+    Accurate,
+}
+
+impl FromStr for WhiteStrategy {
+    type Err = &'static str;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        return match strategy {
+            "off" => Ok(WhiteStrategy::Off),
+            "max" => Ok(WhiteStrategy::Max),
+            "accurate" => Ok(WhiteStrategy::Accurate),
+            _ => Err("unknown white strategy"),
+        };
+    }
+}
+
+impl WhiteStrategy {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            WhiteStrategy::Off => "off",
+            WhiteStrategy::Max => "max",
+            WhiteStrategy::Accurate => "accurate",
+        };
+    }
+}
+
+/// Converts r,g,b and explicit white values into the output channel values of the layout
+/// (r, g, b, w or r, g, b, warm white, cold white). `white_point` is the r,g,b color the white
+/// LEDs emit at full duty (for RGBWW both white channels together), used by `Accurate`.
+pub fn to_channels(
+    rgb: RGB8,
+    white: WhiteChannels,
+    layout: ChannelLayout,
+    strategy: WhiteStrategy,
+    white_point: RGB8,
+) -> [u8; 5] {
+    if layout == ChannelLayout::Rgb {
+        return [rgb.r, rgb.g, rgb.b, 0, 0];
+    }
+
+    let (extracted, rest) = match strategy {
+        WhiteStrategy::Off => (0, rgb),
+        WhiteStrategy::Max => {
+            let w = rgb.r.min(rgb.g).min(rgb.b);
+            (w, RGB8::new(rgb.r - w, rgb.g - w, rgb.b - w))
+        }
+        WhiteStrategy::Accurate => {
+            // the largest white duty, which does not exceed any of the channels
+            let duty = |value: u8, point: u8| -> u16 {
+                match point {
+                    0 => 255,
+                    _ => (value as u16 * 255 / point as u16).min(255),
+                }
+            };
+            let w = duty(rgb.r, white_point.r)
+                .min(duty(rgb.g, white_point.g))
+                .min(duty(rgb.b, white_point.b));
+            let remove = |value: u8, point: u8| -> u8 {
+                value.saturating_sub(((w * point as u16 + 127) / 255) as u8)
+            };
+            (
+                w as u8,
+                RGB8::new(
+                    remove(rgb.r, white_point.r),
+                    remove(rgb.g, white_point.g),
+                    remove(rgb.b, white_point.b),
+                ),
+            )
+        }
+    };
+
+    let w = extracted.saturating_add(white.w);
+    return match layout {
+        ChannelLayout::Rgbww => [
+            rest.r,
+            rest.g,
+            rest.b,
+            w.saturating_add(white.ww),
+            w.saturating_add(white.cw),
+        ],
+        _ => [rest.r, rest.g, rest.b, w, 0],
+    };
+}
+
+/// returns true for the parameter keys handled by `apply_white_params`
+pub fn is_white_param(key: &str) -> bool {
+    return matches!(key, "w" | "ww" | "cw");
+}
+
+/// Applies the `w`, `ww` and `cw` parameters (absolute or relative) to the white values,
+/// other keys are ignored. Returns false, if no white parameter was given.
+pub fn apply_white_params<K: AsRef<str>, V: AsRef<str>>(
+    white: &mut WhiteChannels,
+    params: &[(K, V)],
+) -> Result<bool, &'static str> {
+    let mut updated = false;
+    for (key, value) in params {
+        let channel = match key.as_ref() {
+            "w" => &mut white.w,
+            "ww" => &mut white.ww,
+            "cw" => &mut white.cw,
+            _ => continue,
+        };
+        *channel = Adjustment::from_str(value.as_ref())?.apply_channel(*channel)?;
+        updated = true;
+    }
+    return Ok(updated);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// primaries, secondaries, greys and pseudo random colors
+    fn sample_colors() -> Vec<RGB8> {
+        let mut colors = vec![
+            RGB8::new(255, 0, 0),
+            RGB8::new(0, 255, 0),
+            RGB8::new(0, 0, 255),
+            RGB8::new(255, 255, 0),
+            RGB8::new(0, 255, 255),
+            RGB8::new(255, 0, 255),
+            RGB8::new(0, 0, 0),
+            RGB8::new(1, 1, 1),
+            RGB8::new(128, 128, 128),
+            RGB8::new(255, 255, 255),
+        ];
+        let mut seed: u32 = 0x1234_5678;
+        for _ in 0..1000 {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let [r, g, b, _] = seed.to_be_bytes();
+            colors.push(RGB8::new(r, g, b));
+        }
+        return colors;
+    }
+
+    fn assert_close(expected: RGB8, actual: RGB8) {
+        let close = |a: u8, b: u8| a.abs_diff(b) <= 1;
+        assert!(
+            close(expected.r, actual.r)
+                && close(expected.g, actual.g)
+                && close(expected.b, actual.b),
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for color in sample_colors() {
+            let (h, s, v) = rgb_to_hsv(color);
+            assert_close(color, hsv_to_rgb(h, s, v));
+        }
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for color in sample_colors() {
+            let (h, s, l) = rgb_to_hsl(color);
+            assert_close(color, hsl_to_rgb(h, s, l));
+        }
+    }
+
+    #[test]
+    fn hsv_of_primaries() {
+        assert_eq!(rgb_to_hsv(RGB8::new(255, 0, 0)), (0.0, 100.0, 100.0));
+        assert_eq!(rgb_to_hsv(RGB8::new(0, 255, 0)), (120.0, 100.0, 100.0));
+        assert_eq!(rgb_to_hsv(RGB8::new(0, 0, 255)), (240.0, 100.0, 100.0));
+        assert_eq!(rgb_to_hsl(RGB8::new(128, 128, 128)).1, 0.0);
+    }
+
+    #[test]
+    fn parses_hex() {
+        let orange = RGB8::new(0xff, 0x80, 0x00);
+        assert_eq!(parse_hex("FF8000"), Some((orange, None)));
+        assert_eq!(parse_hex("#ff8000"), Some((orange, None)));
+        assert_eq!(parse_hex("FF800040"), Some((orange, Some(0x40))));
+        assert_eq!(parse_hex("#FF800040"), Some((orange, Some(0x40))));
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        for hex in ["", "#", "FFF", "FF800", "FF80001", "FF8000400", "##FF8000"] {
+            assert_eq!(parse_hex(hex), None, "{:?}", hex);
+        }
+        for hex in ["GG8000", "FF80ZZ", "FF8000XY", "+F8000", "FF 800", "FF80é0"] {
+            assert_eq!(parse_hex(hex), None, "{:?}", hex);
+        }
+    }
+
+    fn adjustment(value: &str) -> Adjustment {
+        return Adjustment::from_str(value).unwrap();
+    }
+
+    #[test]
+    fn relative_channel_steps_saturate() {
+        assert_eq!(adjustment("+20").apply_channel(100), Ok(120));
+        assert_eq!(adjustment("-20").apply_channel(100), Ok(80));
+        assert_eq!(adjustment("+20").apply_channel(250), Ok(255));
+        assert_eq!(adjustment("-20").apply_channel(5), Ok(0));
+        assert_eq!(adjustment("+1000").apply_channel(0), Ok(255));
+        // an unencoded `+` arrives as space
+        assert_eq!(adjustment(" 20").apply_channel(250), Ok(255));
+    }
+
+    #[test]
+    fn relative_percent_steps_saturate() {
+        assert_eq!(adjustment("+10").apply_percent(50.0), Ok(60.0));
+        assert_eq!(adjustment("+10").apply_percent(95.0), Ok(100.0));
+        assert_eq!(adjustment("-10").apply_percent(5.0), Ok(0.0));
+    }
+
+    #[test]
+    fn relative_hue_steps_wrap() {
+        assert_eq!(adjustment("+30").apply_hue(350.0), Ok(20.0));
+        assert_eq!(adjustment("-30").apply_hue(10.0), Ok(340.0));
+        assert_eq!(adjustment("+720").apply_hue(90.0), Ok(90.0));
+        assert_eq!(adjustment("-360").apply_hue(0.0), Ok(0.0));
+    }
+
+    #[test]
+    fn absolute_values_out_of_range_are_rejected() {
+        assert_eq!(adjustment("255").apply_channel(0), Ok(255));
+        assert!(adjustment("256").apply_channel(0).is_err());
+        assert!(adjustment("1.5").apply_channel(0).is_err());
+        assert_eq!(
+            Adjustment::Absolute(-1.0).apply_channel(0),
+            Err("channel value out of range")
+        );
+        assert_eq!(adjustment("100").apply_percent(0.0), Ok(100.0));
+        assert!(adjustment("101").apply_percent(0.0).is_err());
+        assert!(Adjustment::Absolute(-0.5).apply_percent(0.0).is_err());
+        assert_eq!(adjustment("360").apply_hue(0.0), Ok(360.0));
+        assert!(adjustment("361").apply_hue(0.0).is_err());
+        assert!(Adjustment::Absolute(-1.0).apply_hue(0.0).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_adjustments() {
+        for value in [
+            "", "+", "-", "++5", "--5", "+-5", "abc", "5a", "inf", "+NaN",
+        ] {
+            assert!(Adjustment::from_str(value).is_err(), "{:?}", value);
+        }
+    }
+}
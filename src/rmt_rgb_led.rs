@@ -14,12 +14,10 @@
 
 use ::core::ffi::c_void;
 use std::ptr::{null, null_mut};
-use std::thread::sleep;
-use std::time::Duration;
 
 use esp_idf_sys::EspError;
 use esp_idf_sys::{
-    esp, rmt_config, rmt_config_t, rmt_config_t__bindgen_ty_1, rmt_driver_install,
+    esp, rmt_channel_t, rmt_config, rmt_config_t, rmt_config_t__bindgen_ty_1, rmt_driver_install,
     rmt_get_counter_clock, rmt_item32_t, rmt_item32_t__bindgen_ty_1,
     rmt_item32_t__bindgen_ty_1__bindgen_ty_1, rmt_mode_t_RMT_MODE_TX, rmt_translator_init,
     rmt_tx_config_t, rmt_wait_tx_done, rmt_write_sample, u_int8_t,
@@ -111,6 +109,11 @@ pub struct WS2812RMT {
 }
 impl WS2812RMT {
     pub fn new(gpio_num: i32) -> Result<Self, EspError> {
+        return Self::with_channel(gpio_num, 0);
+    }
+
+    /// creates the driver on the given RMT TX channel, so more than one strip can be driven
+    pub fn with_channel(gpio_num: i32, channel: rmt_channel_t) -> Result<Self, EspError> {
         let rmt_tx_config = rmt_tx_config_t {
             carrier_freq_hz: 38000,
             carrier_level: 1,
@@ -124,7 +127,7 @@ impl WS2812RMT {
 
         let config = rmt_config_t {
             rmt_mode: rmt_mode_t_RMT_MODE_TX,
-            channel: 0,
+            channel,
             gpio_num,
             clk_div: 2,
             mem_block_num: 1,
@@ -172,31 +175,26 @@ impl WS2812RMT {
 
         Ok(())
     }
-}
-
-#[derive(Debug)]
-struct LedStatus;
-
-impl LedStatus {
-    const SUCCESS: RGB8 = RGB8::new(0, 10, 0);
-    const FAILURE: RGB8 = RGB8::new(10, 0, 0);
-    const OFF: RGB8 = RGB8::new(0, 0, 0);
-}
-
-const STATUS_LED_DURATION_SECONDS: u64 = 1;
 
-pub fn show_success(led: &mut WS2812RMT) {
-    led.set_pixel(LedStatus::SUCCESS)
-        .expect("WS2812 LED should be settable to green light");
-    sleep(Duration::from_secs(STATUS_LED_DURATION_SECONDS));
-    led.set_pixel(LedStatus::OFF)
-        .expect("WS2812 LED should be settable");
-}
+    /// sets every pixel of a strip with `count` pixels to the same color
+    pub fn set_pixels(&mut self, color: RGB8, count: usize) -> Result<(), EspError> {
+        // GRB, like set_pixel
+        let data: Vec<u8> = [color.g, color.r, color.b].repeat(count);
+        // 30us per pixel, plus some headroom
+        let timeout_ms = 10 + count as u32 * 30 / 1000;
+        unsafe {
+            esp!(rmt_write_sample(
+                self.config.channel,
+                data.as_ptr(),
+                data.len(),
+                true,
+            ))?;
+            esp!(rmt_wait_tx_done(
+                self.config.channel,
+                (timeout_ms * FREERTOS_HZ) / 1000,
+            ))?;
+        }
 
-pub fn show_failure(led: &mut WS2812RMT) {
-    led.set_pixel(LedStatus::FAILURE)
-        .expect("WS2812 LED should be settable to red light");
-    sleep(Duration::from_secs(STATUS_LED_DURATION_SECONDS));
-    led.set_pixel(LedStatus::OFF)
-        .expect("WS2812 LED should be settable");
+        Ok(())
+    }
 }
@@ -0,0 +1,97 @@
+//! Named color presets, persisted in NVS
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rgb_led::RGBA8;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "scenes";
+pub const MAX_SCENES: usize = 16;
+pub const MAX_NAME_LENGTH: usize = 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    pub color: RGBA8,
+}
+
+pub struct Scenes {
+    scenes: Vec<Scene>,
+    storage: Arc<Storage>,
+}
+
+impl Scenes {
+    /// loads all previously saved scenes from the storage
+    pub fn load(storage: Arc<Storage>) -> Scenes {
+        let scenes = storage.load(STORAGE_KEY).unwrap_or_default();
+        return Scenes { scenes, storage };
+    }
+
+    pub fn list(&self) -> &[Scene] {
+        return &self.scenes;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Scene> {
+        return self.scenes.iter().find(|scene| scene.name == name);
+    }
+
+    /// saves the color under the given name, an existing scene with the same name is overwritten
+    pub fn save(&mut self, name: &str, color: RGBA8) -> Result<(), &'static str> {
+        validate_name(name)?;
+        match self.scenes.iter_mut().find(|scene| scene.name == name) {
+            Some(scene) => scene.color = color,
+            None => {
+                if self.scenes.len() >= MAX_SCENES {
+                    return Err("maximum number of scenes reached");
+                }
+                self.scenes.push(Scene {
+                    name: name.to_string(),
+                    color,
+                });
+            }
+        }
+        return self.persist();
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), &'static str> {
+        validate_name(new_name)?;
+        if self.get(new_name).is_some() {
+            return Err("scene with new name already exists");
+        }
+        let scene = self
+            .scenes
+            .iter_mut()
+            .find(|scene| scene.name == name)
+            .ok_or("unknown scene")?;
+        scene.name = new_name.to_string();
+        return self.persist();
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), &'static str> {
+        let idx = self
+            .scenes
+            .iter()
+            .position(|scene| scene.name == name)
+            .ok_or("unknown scene")?;
+        self.scenes.remove(idx);
+        return self.persist();
+    }
+
+    fn persist(&self) -> Result<(), &'static str> {
+        return self.storage.store(STORAGE_KEY, &self.scenes);
+    }
+}
+
+/// scene names are used in CSV responses and in the UDP protocol, so they must not contain
+/// any of the separator characters
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err("invalid scene name length");
+    }
+    if name.contains(|c: char| c == ',' || c == '=' || c.is_control()) {
+        return Err("scene name contains invalid characters");
+    }
+    return Ok(());
+}
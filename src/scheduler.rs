@@ -0,0 +1,388 @@
+//! Triggers actions at cron-like times or relative to sunrise/ sunset
+//!
+//! A schedule consists of a trigger (`when`) and an action, both kept as strings so they can be
+//! listed like they were entered:
+//! - `when`: a cron expression `MINUTE HOUR DAY_OF_MONTH MONTH DAY_OF_WEEK` supporting `*`,
+//!   lists (`1,15`), ranges (`1-5`) and steps (`*/15`), or a sun event with an optional offset in
+//!   minutes and an optional day of week field, e.g. `sunset-15` or `sunrise+30 1-5`
+//! - `action`: `color:R,G,B,A`, `scene:NAME`, `on` or `off`, color and scene actions also turn the
+//!   stripe on
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, LocalTime};
+use crate::power::Power;
+use crate::rgb_led::RGBA8;
+use crate::scenes::Scenes;
+use crate::storage::Storage;
+use crate::sun::{self, SunEvent};
+use crate::transition::{self, Transition};
+
+const STORAGE_KEY: &str = "schedules";
+pub const MAX_SCHEDULES: usize = 16;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u8,
+    pub enabled: bool,
+    pub when: String,
+    pub action: String,
+    /// transition time in milliseconds
+    pub t: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CronSpec {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // cron matches day of month OR day of week if both are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    Cron(CronSpec),
+    Sun {
+        event: SunEvent,
+        offset_minutes: i16,
+        weekdays: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Color(RGBA8),
+    Scene(String),
+    On,
+    Off,
+}
+
+impl FromStr for Trigger {
+    type Err = &'static str;
+
+    fn from_str(when: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = when.split_whitespace().collect();
+
+        for (prefix, event) in [("sunrise", SunEvent::Sunrise), ("sunset", SunEvent::Sunset)] {
+            let offset = match fields.first().and_then(|f| f.strip_prefix(prefix)) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            let offset_minutes = match offset {
+                "" => 0,
+                _ => offset
+                    .trim_start_matches('+')
+                    .parse::<i16>()
+                    .map_err(|_| "invalid sun event offset")?,
+            };
+            if offset_minutes.abs() > 12 * 60 {
+                return Err("sun event offset out of range");
+            }
+            let weekdays = match fields.len() {
+                1 => parse_weekdays("*")?,
+                2 => parse_weekdays(fields[1])?,
+                _ => return Err("too many fields for sun event"),
+            };
+            return Ok(Trigger::Sun {
+                event,
+                offset_minutes,
+                weekdays,
+            });
+        }
+
+        if fields.len() != 5 {
+            return Err("cron expression needs 5 fields");
+        }
+        return Ok(Trigger::Cron(CronSpec {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays: parse_weekdays(fields[4])?,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        }));
+    }
+}
+
+/// parses a day of week field into a bitmask (bit 0 is Sunday), 0 and 7 are both Sunday
+pub fn parse_weekdays(field: &str) -> Result<u64, &'static str> {
+    let weekdays = parse_field(field, 0, 7)?;
+    // fold 7 (Sunday) onto 0
+    return Ok((weekdays | weekdays >> 7) & 0x7f);
+}
+
+/// parses a single cron field into a bitmask with bit n set if value n matches
+fn parse_field(field: &str, min: u8, max: u8) -> Result<u64, &'static str> {
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u8>().map_err(|_| "invalid step")?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err("invalid step");
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u8>().map_err(|_| "invalid range")?,
+                    end.parse::<u8>().map_err(|_| "invalid range")?,
+                ),
+                None => {
+                    let value = range.parse::<u8>().map_err(|_| "invalid value")?;
+                    // a single value with step means "from value to max"
+                    if item.contains('/') {
+                        (value, max)
+                    } else {
+                        (value, value)
+                    }
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err("value out of range");
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1u64 << value;
+        }
+    }
+    return Ok(mask);
+}
+
+impl Trigger {
+    fn matches(&self, now: &LocalTime, latitude: f32, longitude: f32) -> bool {
+        match self {
+            Trigger::Cron(spec) => {
+                let day_matches = bit_set(spec.days, now.day);
+                let weekday_matches = bit_set(spec.weekdays, now.weekday);
+                let day_or_weekday = match (spec.days_restricted, spec.weekdays_restricted) {
+                    (true, true) => day_matches || weekday_matches,
+                    _ => day_matches && weekday_matches,
+                };
+                return bit_set(spec.minutes, now.minute)
+                    && bit_set(spec.hours, now.hour)
+                    && bit_set(spec.months, now.month)
+                    && day_or_weekday;
+            }
+            Trigger::Sun {
+                event,
+                offset_minutes,
+                weekdays,
+            } => {
+                if !bit_set(*weekdays, now.weekday) {
+                    return false;
+                }
+                let utc_minutes = match sun::event_utc_minutes(
+                    *event, now.year, now.month, now.day, latitude, longitude,
+                ) {
+                    Some(val) => val.round() as i32,
+                    None => return false,
+                };
+                let local_minutes = (utc_minutes + now.utc_offset_minutes + *offset_minutes as i32)
+                    .rem_euclid(24 * 60);
+                return local_minutes == now.minute_of_day() as i32;
+            }
+        }
+    }
+}
+
+fn bit_set(mask: u64, value: u8) -> bool {
+    return mask & (1u64 << value) != 0;
+}
+
+impl FromStr for Action {
+    type Err = &'static str;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        if action == "on" {
+            return Ok(Action::On);
+        }
+        if action == "off" {
+            return Ok(Action::Off);
+        }
+        if let Some(name) = action.strip_prefix("scene:") {
+            return Ok(Action::Scene(name.to_string()));
+        }
+        if let Some(color) = action.strip_prefix("color:") {
+            let channels = color
+                .split(',')
+                .map(|channel| channel.trim().parse::<u8>())
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| "invalid color value")?;
+            return match channels[..] {
+                [r, g, b, a] => Ok(Action::Color(RGBA8::new(r, g, b, a))),
+                _ => Err("color needs 4 values"),
+            };
+        }
+        return Err("unknown action");
+    }
+}
+
+impl Action {
+    pub fn execute(
+        &self,
+        duration: Duration,
+        rgba: &RwLock<RGBA8>,
+        transition: &Mutex<Option<Transition>>,
+        scenes: &Mutex<Scenes>,
+        power: &Mutex<Power>,
+    ) -> Result<(), &'static str> {
+        // without a transition time, switching uses the configured power fade
+        let power_fade = if duration.is_zero() {
+            None
+        } else {
+            Some(duration)
+        };
+        let target = match self {
+            Action::Color(color) => *color,
+            Action::Scene(name) => {
+                let scenes = scenes.lock().map_err(|_| "could not get scenes lock")?;
+                scenes.get(name).ok_or("unknown scene")?.color
+            }
+            Action::On | Action::Off => {
+                let mut power = power.lock().map_err(|_| "could not get power lock")?;
+                power.set(*self == Action::On, power_fade);
+                return Ok(());
+            }
+        };
+        transition::start(rgba, transition, target, duration)?;
+        let mut power = power.lock().map_err(|_| "could not get power lock")?;
+        power.set(true, power_fade);
+        return Ok(());
+    }
+}
+
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+    storage: Arc<Storage>,
+}
+
+impl Scheduler {
+    /// loads all previously saved schedules from the storage
+    pub fn load(storage: Arc<Storage>) -> Scheduler {
+        let schedules = storage.load(STORAGE_KEY).unwrap_or_default();
+        return Scheduler { schedules, storage };
+    }
+
+    pub fn list(&self) -> &[Schedule] {
+        return &self.schedules;
+    }
+
+    /// validates and adds a new schedule, returns its id
+    pub fn add(&mut self, when: &str, action: &str, t: u32) -> Result<u8, &'static str> {
+        Trigger::from_str(when)?;
+        Action::from_str(action)?;
+        if self.schedules.len() >= MAX_SCHEDULES {
+            return Err("maximum number of schedules reached");
+        }
+        let id = (0..=u8::MAX)
+            .find(|id| self.schedules.iter().all(|schedule| schedule.id != *id))
+            .ok_or("no free schedule id")?;
+        self.schedules.push(Schedule {
+            id,
+            enabled: true,
+            when: when.trim().to_string(),
+            action: action.to_string(),
+            t,
+        });
+        self.persist()?;
+        return Ok(id);
+    }
+
+    pub fn delete(&mut self, id: u8) -> Result<(), &'static str> {
+        let idx = self
+            .schedules
+            .iter()
+            .position(|schedule| schedule.id == id)
+            .ok_or("unknown schedule")?;
+        self.schedules.remove(idx);
+        return self.persist();
+    }
+
+    pub fn set_enabled(&mut self, id: u8, enabled: bool) -> Result<(), &'static str> {
+        let schedule = self
+            .schedules
+            .iter_mut()
+            .find(|schedule| schedule.id == id)
+            .ok_or("unknown schedule")?;
+        schedule.enabled = enabled;
+        return self.persist();
+    }
+
+    /// returns the actions of all enabled schedules due at the given minute
+    fn due(&self, now: &LocalTime, latitude: f32, longitude: f32) -> Vec<(Action, Duration)> {
+        return self
+            .schedules
+            .iter()
+            .filter(|schedule| schedule.enabled)
+            .filter_map(|schedule| {
+                let trigger = Trigger::from_str(&schedule.when).ok()?;
+                if !trigger.matches(now, latitude, longitude) {
+                    return None;
+                }
+                let action = Action::from_str(&schedule.action).ok()?;
+                Some((action, Duration::from_millis(schedule.t as u64)))
+            })
+            .collect();
+    }
+
+    fn persist(&self) -> Result<(), &'static str> {
+        return self.storage.store(STORAGE_KEY, &self.schedules);
+    }
+}
+
+/// Spawns the scheduler thread, which checks once per minute for due schedules
+pub fn spawn(
+    scheduler: Arc<Mutex<Scheduler>>,
+    clock: Arc<Clock>,
+    rgba: Arc<RwLock<RGBA8>>,
+    transition: Arc<Mutex<Option<Transition>>>,
+    scenes: Arc<Mutex<Scenes>>,
+    power: Arc<Mutex<Power>>,
+) {
+    thread::spawn(move || {
+        let mut last_checked_minute = None;
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+
+            let now = match clock.now() {
+                Some(val) => val,
+                None => continue,
+            };
+            let minute = (now.day, now.minute_of_day());
+            if last_checked_minute == Some(minute) {
+                continue;
+            }
+            last_checked_minute = Some(minute);
+
+            let (latitude, longitude) = match clock.config() {
+                Some(config) => (config.latitude, config.longitude),
+                None => continue,
+            };
+            let due = match scheduler.lock() {
+                Ok(scheduler) => scheduler.due(&now, latitude, longitude),
+                Err(_) => continue,
+            };
+            for (action, duration) in due {
+                info!("Executing scheduled action {:?}", action);
+                if let Err(e) = action.execute(duration, &rgba, &transition, &scenes, &power) {
+                    warn!("Could not execute scheduled action {:?}: {}", action, e);
+                }
+            }
+        }
+    });
+}
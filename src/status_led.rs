@@ -0,0 +1,218 @@
+//! Non-blocking status LED
+//!
+//! A background thread drives the WS2812 status LED. Parts of the firmware raise and clear
+//! status codes when system events happen (e.g. the wifi connection is lost), the raised code
+//! with the highest priority is shown with its pattern. Without a raised code the LED is off.
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::rgb_led::RGB8;
+use crate::rmt_rgb_led::WS2812RMT;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(20);
+const BLINK_ON: Duration = Duration::from_millis(150);
+const BLINK_OFF: Duration = Duration::from_millis(150);
+/// pause after the blinks of a blink pattern, so the blinks can be counted
+const BLINK_PAUSE: Duration = Duration::from_millis(1000);
+
+const OFF: RGB8 = RGB8::new(0, 0, 0);
+const GREEN: RGB8 = RGB8::new(0, 20, 0);
+const YELLOW: RGB8 = RGB8::new(20, 12, 0);
+const BLUE: RGB8 = RGB8::new(0, 0, 50);
+const RED: RGB8 = RGB8::new(30, 0, 0);
+const ORANGE: RGB8 = RGB8::new(30, 6, 0);
+const WHITE: RGB8 = RGB8::new(40, 40, 40);
+const MAGENTA: RGB8 = RGB8::new(40, 0, 40);
+const CYAN: RGB8 = RGB8::new(0, 30, 30);
+const OVERHEATED: [(RGB8, Duration); 2] = [
+    (RED, Duration::from_millis(300)),
+    (ORANGE, Duration::from_millis(300)),
+];
+const IDENTIFY: [(RGB8, Duration); 4] = [
+    (WHITE, Duration::from_millis(150)),
+    (OFF, Duration::from_millis(100)),
+    (MAGENTA, Duration::from_millis(150)),
+    (OFF, Duration::from_millis(100)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Off,
+    Solid(RGB8),
+    /// `count` short blinks followed by a pause, repeated
+    Blink {
+        color: RGB8,
+        count: u8,
+    },
+    /// fades in and out once per `period`
+    Breathe {
+        color: RGB8,
+        period: Duration,
+    },
+    /// shows every color for its duration, repeated
+    Sequence(&'static [(RGB8, Duration)]),
+}
+
+impl Pattern {
+    /// the color shown `elapsed` after the start of the pattern
+    pub fn color_at(&self, elapsed: Duration) -> RGB8 {
+        return match *self {
+            Pattern::Off => OFF,
+            Pattern::Solid(color) => color,
+            Pattern::Blink { color, count } => {
+                let blink = BLINK_ON + BLINK_OFF;
+                let cycle = blink * count.into() + BLINK_PAUSE;
+                let offset = elapsed.as_millis() % cycle.as_millis();
+                if offset < blink.as_millis() * u128::from(count)
+                    && offset % blink.as_millis() < BLINK_ON.as_millis()
+                {
+                    color
+                } else {
+                    OFF
+                }
+            }
+            Pattern::Breathe { color, period } => {
+                let phase =
+                    (elapsed.as_millis() % period.as_millis()) as f32 / period.as_millis() as f32;
+                let scale = (1.0 - (phase * 2.0 * PI).cos()) / 2.0;
+                let dim = |value: u8| (value as f32 * scale).round() as u8;
+                RGB8::new(dim(color.r), dim(color.g), dim(color.b))
+            }
+            Pattern::Sequence(steps) => {
+                let cycle: u128 = steps.iter().map(|(_, time)| time.as_millis()).sum();
+                if cycle == 0 {
+                    return OFF;
+                }
+                let mut offset = elapsed.as_millis() % cycle;
+                for (color, time) in steps {
+                    if offset < time.as_millis() {
+                        return *color;
+                    }
+                    offset -= time.as_millis();
+                }
+                OFF
+            }
+        };
+    }
+}
+
+/// Status codes in ascending priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatusCode {
+    /// the wifi connection was established, shown for a moment
+    Connected,
+    Connecting,
+    /// the setup access point is open
+    Provisioning,
+    /// the wifi driver failed or no connection after all attempts
+    WifiError,
+    /// a firmware update is written, the firmware has no OTA update yet, so nothing raises it
+    #[allow(dead_code)]
+    Ota,
+    /// the thermal derating cut off the output
+    Overheated,
+    /// the controller is asked to show where it is
+    Identify,
+}
+
+impl StatusCode {
+    pub fn pattern(&self) -> Pattern {
+        return match self {
+            StatusCode::Connected => Pattern::Solid(GREEN),
+            StatusCode::Connecting => Pattern::Blink {
+                color: YELLOW,
+                count: 1,
+            },
+            StatusCode::Provisioning => Pattern::Breathe {
+                color: BLUE,
+                period: Duration::from_secs(2),
+            },
+            StatusCode::WifiError => Pattern::Blink {
+                color: RED,
+                count: 3,
+            },
+            StatusCode::Ota => Pattern::Breathe {
+                color: CYAN,
+                period: Duration::from_millis(500),
+            },
+            StatusCode::Overheated => Pattern::Sequence(&OVERHEATED),
+            StatusCode::Identify => Pattern::Sequence(&IDENTIFY),
+        };
+    }
+}
+
+#[derive(Default)]
+struct Codes {
+    raised: Vec<StatusCode>,
+    // codes shown until the instant
+    timed: Vec<(StatusCode, Instant)>,
+}
+
+/// Raised status codes, shared with the thread driving the LED
+#[derive(Default)]
+pub struct StatusLed {
+    codes: Mutex<Codes>,
+}
+
+impl StatusLed {
+    /// raises or clears the code, clearing also ends a code shown for a given time
+    pub fn set(&self, code: StatusCode, raised: bool) {
+        if let Ok(mut codes) = self.codes.lock() {
+            codes.raised.retain(|val| *val != code);
+            codes.timed.retain(|(val, _)| *val != code);
+            if raised {
+                codes.raised.push(code);
+            }
+        }
+    }
+
+    /// raises the code for the given time
+    pub fn show_for(&self, code: StatusCode, duration: Duration) {
+        if let Ok(mut codes) = self.codes.lock() {
+            codes.timed.retain(|(val, _)| *val != code);
+            codes.timed.push((code, Instant::now() + duration));
+        }
+    }
+
+    /// the raised code with the highest priority
+    pub fn current(&self) -> Option<StatusCode> {
+        let mut codes = self.codes.lock().ok()?;
+        let now = Instant::now();
+        codes.timed.retain(|(_, until)| *until > now);
+        let timed = codes.timed.iter().map(|(code, _)| code);
+        return codes.raised.iter().chain(timed).max().copied();
+    }
+}
+
+/// Shows the current status code on the `led` in a background thread
+pub fn spawn(mut led: WS2812RMT) -> Arc<StatusLed> {
+    let status_led = Arc::new(StatusLed::default());
+    let status = status_led.clone();
+    thread::spawn(move || {
+        let mut code = None;
+        let mut start = Instant::now();
+        let mut shown = None;
+        loop {
+            let current = status.current();
+            if current != code {
+                code = current;
+                start = Instant::now();
+            }
+            let pattern = code.map_or(Pattern::Off, |code| code.pattern());
+            let color = pattern.color_at(start.elapsed());
+            if shown != Some(color) {
+                match led.set_pixel(color) {
+                    Ok(_) => shown = Some(color),
+                    Err(e) => warn!("Could not set the status LED! Error: {:?}", e),
+                }
+            }
+            thread::sleep(UPDATE_INTERVAL);
+        }
+    });
+    return status_led;
+}
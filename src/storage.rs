@@ -0,0 +1,59 @@
+//! Persists serializable values as JSON blobs in the NVS flash partition
+//!
+//! NVS keys are limited to 15 characters, values to `MAX_VALUE_SIZE` bytes.
+
+use std::sync::Mutex;
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+
+const NAMESPACE: &str = "ledstripe";
+const MAX_VALUE_SIZE: usize = 4096;
+
+pub struct Storage {
+    nvs: Mutex<EspNvs<NvsDefault>>,
+}
+
+impl Storage {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Storage, EspError> {
+        return Ok(Storage {
+            nvs: Mutex::new(EspNvs::new(partition, NAMESPACE, true)?),
+        });
+    }
+
+    /// returns None, if there is no stored value for the key or it could not be deserialized
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let nvs = self.nvs.lock().ok()?;
+        let mut buf = vec![0u8; MAX_VALUE_SIZE];
+        let data = match nvs.get_raw(key, &mut buf) {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("Could not read {} from NVS! Error: {:?}", key, e);
+                return None;
+            }
+        };
+        return match serde_json::from_slice(data) {
+            Ok(val) => Some(val),
+            Err(e) => {
+                warn!("Could not deserialize {} from NVS! Error: {}", key, e);
+                None
+            }
+        };
+    }
+
+    pub fn store<T: Serialize>(&self, key: &str, value: &T) -> Result<(), &'static str> {
+        let data = serde_json::to_vec(value).map_err(|_| "could not serialize value")?;
+        if data.len() > MAX_VALUE_SIZE {
+            return Err("value too large for storage");
+        }
+        let mut nvs = self.nvs.lock().map_err(|_| "could not get storage lock")?;
+        nvs.set_raw(key, &data).map_err(|e| {
+            warn!("Could not write {} to NVS! Error: {:?}", key, e);
+            "could not write to storage"
+        })?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,81 @@
+//! Sunrise and sunset calculation
+//!
+//! Implements the sunrise equation of the "Almanac for Computers" (US Naval Observatory), which
+//! is accurate to about a minute for latitudes below the polar circles.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+/// official zenith for sunrise/ sunset including refraction, in degrees
+const ZENITH: f64 = 90.833;
+
+/// returns the minutes after midnight UTC of the sun event at the given date and location,
+/// None if the sun does not rise or set at this day (polar day/ night)
+pub fn event_utc_minutes(
+    event: SunEvent,
+    year: i32,
+    month: u8,
+    day: u8,
+    latitude: f32,
+    longitude: f32,
+) -> Option<f64> {
+    let day_of_year = day_of_year(year, month, day) as f64;
+    let lng_hour = longitude as f64 / 15.0;
+
+    let t = match event {
+        SunEvent::Sunrise => day_of_year + (6.0 - lng_hour) / 24.0,
+        SunEvent::Sunset => day_of_year + (18.0 - lng_hour) / 24.0,
+    };
+
+    // sun's mean anomaly and true longitude
+    let m = 0.9856 * t - 3.289;
+    let l = (m + 1.916 * sin_deg(m) + 0.020 * sin_deg(2.0 * m) + 282.634).rem_euclid(360.0);
+
+    // sun's right ascension, in the same quadrant as the true longitude, in hours
+    let mut ra = atan_deg(0.91764 * tan_deg(l)).rem_euclid(360.0);
+    ra += (l / 90.0).floor() * 90.0 - (ra / 90.0).floor() * 90.0;
+    ra /= 15.0;
+
+    // sun's declination and local hour angle
+    let sin_dec = 0.39782 * sin_deg(l);
+    let cos_dec = sin_dec.asin().cos();
+    let lat = latitude as f64;
+    let cos_h = (cos_deg(ZENITH) - sin_dec * sin_deg(lat)) / (cos_dec * cos_deg(lat));
+    if !(-1.0..=1.0).contains(&cos_h) {
+        return None;
+    }
+    let h = match event {
+        SunEvent::Sunrise => 360.0 - cos_h.acos().to_degrees(),
+        SunEvent::Sunset => cos_h.acos().to_degrees(),
+    } / 15.0;
+
+    let local_mean_time = h + ra - 0.06571 * t - 6.622;
+    let utc_hours = (local_mean_time - lng_hour).rem_euclid(24.0);
+    return Some(utc_hours * 60.0);
+}
+
+fn day_of_year(year: i32, month: u8, day: u8) -> u16 {
+    const DAYS_BEFORE_MONTH: [u16; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let is_leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let leap_day = if is_leap_year && month > 2 { 1 } else { 0 };
+    return DAYS_BEFORE_MONTH[(month.clamp(1, 12) - 1) as usize] + day as u16 + leap_day;
+}
+
+fn sin_deg(deg: f64) -> f64 {
+    return deg.to_radians().sin();
+}
+
+fn cos_deg(deg: f64) -> f64 {
+    return deg.to_radians().cos();
+}
+
+fn tan_deg(deg: f64) -> f64 {
+    return deg.to_radians().tan();
+}
+
+fn atan_deg(x: f64) -> f64 {
+    return x.atan().to_degrees();
+}
@@ -0,0 +1,208 @@
+//! Temperature readings of the on-chip sensor and an optional NTC thermistor
+//!
+//! A background thread reads the sensors every `READ_INTERVAL` and feeds the hotter reading
+//! into the derating policy, the render loop limits the output to the resulting level.
+//! The NTC is expected between the ADC pin and ground, with a series resistor to 3.3 V.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_sys::{
+    adc1_config_channel_atten, adc1_config_width, adc1_get_raw, adc_atten_t_ADC_ATTEN_DB_11,
+    adc_bits_width_t_ADC_WIDTH_BIT_12, adc_unit_t_ADC_UNIT_1, esp, esp_adc_cal_characteristics_t,
+    esp_adc_cal_characterize, esp_adc_cal_raw_to_voltage, temp_sensor_config_t,
+    temp_sensor_dac_offset_t_TSENS_DAC_L2, temp_sensor_read_celsius, temp_sensor_set_config,
+    temp_sensor_start, EspError,
+};
+use log::{error, info, warn};
+
+use crate::derating::{Derating, DeratingConfig, DeratingStatus};
+use crate::metrics::Metrics;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "thermal";
+const READ_INTERVAL: Duration = Duration::from_secs(2);
+const SUPPLY_MV: f32 = 3300.0;
+// default reference voltage used when the chip has no eFuse calibration
+const DEFAULT_VREF_MV: u32 = 1100;
+
+pub struct NtcConfig {
+    /// ADC1 channel, equal to the GPIO number on the ESP32-C3 (0 - 4)
+    pub adc_channel: u8,
+    pub series_ohm: f32,
+    /// resistance at 25 °C
+    pub nominal_ohm: f32,
+    pub beta: f32,
+}
+
+impl NtcConfig {
+    /// temperature in °C for the measured voltage, None for an open or shorted NTC
+    fn temperature_c(&self, millivolts: u32) -> Option<f32> {
+        let millivolts = millivolts as f32;
+        if millivolts <= 0.0 || millivolts >= SUPPLY_MV {
+            return None;
+        }
+        let resistance = self.series_ohm * millivolts / (SUPPLY_MV - millivolts);
+        let inverse_kelvin = 1.0 / 298.15 + (resistance / self.nominal_ohm).ln() / self.beta;
+        return Some(1.0 / inverse_kelvin - 273.15);
+    }
+}
+
+pub struct Thermal {
+    derating: Derating,
+    chip_c: Option<f32>,
+    ntc_c: Option<f32>,
+    storage: Arc<Storage>,
+}
+
+impl Thermal {
+    /// loads the previously saved derating config from the storage
+    pub fn load(storage: Arc<Storage>) -> Thermal {
+        let config: DeratingConfig = storage.load(STORAGE_KEY).unwrap_or_default();
+        let config = match config.validate() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!(
+                    "Stored derating config is invalid, using defaults! Error: {}",
+                    e
+                );
+                DeratingConfig::default()
+            }
+        };
+        return Thermal {
+            derating: Derating::new(config),
+            chip_c: None,
+            ntc_c: None,
+            storage,
+        };
+    }
+
+    pub fn config(&self) -> &DeratingConfig {
+        return self.derating.config();
+    }
+
+    pub fn set_config(&mut self, config: DeratingConfig) -> Result<(), &'static str> {
+        config.validate()?;
+        self.storage.store(STORAGE_KEY, &config)?;
+        return self.derating.set_config(config);
+    }
+
+    pub fn chip_c(&self) -> Option<f32> {
+        return self.chip_c;
+    }
+
+    pub fn ntc_c(&self) -> Option<f32> {
+        return self.ntc_c;
+    }
+
+    pub fn status(&self) -> DeratingStatus {
+        return self.derating.status();
+    }
+
+    /// maximum output level from 0.0 (cut off) to 1.0 (full output)
+    pub fn level(&self) -> f32 {
+        return self.derating.level();
+    }
+
+    fn update(&mut self, chip_c: Option<f32>, ntc_c: Option<f32>) {
+        self.chip_c = chip_c;
+        self.ntc_c = ntc_c;
+        // the hotter sensor decides, without readings the last level is kept
+        let hottest = match (chip_c, ntc_c) {
+            (Some(chip), Some(ntc)) => chip.max(ntc),
+            (Some(val), None) | (None, Some(val)) => val,
+            (None, None) => return,
+        };
+        let status = self.derating.status();
+        self.derating.update(hottest);
+        if self.derating.status() != status {
+            warn!(
+                "Thermal status changed to {} at {:.1} °C, output level {:.2}",
+                self.derating.status().name(),
+                hottest,
+                self.derating.level()
+            );
+        }
+    }
+}
+
+fn start_chip_sensor() -> Result<(), EspError> {
+    let config = temp_sensor_config_t {
+        dac_offset: temp_sensor_dac_offset_t_TSENS_DAC_L2,
+        clk_div: 6,
+    };
+    esp!(unsafe { temp_sensor_set_config(config) })?;
+    return esp!(unsafe { temp_sensor_start() });
+}
+
+fn read_chip_sensor() -> Option<f32> {
+    let mut celsius = 0.0;
+    esp!(unsafe { temp_sensor_read_celsius(&mut celsius) }).ok()?;
+    return Some(celsius);
+}
+
+fn start_ntc(ntc: &NtcConfig) -> Result<esp_adc_cal_characteristics_t, EspError> {
+    esp!(unsafe { adc1_config_width(adc_bits_width_t_ADC_WIDTH_BIT_12) })?;
+    esp!(unsafe {
+        adc1_config_channel_atten(ntc.adc_channel.into(), adc_atten_t_ADC_ATTEN_DB_11)
+    })?;
+    let mut characteristics = esp_adc_cal_characteristics_t::default();
+    unsafe {
+        esp_adc_cal_characterize(
+            adc_unit_t_ADC_UNIT_1,
+            adc_atten_t_ADC_ATTEN_DB_11,
+            adc_bits_width_t_ADC_WIDTH_BIT_12,
+            DEFAULT_VREF_MV,
+            &mut characteristics,
+        )
+    };
+    return Ok(characteristics);
+}
+
+fn read_ntc(ntc: &NtcConfig, characteristics: &esp_adc_cal_characteristics_t) -> Option<f32> {
+    let raw = unsafe { adc1_get_raw(ntc.adc_channel.into()) };
+    if raw < 0 {
+        return None;
+    }
+    let millivolts = unsafe { esp_adc_cal_raw_to_voltage(raw as u32, characteristics) };
+    return ntc.temperature_c(millivolts);
+}
+
+/// Starts the sensors and periodically updates the derating level in a background thread
+pub fn spawn(thermal: Arc<Mutex<Thermal>>, ntc: Option<NtcConfig>, metrics: Arc<Metrics>) {
+    let chip_sensor = match start_chip_sensor() {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Could not start the temperature sensor! Error: {:?}", e);
+            false
+        }
+    };
+    let ntc = ntc.and_then(|ntc| match start_ntc(&ntc) {
+        Ok(characteristics) => Some((ntc, characteristics)),
+        Err(e) => {
+            error!("Could not start the NTC adc channel! Error: {:?}", e);
+            None
+        }
+    });
+    if !chip_sensor && ntc.is_none() {
+        return;
+    }
+    info!("Thermal derating active");
+
+    thread::spawn(move || loop {
+        let chip_c = if chip_sensor {
+            read_chip_sensor()
+        } else {
+            None
+        };
+        let ntc_c = ntc
+            .as_ref()
+            .and_then(|(ntc, characteristics)| read_ntc(ntc, characteristics));
+        if let Ok(mut thermal) = thermal.lock() {
+            thermal.update(chip_c, ntc_c);
+            metrics.record_thermal(chip_c, ntc_c, thermal.level());
+        }
+        thread::sleep(READ_INTERVAL);
+    });
+}
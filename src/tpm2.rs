@@ -0,0 +1,51 @@
+//! TPM2.net realtime receiver
+//!
+//! Ambilight and visualiser software (e.g. Hyperion, Jinx) streams frames as TPM2.net data
+//! packets, every zone is one pixel, see `realtime`. A frame can be split into several packets
+//! of the same size, the packet number gives the position of the pixels in the frame.
+
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::realtime::{self, Realtime};
+use crate::rgb_led::RGB8;
+
+pub const TPM2_NET_PORT: u16 = 65506;
+const START_BYTE: u8 = 0x9c;
+const END_BYTE: u8 = 0x36;
+const DATA_FRAME: u8 = 0xda;
+/// start byte, packet type, frame size (2 bytes), packet number and packet count
+const HEADER_LEN: usize = 6;
+
+/// parses a data packet into the first pixel and the colors, None for other packets
+fn parse_packet(packet: &[u8]) -> Option<(usize, Vec<RGB8>)> {
+    if packet.len() < HEADER_LEN + 1 || packet[0] != START_BYTE || packet[1] != DATA_FRAME {
+        return None;
+    }
+    let size = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+    let data = packet.get(HEADER_LEN..HEADER_LEN + size)?;
+    if packet.get(HEADER_LEN + size) != Some(&END_BYTE) {
+        return None;
+    }
+    // packets are numbered from 1, some senders start with 0
+    let start = usize::from(packet[4].saturating_sub(1)) * (size / 3);
+    let colors = data
+        .chunks_exact(3)
+        .map(|chunk| RGB8::new(chunk[0], chunk[1], chunk[2]))
+        .collect();
+    return Some((start, colors));
+}
+
+/// Receives TPM2.net packets in a background thread
+pub fn spawn(realtime: Arc<Realtime>) {
+    realtime::listen("TPM2.net", TPM2_NET_PORT, move |packet| {
+        let (start, colors) = match parse_packet(packet) {
+            Some(val) => val,
+            None => return,
+        };
+        if let Err(e) = realtime.show("tpm2", start, &colors, Some(realtime.timeout())) {
+            warn!("Could not show TPM2.net frame: {}", e);
+        }
+    });
+}
@@ -0,0 +1,106 @@
+//! Smooth transitions between two colors, interpolated by the render loop
+
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::rgb_led::RGBA8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    from: RGBA8,
+    to: RGBA8,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Transition {
+    pub fn new(from: RGBA8, to: RGBA8, duration: Duration) -> Transition {
+        return Transition {
+            from,
+            to,
+            start: Instant::now(),
+            duration,
+        };
+    }
+
+    pub fn target(&self) -> RGBA8 {
+        return self.to;
+    }
+
+    /// point in time at which the transition reaches its target color
+    pub fn end(&self) -> Instant {
+        return self.start + self.duration;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.start.elapsed() >= self.duration;
+    }
+
+    /// returns the linearly interpolated color for the current point in time
+    pub fn current(&self) -> RGBA8 {
+        return self.at(Instant::now());
+    }
+
+    /// returns the linearly interpolated color for the given point in time
+    pub fn at(&self, instant: Instant) -> RGBA8 {
+        let elapsed = instant.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let lerp = |from: u8, to: u8| -> u8 {
+            (from as f32 + (to as f32 - from as f32) * progress).round() as u8
+        };
+        return RGBA8::new(
+            lerp(self.from.r, self.to.r),
+            lerp(self.from.g, self.to.g),
+            lerp(self.from.b, self.to.b),
+            lerp(self.from.a, self.to.a),
+        );
+    }
+}
+
+/// Sets `rgba` to `target` and lets the render loop fade from the current color to it.
+/// A zero duration sets the color immediately.
+pub fn start(
+    rgba: &RwLock<RGBA8>,
+    transition: &Mutex<Option<Transition>>,
+    target: RGBA8,
+    duration: Duration,
+) -> Result<(), &'static str> {
+    let mut rgba = rgba.write().map_err(|_| "could not get write lock")?;
+    let mut transition = transition
+        .lock()
+        .map_err(|_| "could not get transition lock")?;
+
+    // start from the currently shown color, if another transition is still running
+    let from = match *transition {
+        Some(running) if running.target() == *rgba => running.current(),
+        _ => *rgba,
+    };
+    *transition = if duration.is_zero() {
+        None
+    } else {
+        Some(Transition::new(from, target, duration))
+    };
+    *rgba = target;
+    return Ok(());
+}
+
+/// returns the color to show for the set color `rgba`, taking a running transition into account.
+/// Finished transitions and transitions to another color than `rgba` are cleared.
+pub fn current_color(rgba: RGBA8, transition: &Mutex<Option<Transition>>) -> RGBA8 {
+    let mut transition = match transition.lock() {
+        Ok(val) => val,
+        Err(_) => return rgba,
+    };
+    return match *transition {
+        Some(running) if running.target() == rgba && !running.is_finished() => running.current(),
+        Some(_) => {
+            // the color was changed in the meantime or the transition is over
+            *transition = None;
+            rgba
+        }
+        None => rgba,
+    };
+}
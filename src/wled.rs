@@ -0,0 +1,457 @@
+//! Emulation of the WLED JSON API and the WLED UDP realtime protocols
+//!
+//! Apps and home automation integrations speaking WLED can control the controller without an
+//! own integration. Every zone is a WLED segment with one color, the master `on` and `bri`
+//! apply to all zones. There are no effects or palettes, only `Solid` and `Default` are listed.
+//! Realtime packets stream one pixel per zone, see `realtime`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use esp_idf_sys::{
+    esp, esp_get_free_heap_size, esp_mac_type_t_ESP_MAC_WIFI_STA, esp_read_mac, esp_timer_get_time,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics;
+use crate::realtime::{self, Realtime};
+use crate::rgb_led::{parse_hex, RGB8, RGBA8};
+use crate::transition;
+use crate::zones::{self, Zone};
+
+pub const WLED_UDP_PORT: u16 = 21324;
+/// WLED version reported to the clients, the JSON API of this version is emulated
+const WLED_VERSION: &str = "0.14.0";
+const WLED_VERSION_ID: u32 = 2310130;
+/// WLED transition times are given in 100 ms steps
+const TRANSITION_STEP: Duration = Duration::from_millis(100);
+/// realtime timeout byte, which streams until stopped
+const NO_TIMEOUT: u8 = 255;
+
+#[derive(Debug, Serialize)]
+pub struct Segment {
+    id: u8,
+    n: String,
+    start: u16,
+    stop: u16,
+    len: u16,
+    on: bool,
+    bri: u8,
+    /// primary, secondary and tertiary color, only the primary one is used
+    col: [Vec<u8>; 3],
+    fx: u8,
+    sx: u8,
+    ix: u8,
+    pal: u8,
+    sel: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct State {
+    on: bool,
+    bri: u8,
+    transition: u16,
+    ps: i16,
+    pl: i16,
+    live: bool,
+    mainseg: u8,
+    seg: Vec<Segment>,
+}
+
+/// `true`, `false` or `"t"` for toggling
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OnValue {
+    Set(bool),
+    Toggle(String),
+}
+
+/// a color as `[r, g, b]`, `[r, g, b, w]` or hex string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ColorValue {
+    Channels(Vec<u8>),
+    Hex(String),
+}
+
+impl ColorValue {
+    fn rgb(&self) -> Result<RGB8, &'static str> {
+        return match self {
+            ColorValue::Channels(channels) if channels.len() >= 3 => {
+                Ok(RGB8::new(channels[0], channels[1], channels[2]))
+            }
+            ColorValue::Channels(_) => Err("color needs r, g and b"),
+            ColorValue::Hex(hex) => parse_hex(hex)
+                .map(|(rgb, _)| rgb)
+                .ok_or("invalid hex color"),
+        };
+    }
+
+    fn white(&self) -> Option<u8> {
+        return match self {
+            ColorValue::Channels(channels) => channels.get(3).copied(),
+            ColorValue::Hex(_) => None,
+        };
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SegmentUpdate {
+    id: Option<u8>,
+    on: Option<OnValue>,
+    bri: Option<u8>,
+    col: Option<Vec<ColorValue>>,
+}
+
+/// WLED accepts a single segment object or a list of segments
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SegmentUpdates {
+    One(SegmentUpdate),
+    Many(Vec<SegmentUpdate>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StateUpdate {
+    on: Option<OnValue>,
+    bri: Option<u8>,
+    transition: Option<u16>,
+    /// transition of this request only
+    tt: Option<u16>,
+    seg: Option<SegmentUpdates>,
+    live: Option<bool>,
+    /// true, if the client wants the new state in the response
+    #[serde(default)]
+    pub v: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Leds {
+    count: usize,
+    pwr: u32,
+    fps: u8,
+    maxpwr: u32,
+    maxseg: usize,
+    rgbw: bool,
+    wv: u8,
+    cct: u8,
+}
+
+#[derive(Debug, Serialize)]
+struct WifiInfo {
+    bssid: String,
+    rssi: i8,
+    signal: u8,
+    channel: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    ver: &'static str,
+    vid: u32,
+    leds: Leds,
+    str: bool,
+    name: String,
+    udpport: u16,
+    live: bool,
+    lm: String,
+    fxcount: u8,
+    palcount: u8,
+    wifi: WifiInfo,
+    arch: &'static str,
+    core: &'static str,
+    freeheap: u32,
+    uptime: u64,
+    brand: &'static str,
+    product: &'static str,
+    mac: String,
+}
+
+/// the whole `/json` response
+#[derive(Debug, Serialize)]
+pub struct Everything {
+    pub state: State,
+    pub info: Info,
+    pub effects: [&'static str; 1],
+    pub palettes: [&'static str; 1],
+}
+
+pub const EFFECTS: [&str; 1] = ["Solid"];
+pub const PALETTES: [&str; 1] = ["Default"];
+
+fn on_value(value: &OnValue, current: bool) -> Result<bool, &'static str> {
+    return match value {
+        OnValue::Set(on) => Ok(*on),
+        OnValue::Toggle(toggle) if toggle == "t" => Ok(!current),
+        OnValue::Toggle(_) => Err("invalid on value"),
+    };
+}
+
+/// The WLED view of the zones
+pub struct Wled {
+    zones: Arc<Vec<Arc<Zone>>>,
+    realtime: Arc<Realtime>,
+    hostname: &'static str,
+    /// true for stripes with white channels
+    rgbw: bool,
+}
+
+impl Wled {
+    pub fn new(
+        zones: Arc<Vec<Arc<Zone>>>,
+        realtime: Arc<Realtime>,
+        hostname: &'static str,
+        rgbw: bool,
+    ) -> Wled {
+        return Wled {
+            zones,
+            realtime,
+            hostname,
+            rgbw,
+        };
+    }
+
+    pub fn state(&self) -> Result<State, &'static str> {
+        let mut seg = Vec::new();
+        for zone in self.zones.iter() {
+            let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+            let white = zone.white.read().map_err(|_| "could not get read lock")?.w;
+            let on = zone
+                .power
+                .lock()
+                .map_err(|_| "could not get power lock")?
+                .is_on();
+            let mut primary = vec![color.r, color.g, color.b];
+            if self.rgbw {
+                primary.push(white);
+            }
+            seg.push(Segment {
+                id: zone.id,
+                n: zone.name.clone(),
+                start: zone.id.into(),
+                stop: u16::from(zone.id) + 1,
+                len: 1,
+                on,
+                bri: color.a,
+                col: [primary, vec![0, 0, 0], vec![0, 0, 0]],
+                fx: 0,
+                sx: 128,
+                ix: 128,
+                pal: 0,
+                sel: true,
+            });
+        }
+        let all: Vec<&Arc<Zone>> = self.zones.iter().collect();
+        return Ok(State {
+            on: zones::any_on(&all)?,
+            // the main stripe stands for the master brightness
+            bri: seg.first().map_or(0, |seg| seg.bri),
+            transition: 0,
+            ps: -1,
+            pl: -1,
+            live: self.realtime.source().is_some(),
+            mainseg: 0,
+            seg,
+        });
+    }
+
+    pub fn info(&self) -> Info {
+        let mut mac = [0_u8; 6];
+        if let Err(e) =
+            esp!(unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_WIFI_STA) })
+        {
+            warn!("Could not read the mac address! Error: {:?}", e);
+        }
+        let rssi = metrics::wifi_rssi().unwrap_or(0);
+        return Info {
+            ver: WLED_VERSION,
+            vid: WLED_VERSION_ID,
+            leds: Leds {
+                count: self.zones.len(),
+                pwr: 0,
+                fps: 40,
+                maxpwr: 0,
+                maxseg: self.zones.len(),
+                rgbw: self.rgbw,
+                wv: u8::from(self.rgbw),
+                cct: 0,
+            },
+            str: false,
+            name: self.hostname.to_string(),
+            udpport: WLED_UDP_PORT,
+            live: self.realtime.source().is_some(),
+            lm: self.realtime.source().unwrap_or("").to_string(),
+            fxcount: EFFECTS.len() as u8,
+            palcount: PALETTES.len() as u8,
+            wifi: WifiInfo {
+                bssid: String::new(),
+                rssi,
+                // WLED maps -100 ... -50 dBm to 0 ... 100 %
+                signal: ((i16::from(rssi) + 100) * 2).clamp(0, 100) as u8,
+                channel: 0,
+            },
+            arch: "esp32",
+            core: "v4.4",
+            freeheap: unsafe { esp_get_free_heap_size() },
+            uptime: (unsafe { esp_timer_get_time() } / 1_000_000) as u64,
+            brand: "WLED",
+            product: env!("CARGO_PKG_NAME"),
+            mac: mac.iter().map(|val| format!("{:02x}", val)).collect(),
+        };
+    }
+
+    /// applies a state update, segment ids are zone ids
+    pub fn update(&self, update: &StateUpdate) -> Result<(), &'static str> {
+        let duration = update
+            .tt
+            .or(update.transition)
+            .map(|steps| TRANSITION_STEP * steps.into());
+        if update.live == Some(false) {
+            self.realtime.stop()?;
+        }
+
+        let all: Vec<&Arc<Zone>> = self.zones.iter().collect();
+        if let Some(bri) = update.bri {
+            // WLED turns off at brightness 0, the brightness is kept for turning on again
+            if bri == 0 {
+                for zone in &all {
+                    zone.power
+                        .lock()
+                        .map_err(|_| "could not get power lock")?
+                        .set(false, duration);
+                }
+            } else {
+                for zone in &all {
+                    set_brightness(zone, bri, duration)?;
+                }
+            }
+        }
+        if let Some(on) = &update.on {
+            let on = on_value(on, zones::any_on(&all)?)?;
+            for zone in &all {
+                zone.power
+                    .lock()
+                    .map_err(|_| "could not get power lock")?
+                    .set(on, duration);
+            }
+        }
+
+        let segments = match &update.seg {
+            Some(SegmentUpdates::One(segment)) => std::slice::from_ref(segment),
+            Some(SegmentUpdates::Many(segments)) => segments.as_slice(),
+            None => &[],
+        };
+        for (idx, segment) in segments.iter().enumerate() {
+            let id = segment.id.unwrap_or(idx as u8);
+            let zone = self
+                .zones
+                .iter()
+                .find(|zone| zone.id == id)
+                .ok_or("unknown segment")?;
+            self.update_segment(zone, segment, duration)?;
+        }
+        return Ok(());
+    }
+
+    fn update_segment(
+        &self,
+        zone: &Zone,
+        segment: &SegmentUpdate,
+        duration: Option<Duration>,
+    ) -> Result<(), &'static str> {
+        if let Some(primary) = segment.col.as_ref().and_then(|col| col.first()) {
+            let rgb = primary.rgb()?;
+            if let Some(w) = primary.white() {
+                zone.white
+                    .write()
+                    .map_err(|_| "could not get write lock")?
+                    .w = w;
+            }
+            let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+            transition::start(
+                &zone.rgba,
+                &zone.transition,
+                RGBA8::new(rgb.r, rgb.g, rgb.b, color.a),
+                duration.unwrap_or(Duration::ZERO),
+            )?;
+        }
+        if let Some(bri) = segment.bri {
+            set_brightness(zone, bri, duration)?;
+        }
+        if let Some(on) = &segment.on {
+            let mut power = zone.power.lock().map_err(|_| "could not get power lock")?;
+            let on = on_value(on, power.is_on())?;
+            power.set(on, duration);
+        }
+        return Ok(());
+    }
+}
+
+fn set_brightness(zone: &Zone, bri: u8, duration: Option<Duration>) -> Result<(), &'static str> {
+    let color = *zone.rgba.read().map_err(|_| "could not get read lock")?;
+    return transition::start(
+        &zone.rgba,
+        &zone.transition,
+        RGBA8 { a: bri, ..color },
+        duration.unwrap_or(Duration::ZERO),
+    );
+}
+
+/// A frame of a WLED realtime packet
+#[derive(Debug, PartialEq)]
+struct RealtimeFrame {
+    /// None streams until stopped
+    timeout: Option<Duration>,
+    pixels: Vec<(usize, RGB8)>,
+}
+
+/// parses WARLS (1), DRGB (2), DRGBW (3) and DNRGB (4) packets, None for other packets
+fn parse_realtime(packet: &[u8]) -> Option<RealtimeFrame> {
+    let (protocol, timeout, data) = match packet {
+        [protocol, timeout, data @ ..] => (*protocol, *timeout, data),
+        _ => return None,
+    };
+    let timeout = match timeout {
+        NO_TIMEOUT => None,
+        seconds => Some(Duration::from_secs(seconds.into())),
+    };
+    let rgb = |chunk: &[u8]| RGB8::new(chunk[0], chunk[1], chunk[2]);
+    let pixels = match protocol {
+        // index, r, g, b
+        1 => data
+            .chunks_exact(4)
+            .map(|chunk| (usize::from(chunk[0]), rgb(&chunk[1..])))
+            .collect(),
+        2 => data.chunks_exact(3).map(rgb).enumerate().collect(),
+        // the white channel is not streamed
+        3 => data.chunks_exact(4).map(rgb).enumerate().collect(),
+        // start index (high byte first), then r, g, b
+        4 if data.len() >= 2 => {
+            let start = usize::from(u16::from_be_bytes([data[0], data[1]]));
+            data[2..]
+                .chunks_exact(3)
+                .enumerate()
+                .map(|(idx, chunk)| (start + idx, rgb(chunk)))
+                .collect()
+        }
+        _ => return None,
+    };
+    return Some(RealtimeFrame { timeout, pixels });
+}
+
+/// Receives WLED realtime packets in a background thread
+pub fn spawn_realtime(realtime: Arc<Realtime>) {
+    realtime::listen("WLED realtime", WLED_UDP_PORT, move |packet| {
+        // sync notifications of other WLED devices (protocol 0) are ignored
+        let frame = match parse_realtime(packet) {
+            Some(val) => val,
+            None => return,
+        };
+        for (pixel, color) in frame.pixels {
+            if let Err(e) = realtime.show("wled", pixel, &[color], frame.timeout) {
+                warn!("Could not show WLED realtime frame: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,290 @@
+//! Independent zones on one controller
+//!
+//! Every zone has its own color, white values, transition and power state, the first zone (id 0)
+//! is the one addressed by the zone-less API (`/setRGBA`, `/on`, UDP messages without zone, ...).
+//! A zone is driven by PWM channels or by an addressable WS2812 strip showing one color.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use esp_idf_sys::EspError;
+use log::debug;
+
+use crate::calibration::Calibration;
+use crate::power::Power;
+use crate::power_budget::PowerBudget;
+use crate::pwm_led::PwmLed;
+use crate::rgb_led::{ChannelLayout, RGBABrightnessExt, WhiteChannels, RGB8, RGBA8};
+use crate::rmt_rgb_led::WS2812RMT;
+use crate::storage::Storage;
+use crate::transition::{self, Transition};
+
+/// path segment addressing all zones at once
+pub const GROUP_TARGET: &str = "all";
+/// shorter fades are not worth handing over to the hardware
+const MIN_HARDWARE_FADE: Duration = Duration::from_millis(100);
+/// the hardware can't cancel a fade, longer fades are handed over in segments, so changes of
+/// the output level (thermal derating) and overrides reach the outputs within one segment
+const MAX_HARDWARE_SEGMENT: Duration = Duration::from_secs(1);
+
+pub struct Zone {
+    pub id: u8,
+    pub name: String,
+    pub rgba: Arc<RwLock<RGBA8>>,
+    pub white: Arc<RwLock<WhiteChannels>>,
+    pub transition: Arc<Mutex<Option<Transition>>>,
+    pub power: Arc<Mutex<Power>>,
+    pub budget: Arc<Mutex<PowerBudget>>,
+}
+
+impl Zone {
+    pub fn new(id: u8, name: &str, storage: Arc<Storage>) -> Zone {
+        return Zone {
+            id,
+            name: name.to_string(),
+            rgba: Arc::new(RwLock::new(RGBA8::new(0, 0, 0, 255))),
+            white: Arc::new(RwLock::new(WhiteChannels::default())),
+            transition: Arc::new(Mutex::new(None)),
+            power: Arc::new(Mutex::new(Power::load(storage.clone(), id))),
+            budget: Arc::new(Mutex::new(PowerBudget::load(storage, id))),
+        };
+    }
+}
+
+/// Resolves a zone target (zone id, zone name or `all`) into the addressed zones
+pub fn resolve<'a>(zones: &'a [Arc<Zone>], target: &str) -> Option<Vec<&'a Arc<Zone>>> {
+    if target == GROUP_TARGET {
+        return Some(zones.iter().collect());
+    }
+    let zone = zones
+        .iter()
+        .find(|zone| zone.id.to_string() == target || zone.name == target)?;
+    return Some(vec![zone]);
+}
+
+/// true, if any of the zones is powered on
+pub fn any_on(zones: &[&Arc<Zone>]) -> Result<bool, &'static str> {
+    for zone in zones {
+        if zone
+            .power
+            .lock()
+            .map_err(|_| "could not get power lock")?
+            .is_on()
+        {
+            return Ok(true);
+        }
+    }
+    return Ok(false);
+}
+
+/// switches the zone with the default fade duration
+pub fn set_power(zone: &Zone, on: bool) -> Result<(), &'static str> {
+    zone.power
+        .lock()
+        .map_err(|_| "could not get power lock")?
+        .set(on, None);
+    return Ok(());
+}
+
+/// switches all zones off if any of them is on, otherwise on, so the zones stay in sync
+pub fn toggle_power(zones: &[&Arc<Zone>]) -> Result<(), &'static str> {
+    let on = !any_on(zones)?;
+    for zone in zones {
+        set_power(zone, on)?;
+    }
+    return Ok(());
+}
+
+/// Hardware driving a zone
+pub enum Output<'a> {
+    Pwm(PwmLed<'a>),
+    Ws2812 { strip: WS2812RMT, pixels: usize },
+}
+
+impl<'a> Output<'a> {
+    pub fn layout(&self) -> ChannelLayout {
+        return match self {
+            Output::Pwm(pwm_led) => pwm_led.layout(),
+            Output::Ws2812 { .. } => ChannelLayout::Rgb,
+        };
+    }
+
+    fn set_channels(&mut self, channels: &[u8; 5]) -> Result<(), EspError> {
+        return match self {
+            Output::Pwm(pwm_led) => pwm_led.set_channels(channels),
+            Output::Ws2812 { strip, pixels } => {
+                strip.set_pixels(RGB8::new(channels[0], channels[1], channels[2]), *pixels)
+            }
+        };
+    }
+
+    fn supports_fade(&self) -> bool {
+        return match self {
+            Output::Pwm(pwm_led) => pwm_led.supports_fade(),
+            Output::Ws2812 { .. } => false,
+        };
+    }
+
+    fn is_fading(&self) -> bool {
+        return match self {
+            Output::Pwm(pwm_led) => pwm_led.is_fading(),
+            Output::Ws2812 { .. } => false,
+        };
+    }
+
+    fn fade_to(&mut self, channels: &[u8; 5], duration: Duration) -> Result<(), EspError> {
+        return match self {
+            Output::Pwm(pwm_led) => pwm_led.fade_to(channels, duration),
+            Output::Ws2812 { .. } => self.set_channels(channels),
+        };
+    }
+}
+
+/// A zone together with its output, rendered by the render loop
+///
+/// Outputs with hardware fade take over simple fades (a color transition or a power fade, but
+/// not both at once) in segments of up to `MAX_HARDWARE_SEGMENT`, until the end of a segment
+/// is reported the zone is not rendered in software.
+pub struct ZoneRenderer<'a> {
+    zone: Arc<Zone>,
+    output: Output<'a>,
+    last_channels: Option<[u8; 5]>,
+    // target channels of the running hardware fade segment
+    fade_target: Option<[u8; 5]>,
+}
+
+impl<'a> ZoneRenderer<'a> {
+    pub fn new(zone: Arc<Zone>, output: Output<'a>) -> ZoneRenderer<'a> {
+        return ZoneRenderer {
+            zone,
+            output,
+            last_channels: None,
+            fade_target: None,
+        };
+    }
+
+    pub fn zone(&self) -> &Zone {
+        return &self.zone;
+    }
+
+    /// the white values of the zone, scaled with the brightness of the color
+    fn white(&self, color: RGBA8) -> Result<WhiteChannels, &'static str> {
+        return Ok(self
+            .zone
+            .white
+            .read()
+            .map_err(|_| "could not get read lock")?
+            .scaled(color.a));
+    }
+
+    /// output channels for a color and white values, limited to the maximum output level and
+    /// the power budget
+    fn channels(
+        &self,
+        color: RGBA8,
+        white: WhiteChannels,
+        calibration: &Mutex<Calibration>,
+        max_level: f32,
+    ) -> Result<[u8; 5], &'static str> {
+        let mut rgb = RGB8::new(0, 0, 0);
+        color.update_channels(&mut rgb);
+        let mut channels = calibration
+            .lock()
+            .map_err(|_| "could not get calibration lock")?
+            .to_channels(rgb, white, self.output.layout());
+        if max_level < 1.0 {
+            for channel in channels.iter_mut() {
+                *channel = (*channel as f32 * max_level) as u8;
+            }
+        }
+        self.zone
+            .budget
+            .lock()
+            .map_err(|_| "could not get budget lock")?
+            .limit(&mut channels);
+        return Ok(channels);
+    }
+
+    /// returns the color at the end and the end of the next segment of the running fade, if it
+    /// is a simple fade the hardware can take over
+    fn hardware_fade(&self, rgba: RGBA8, power: &Power) -> Option<(RGBA8, Instant)> {
+        let transition = match *self.zone.transition.lock().ok()? {
+            Some(running) if running.target() == rgba && !running.is_finished() => Some(running),
+            _ => None,
+        };
+        let end = match (transition.map(|running| running.end()), power.fade_end()) {
+            (Some(end), None) | (None, Some(end)) => end,
+            // nothing to fade or a combined curve, which is interpolated in software
+            _ => return None,
+        };
+        let now = Instant::now();
+        if end.saturating_duration_since(now) < MIN_HARDWARE_FADE {
+            return None;
+        }
+        let segment_end = end.min(now + MAX_HARDWARE_SEGMENT);
+        let color = transition.map_or(rgba, |running| running.at(segment_end));
+        return Some((power.apply_at(color, segment_end), segment_end));
+    }
+
+    /// writes the current output color of the zone, if it changed since the last frame,
+    /// `max_level` (0.0 - 1.0) scales down the whole output, an `override_color` (identify,
+    /// realtime streaming) is shown instead of the zone state. While the output is derated or
+    /// overridden, fades are interpolated in software.
+    pub fn render(
+        &mut self,
+        calibration: &Mutex<Calibration>,
+        max_level: f32,
+        override_color: Option<RGBA8>,
+    ) -> Result<(), &'static str> {
+        if self.output.is_fading() {
+            return Ok(());
+        }
+        if let Some(channels) = self.fade_target.take() {
+            debug!("Hardware fade of zone {} done", self.zone.id);
+            self.last_channels = Some(channels);
+        }
+        if let Some(color) = override_color {
+            let channels =
+                self.channels(color, WhiteChannels::default(), calibration, max_level)?;
+            return self.show(channels);
+        }
+
+        let rgba = *self
+            .zone
+            .rgba
+            .read()
+            .map_err(|_| "could not get read lock")?;
+        let power = self
+            .zone
+            .power
+            .lock()
+            .map_err(|_| "could not get power lock")?;
+        if self.output.supports_fade() && max_level >= 1.0 {
+            if let Some((target, end)) = self.hardware_fade(rgba, &power) {
+                drop(power);
+                let channels =
+                    self.channels(target, self.white(target)?, calibration, max_level)?;
+                self.output
+                    .fade_to(&channels, end.saturating_duration_since(Instant::now()))
+                    .map_err(|_| "could not start hardware fade")?;
+                self.fade_target = Some(channels);
+                return Ok(());
+            }
+        }
+        let color = power.apply(transition::current_color(rgba, &self.zone.transition));
+        drop(power);
+        let channels = self.channels(color, self.white(color)?, calibration, max_level)?;
+        return self.show(channels);
+    }
+
+    /// writes the channels, if they changed since the last frame
+    fn show(&mut self, channels: [u8; 5]) -> Result<(), &'static str> {
+        if self.last_channels != Some(channels) {
+            self.output
+                .set_channels(&channels)
+                .map_err(|_| "could not set output channels")?;
+            self.last_channels = Some(channels);
+        }
+        return Ok(());
+    }
+}